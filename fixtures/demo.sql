@@ -0,0 +1,24 @@
+-- Demo data for a development database: two locations with receivers, trackers and a display each,
+-- one agency with an order paying for a video per interest. Loaded by the seed command.
+-- The statements run on every database backend, orders is filled without naming its user column, a reserved word in PostgreSQL.
+insert into location (name) values('Entrance');
+insert into location (name) values('Food court');
+insert into interest (name) values('sport');
+insert into interest (name) values('movies');
+insert into rfid_receiver (id, location) values('receiver1', 1);
+insert into rfid_receiver (id, location) values('receiver2', 2);
+insert into display (location) values(1);
+insert into display (location) values(2);
+insert into rfid_tracker (id) values('tracker1');
+insert into rfid_tracker (id) values('tracker2');
+insert into tracker_interest (tracker, interest, weight) values('tracker1', 1, 80);
+insert into tracker_interest (tracker, interest, weight) values('tracker1', 2, 20);
+insert into tracker_interest (tracker, interest, weight) values('tracker2', 2, 100);
+insert into advertisement_video (url, length_sec, interest) values('https://example.com/videos/sport.mp4', 30, 1);
+insert into advertisement_video (url, length_sec, interest) values('https://example.com/videos/movies.mp4', 15, 2);
+insert into agency (orgnr, name) values('556000-0000', 'Demo agency');
+insert into users (username, email, pass_hash, agency) values('demo', 'demo@example.com', 'NOT A HASH', '556000-0000');
+insert into orders values('demo-order', 1000, 'demo@example.com');
+insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, 'demo-order', 0, 2147483647);
+insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(2, 'demo-order', 0, 2147483647);
+insert into rate_card (location, credits_per_second) values(NULL, 0.125);
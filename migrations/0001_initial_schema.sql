@@ -0,0 +1,73 @@
+-- The schema before versioned migrations, tables that already exist are left as they are
+create table if not exists `location` (`name` varchar(128) NOT NULL ,
+`id` integer NOT NULL UNIQUE AUTO_INCREMENT ,
+ PRIMARY KEY( `id`));
+create table if not exists `rfid_tracker` (`id` varchar(64) NOT NULL UNIQUE ,
+`location` integer ,
+FOREIGN KEY (`location`)
+REFERENCES location(`id`),
+ PRIMARY KEY( `id`));
+create table if not exists `rfid_receiver` (`id` varchar(64) NOT NULL UNIQUE ,
+`location` integer NOT NULL ,
+FOREIGN KEY (`location`)
+REFERENCES location(`id`),
+ PRIMARY KEY( `id`));
+create table if not exists `interest` (`name` varchar(128) NOT NULL UNIQUE ,
+`id` integer NOT NULL UNIQUE AUTO_INCREMENT ,
+ PRIMARY KEY( `id`));
+create table if not exists `tracker_interest` (`interest` integer NOT NULL ,
+`tracker` varchar(64) NOT NULL ,
+`weight` float NOT NULL ,
+FOREIGN KEY (`interest`)
+REFERENCES interest(`id`),
+FOREIGN KEY (`tracker`)
+REFERENCES rfid_tracker(`id`),
+ PRIMARY KEY( `interest`, `tracker`));
+create table if not exists `display` (`id` integer NOT NULL UNIQUE AUTO_INCREMENT ,
+`location` integer NOT NULL ,
+FOREIGN KEY (`location`)
+REFERENCES location(`id`),
+ PRIMARY KEY( `id`, `location`));
+create table if not exists `advertisement_video` (`id` integer NOT NULL UNIQUE AUTO_INCREMENT ,
+`interest` integer NOT NULL ,
+`length_sec` integer NOT NULL ,
+`url` varchar(255) NOT NULL ,
+FOREIGN KEY (`interest`)
+REFERENCES interest(`id`),
+ PRIMARY KEY( `id`));
+create table if not exists `agency` (`orgnr` varchar(128) NOT NULL UNIQUE ,
+`name` varchar(128) NOT NULL UNIQUE ,
+ PRIMARY KEY( `orgnr`));
+create table if not exists `users` (`username` varchar(128) NOT NULL UNIQUE ,
+`email` varchar(128) NOT NULL UNIQUE ,
+`agency` varchar(128) NOT NULL ,
+`pass_hash` varchar(128) NOT NULL ,
+FOREIGN KEY (`agency`)
+REFERENCES agency(`orgnr`),
+ PRIMARY KEY( `username`));
+create table if not exists `orders` (`id` varchar(40) NOT NULL UNIQUE ,
+`credits` integer NOT NULL ,
+`user` varchar(128) NOT NULL ,
+FOREIGN KEY (`user`)
+REFERENCES users(`email`),
+ PRIMARY KEY( `id`));
+create table if not exists `played_video` (`id` integer NOT NULL UNIQUE AUTO_INCREMENT ,
+`video` integer NOT NULL ,
+`time_epoch` integer NOT NULL ,
+`order` varchar(40) NOT NULL ,
+FOREIGN KEY (`video`)
+REFERENCES advertisement_video(`id`),
+FOREIGN KEY (`order`)
+REFERENCES orders(`id`),
+ PRIMARY KEY( `id`));
+create table if not exists `advertisement_order` (`video` integer NOT NULL ,
+`orders` varchar(40) NOT NULL ,
+`start_time_epoch` integer NOT NULL ,
+`end_time_epoch` integer NOT NULL ,
+FOREIGN KEY (`video`)
+REFERENCES advertisement_video(`id`),
+FOREIGN KEY (`orders`)
+REFERENCES orders(`id`),
+ PRIMARY KEY( `video`, `orders`));
+create table if not exists refresh_token (`refresh_token` BLOB, INDEX(`refresh_token`(10)), id varchar(40) NOT NULL ,
+  PRIMARY KEY(id));
@@ -0,0 +1,9 @@
+create table `device_log` (`id` integer NOT NULL UNIQUE AUTO_INCREMENT ,
+`device` varchar(64) ,
+`severity` varchar(16) NOT NULL ,
+`firmware` varchar(64) ,
+`time_epoch` integer NOT NULL ,
+`message` text NOT NULL ,
+`fields` text ,
+INDEX(`device`, `time_epoch`),
+ PRIMARY KEY( `id`));
@@ -0,0 +1,8 @@
+create table `device_heartbeat` (`device_type` varchar(16) NOT NULL ,
+`device` varchar(64) NOT NULL ,
+`time_epoch` integer NOT NULL ,
+`uptime_sec` integer ,
+`firmware` varchar(64) ,
+`free_memory` integer ,
+`last_error` text ,
+ PRIMARY KEY( `device_type`, `device`));
@@ -0,0 +1,17 @@
+alter table `played_video` add column `display` integer ,
+add column `length_sec` integer ,
+add column `complete` boolean ,
+add column `credits` integer NOT NULL DEFAULT 0 ;
+create table `rate_card` (`id` integer NOT NULL UNIQUE AUTO_INCREMENT ,
+`location` integer ,
+`start_minute` integer NOT NULL DEFAULT 0 ,
+`end_minute` integer NOT NULL DEFAULT 1440 ,
+`credits_per_second` double NOT NULL ,
+`min_credits` integer NOT NULL DEFAULT 1 ,
+`rounding` varchar(8) NOT NULL DEFAULT 'down' ,
+FOREIGN KEY (`location`)
+REFERENCES location(`id`),
+ PRIMARY KEY( `id`));
+create table `redeemed_play_token` (`signature` varchar(64) NOT NULL ,
+`time_epoch` integer NOT NULL ,
+ PRIMARY KEY( `signature`));
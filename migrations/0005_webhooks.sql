@@ -0,0 +1,31 @@
+create table `agency_webhook` (`id` integer NOT NULL UNIQUE AUTO_INCREMENT ,
+`agency` varchar(128) NOT NULL ,
+`url` varchar(255) NOT NULL ,
+`secret` varchar(128) ,
+`events` varchar(255) ,
+`low_credit_threshold` integer NOT NULL DEFAULT 0 ,
+`active` boolean NOT NULL DEFAULT true ,
+FOREIGN KEY (`agency`)
+REFERENCES agency(`orgnr`),
+ PRIMARY KEY( `id`));
+create table `webhook_delivery` (`id` integer NOT NULL UNIQUE AUTO_INCREMENT ,
+`webhook` integer NOT NULL ,
+`event` varchar(32) NOT NULL ,
+`order` varchar(40) NOT NULL ,
+`payload` text NOT NULL ,
+`status` varchar(16) NOT NULL DEFAULT 'pending' ,
+`attempts` integer NOT NULL DEFAULT 0 ,
+`created_epoch` integer NOT NULL ,
+`next_attempt_epoch` integer NOT NULL ,
+`last_attempt_epoch` integer ,
+`response_code` integer ,
+`last_error` text ,
+FOREIGN KEY (`webhook`)
+REFERENCES agency_webhook(`id`),
+INDEX(`status`, `next_attempt_epoch`),
+ PRIMARY KEY( `id`));
+create table `campaign_event` (`video` integer NOT NULL ,
+`orders` varchar(40) NOT NULL ,
+`event` varchar(32) NOT NULL ,
+`time_epoch` integer NOT NULL ,
+ PRIMARY KEY( `video`, `orders`, `event`));
@@ -0,0 +1,19 @@
+create table `credit_ledger` (`id` integer NOT NULL UNIQUE AUTO_INCREMENT ,
+`order` varchar(40) NOT NULL ,
+`time_epoch` integer NOT NULL ,
+`entry_type` varchar(16) NOT NULL ,
+`amount` integer NOT NULL ,
+`balance_after` integer NOT NULL ,
+`played_video` integer ,
+`rate_card` integer ,
+`credits_per_second` double ,
+`description` varchar(255) ,
+FOREIGN KEY (`order`)
+REFERENCES orders(`id`),
+FOREIGN KEY (`played_video`)
+REFERENCES played_video(`id`),
+INDEX(`order`, `id`),
+ PRIMARY KEY( `id`));
+alter table `played_video` add column `voided` boolean NOT NULL DEFAULT false ,
+add column `voided_epoch` integer ,
+add column `void_reason` varchar(255) ;
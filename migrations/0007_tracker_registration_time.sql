@@ -0,0 +1,2 @@
+-- When a tracker was last registered at a location, trackers registered before this migration have none
+alter table `rfid_tracker` add column `registered_epoch` integer ;
@@ -0,0 +1,9 @@
+-- The number of trackers at a location from time_epoch on, recorded every time it changes
+create table `occupancy_history` (`id` integer NOT NULL UNIQUE AUTO_INCREMENT ,
+`location` integer NOT NULL ,
+`time_epoch` integer NOT NULL ,
+`trackers` integer NOT NULL ,
+FOREIGN KEY (`location`)
+REFERENCES location(`id`),
+INDEX(`location`, `time_epoch`),
+ PRIMARY KEY( `id`));
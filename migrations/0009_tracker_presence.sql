@@ -0,0 +1,9 @@
+-- The receivers that see a tracker, a tracker is gone from its location when no receiver there sees it anymore
+create table `tracker_presence` (`tracker` varchar(64) NOT NULL ,
+`receiver` varchar(64) NOT NULL ,
+`time_epoch` integer NOT NULL ,
+FOREIGN KEY (`tracker`)
+REFERENCES rfid_tracker(`id`),
+FOREIGN KEY (`receiver`)
+REFERENCES rfid_receiver(`id`),
+ PRIMARY KEY( `tracker`, `receiver`));
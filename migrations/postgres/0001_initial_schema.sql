@@ -0,0 +1,108 @@
+-- The schema of the MySQL migrations up to 0007_tracker_registration_time, for PostgreSQL
+create table "location" ("id" serial PRIMARY KEY ,
+"name" varchar(128) NOT NULL );
+create table "rfid_tracker" ("id" varchar(64) NOT NULL PRIMARY KEY ,
+"location" integer REFERENCES location("id") ,
+"registered_epoch" bigint );
+create table "rfid_receiver" ("id" varchar(64) NOT NULL PRIMARY KEY ,
+"location" integer NOT NULL REFERENCES location("id") );
+create table "interest" ("id" serial PRIMARY KEY ,
+"name" varchar(128) NOT NULL UNIQUE );
+create table "tracker_interest" ("interest" integer NOT NULL REFERENCES interest("id") ,
+"tracker" varchar(64) NOT NULL REFERENCES rfid_tracker("id") ,
+"weight" real NOT NULL ,
+ PRIMARY KEY( "interest", "tracker"));
+create table "display" ("id" serial PRIMARY KEY ,
+"location" integer NOT NULL REFERENCES location("id") );
+create table "advertisement_video" ("id" serial PRIMARY KEY ,
+"interest" integer NOT NULL REFERENCES interest("id") ,
+"length_sec" integer NOT NULL ,
+"url" varchar(255) NOT NULL );
+create table "agency" ("orgnr" varchar(128) NOT NULL PRIMARY KEY ,
+"name" varchar(128) NOT NULL UNIQUE );
+create table "users" ("username" varchar(128) NOT NULL PRIMARY KEY ,
+"email" varchar(128) NOT NULL UNIQUE ,
+"agency" varchar(128) NOT NULL REFERENCES agency("orgnr") ,
+"pass_hash" varchar(128) NOT NULL );
+create table "orders" ("id" varchar(40) NOT NULL PRIMARY KEY ,
+"credits" integer NOT NULL ,
+"user" varchar(128) NOT NULL REFERENCES users("email") );
+create table "played_video" ("id" serial PRIMARY KEY ,
+"video" integer NOT NULL REFERENCES advertisement_video("id") ,
+"time_epoch" bigint NOT NULL ,
+"order" varchar(40) NOT NULL REFERENCES orders("id") ,
+"display" integer ,
+"length_sec" integer ,
+"complete" boolean ,
+"credits" integer NOT NULL DEFAULT 0 ,
+"voided" boolean NOT NULL DEFAULT false ,
+"voided_epoch" bigint ,
+"void_reason" varchar(255) );
+create table "advertisement_order" ("video" integer NOT NULL REFERENCES advertisement_video("id") ,
+"orders" varchar(40) NOT NULL REFERENCES orders("id") ,
+"start_time_epoch" bigint NOT NULL ,
+"end_time_epoch" bigint NOT NULL ,
+ PRIMARY KEY( "video", "orders"));
+create table "refresh_token" ("refresh_token" bytea ,
+"id" varchar(40) NOT NULL PRIMARY KEY );
+create table "device_log" ("id" serial PRIMARY KEY ,
+"device" varchar(64) ,
+"severity" varchar(16) NOT NULL ,
+"firmware" varchar(64) ,
+"time_epoch" bigint NOT NULL ,
+"message" text NOT NULL ,
+"fields" text );
+create index "device_log_device_time" on "device_log" ("device", "time_epoch");
+create table "device_heartbeat" ("device_type" varchar(16) NOT NULL ,
+"device" varchar(64) NOT NULL ,
+"time_epoch" bigint NOT NULL ,
+"uptime_sec" bigint ,
+"firmware" varchar(64) ,
+"free_memory" bigint ,
+"last_error" text ,
+ PRIMARY KEY( "device_type", "device"));
+create table "rate_card" ("id" serial PRIMARY KEY ,
+"location" integer REFERENCES location("id") ,
+"start_minute" integer NOT NULL DEFAULT 0 ,
+"end_minute" integer NOT NULL DEFAULT 1440 ,
+"credits_per_second" double precision NOT NULL ,
+"min_credits" integer NOT NULL DEFAULT 1 ,
+"rounding" varchar(8) NOT NULL DEFAULT 'down' );
+create table "redeemed_play_token" ("signature" varchar(64) NOT NULL PRIMARY KEY ,
+"time_epoch" bigint NOT NULL );
+create table "agency_webhook" ("id" serial PRIMARY KEY ,
+"agency" varchar(128) NOT NULL REFERENCES agency("orgnr") ,
+"url" varchar(255) NOT NULL ,
+"secret" varchar(128) ,
+"events" varchar(255) ,
+"low_credit_threshold" integer NOT NULL DEFAULT 0 ,
+"active" boolean NOT NULL DEFAULT true );
+create table "webhook_delivery" ("id" serial PRIMARY KEY ,
+"webhook" integer NOT NULL REFERENCES agency_webhook("id") ,
+"event" varchar(32) NOT NULL ,
+"order" varchar(40) NOT NULL ,
+"payload" text NOT NULL ,
+"status" varchar(16) NOT NULL DEFAULT 'pending' ,
+"attempts" integer NOT NULL DEFAULT 0 ,
+"created_epoch" bigint NOT NULL ,
+"next_attempt_epoch" bigint NOT NULL ,
+"last_attempt_epoch" bigint ,
+"response_code" integer ,
+"last_error" text );
+create index "webhook_delivery_due" on "webhook_delivery" ("status", "next_attempt_epoch");
+create table "campaign_event" ("video" integer NOT NULL ,
+"orders" varchar(40) NOT NULL ,
+"event" varchar(32) NOT NULL ,
+"time_epoch" bigint NOT NULL ,
+ PRIMARY KEY( "video", "orders", "event"));
+create table "credit_ledger" ("id" serial PRIMARY KEY ,
+"order" varchar(40) NOT NULL REFERENCES orders("id") ,
+"time_epoch" bigint NOT NULL ,
+"entry_type" varchar(16) NOT NULL ,
+"amount" integer NOT NULL ,
+"balance_after" integer NOT NULL ,
+"played_video" integer REFERENCES played_video("id") ,
+"rate_card" integer ,
+"credits_per_second" double precision ,
+"description" varchar(255) );
+create index "credit_ledger_order" on "credit_ledger" ("order", "id");
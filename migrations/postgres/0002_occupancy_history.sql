@@ -0,0 +1,6 @@
+-- The number of trackers at a location from time_epoch on, recorded every time it changes
+create table "occupancy_history" ("id" serial PRIMARY KEY ,
+"location" integer NOT NULL REFERENCES location("id") ,
+"time_epoch" bigint NOT NULL ,
+"trackers" bigint NOT NULL );
+create index "occupancy_history_location_time" on "occupancy_history" ("location", "time_epoch");
@@ -0,0 +1,5 @@
+-- The receivers that see a tracker, a tracker is gone from its location when no receiver there sees it anymore
+create table `tracker_presence` (`tracker` varchar(64) NOT NULL REFERENCES rfid_tracker(`id`) ,
+`receiver` varchar(64) NOT NULL REFERENCES rfid_receiver(`id`) ,
+`time_epoch` integer NOT NULL ,
+ PRIMARY KEY( `tracker`, `receiver`));
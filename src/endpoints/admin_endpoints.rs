@@ -0,0 +1,147 @@
+use log::error;
+use rocket::post;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{Json, Value};
+use serde_json::json;
+
+use super::{Admin, Storage, VoidBody, VoidDisplayBody, error_builder, server_error_builder};
+use crate::model::VoidedPlay;
+use crate::services::ledger;
+use crate::services::LedgerServiceError::{NoSuchPlay, NoSuchDisplay, PlayAlreadyVoided, Other};
+
+fn voided_json(plays: &Vec<VoidedPlay>) -> Value {
+    json!({
+        "status": "success",
+        "voided": plays.iter().map(|p| json!({"view": p.id, "order": p.order, "credits": p.credits})).collect::<Vec<serde_json::Value>>(),
+        "credits_refunded": plays.iter().map(|p| p.credits).sum::<i32>()
+    })
+}
+
+/**
+* Voids a play, giving the credits drawn for it back to its order in a single transaction.
+* The play is kept, marked as voided, and the refund is recorded in the credit ledger of the order.
+*
+* Requires the admin token in the `X-Admin-Token` header.
+*
+* Responds with:
+* ### 200: if the play was voided
+* Response body:
+*
+* `{"status": "success", "voided": [{"view": <played_video_id>, "order": <order_id>, "credits": <credits_refunded>}], "credits_refunded": <total_credits_refunded>}`
+*
+* ### 400: if the play has already been voided
+* ### 401: if the admin token is missing or wrong
+* ### 404: if the play does not exist
+* ### 500: if the store fails
+*
+* This is an API endpoint mapped to
+* - /admin/views/<view_id>/void [POST]
+*
+* # Arguments
+* ## url parameters:
+* - `view_id` - the id of the play, as listed in order statements.
+* ## Post body (json):
+* `{ reason: <why_the_play_is_voided> }`, reason is optional
+*  */
+#[post("/admin/views/<view_id>/void", data = "<body>")]
+pub async fn void_view(store: Storage<'_>, _admin: Admin, view_id: i32, body: Json<VoidBody>) -> Result<Value, Option<status::Custom<Value>>> {
+    match store.run(|store| ledger::void_play(store, view_id, &body.reason)) {
+        Ok(play) => Ok(voided_json(&vec![play])),
+        Err(NoSuchPlay) => Err(None),
+        Err(PlayAlreadyVoided) => Err(Some(error_builder(Status::BadRequest, format!("view {} has already been voided", view_id)))),
+        Err(Other) => Err(Some(server_error_builder())),
+        Err(e) => {
+            error!("{:?} shouldn't happen here", e);
+            Err(Some(server_error_builder()))
+        }
+    }
+}
+
+/**
+* Voids every play of a display between two times, e.g. the bogus views reported by a malfunctioning display.
+* Plays already voided are left out. The credits are given back to the orders in a single transaction,
+* the plays are kept, marked as voided, and every refund is recorded in the credit ledger of its order.
+*
+* Requires the admin token in the `X-Admin-Token` header.
+*
+* Responds with:
+* ### 200: with the plays voided, possibly none
+* Response body:
+*
+* `{"status": "success", "voided": [{"view": <played_video_id>, "order": <order_id>, "credits": <credits_refunded>}], "credits_refunded": <total_credits_refunded>}`
+*
+* ### 400: if `from` is after `to`
+* ### 401: if the admin token is missing or wrong
+* ### 404: if the display does not exist
+* ### 500: if the store fails
+*
+* This is an API endpoint mapped to
+* - /admin/displays/<display_id>/void [POST]
+*
+* # Arguments
+* ## url parameters:
+* - `display_id` - the id of the display.
+* ## Post body (json):
+* `{ from: <epoch_seconds>, to: <epoch_seconds>, reason: <why_the_plays_are_voided> }`, plays from `from` to `to` inclusive are voided, reason is optional
+*  */
+#[post("/admin/displays/<display_id>/void", data = "<body>")]
+pub async fn void_display_views(store: Storage<'_>, _admin: Admin, display_id: i32, body: Json<VoidDisplayBody>) -> Result<Value, Option<status::Custom<Value>>> {
+    if body.from > body.to {
+        return Err(Some(error_builder(Status::BadRequest, format!("from {} is after to {}", body.from, body.to))));
+    }
+    match store.run(|store| ledger::void_display_plays(store, display_id, body.from, body.to, &body.reason)) {
+        Ok(plays) => Ok(voided_json(&plays)),
+        Err(NoSuchDisplay) => Err(None),
+        Err(Other) => Err(Some(server_error_builder())),
+        Err(e) => {
+            error!("{:?} shouldn't happen here", e);
+            Err(Some(server_error_builder()))
+        }
+    }
+}
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use mocktopus::mocking::*;
+    use futures::executor::block_on;
+    use super::*;
+    use crate::persistance::db;
+    use crate::persistance::store::MysqlStore;
+
+    #[test]
+    fn void_view_responses_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        ledger::void_play.mock_safe(|_, id, reason| {
+            assert_eq!(reason, &Some("bogus".to_owned()));
+            MockResult::Return(match id {
+                1 => Ok(VoidedPlay {id: 1, order: "order_1".to_owned(), credits: 12}),
+                2 => Err(PlayAlreadyVoided),
+                4 => Err(NoSuchDisplay),
+                _ => Err(NoSuchPlay)
+            })
+        });
+        let body = || Json(VoidBody {reason: Some("bogus".to_owned())});
+        assert_eq!(block_on(void_view(Storage::new(&MysqlStore), Admin(()), 1, body())), Ok(json!({
+            "status": "success",
+            "voided": [{"view": 1, "order": "order_1", "credits": 12}],
+            "credits_refunded": 12
+        })));
+        assert_eq!(block_on(void_view(Storage::new(&MysqlStore), Admin(()), 2, body())), Err(Some(error_builder(Status::BadRequest, "view 2 has already been voided".to_owned()))));
+        assert_eq!(block_on(void_view(Storage::new(&MysqlStore), Admin(()), 3, body())), Err(None));
+        assert_eq!(block_on(void_view(Storage::new(&MysqlStore), Admin(()), 4, body())), Err(Some(server_error_builder())));
+    }
+
+    #[test]
+    fn void_display_views_with_reversed_time_range_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        ledger::void_display_plays.mock_safe(|_, _, _, _, _| panic!("plays should not be voided for a reversed time range"));
+        let body = Json(VoidDisplayBody {from: 10, to: 5, reason: None});
+        assert_eq!(block_on(void_display_views(Storage::new(&MysqlStore), Admin(()), 1, body)), Err(Some(error_builder(Status::BadRequest, "from 10 is after to 5".to_owned()))));
+    }
+}
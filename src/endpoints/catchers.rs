@@ -1,35 +1,46 @@
-use rocket_contrib::json::JsonValue;
+use rocket::serde::json::Value;
 use serde_json::json;
 
 /**
  * 404 override
  */
 #[catch(404)]
-pub fn not_found() -> JsonValue {
-    JsonValue(json!({
+pub fn not_found() -> Value {
+    json!({
         "status": "error",
         "reason": "not found"
-    }))
+    })
+}
+
+/**
+ * 401 override
+ */
+#[catch(401)]
+pub fn unauthorized() -> Value {
+    json!({
+        "status": "error",
+        "message": "unauthorized"
+    })
 }
 
 /**
  * 400 orverride
  */
 #[catch(400)]
-pub fn bad_request() -> JsonValue {
-    JsonValue(json!({
+pub fn bad_request() -> Value {
+    json!({
         "status": "error",
         "message": "request could not be fullfilled. Check request headers and body format"
-    }))
+    })
 }
 
 /**
  * 422 override
  */
 #[catch(422)]
-pub fn unproc_request() -> JsonValue {
-    JsonValue(json!({
+pub fn unproc_request() -> Value {
+    json!({
         "status": "error",
         "message": "request could not be processed. Check request headers and body content"
-    }))
+    })
 }
\ No newline at end of file
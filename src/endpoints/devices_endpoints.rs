@@ -1,15 +1,17 @@
-use futures::executor::block_on;
-use rocket_contrib::json::{JsonValue, Json};
+use rocket::serde::json::{Json, Value};
 use serde_json::json;
-use rocket::get;
+use rocket::{get, State};
+use log::{debug, error};
 
-use crate::persistance::db;
 use crate::services::devices;
-use super::RegisterBody;
+use crate::services::occupancy::SharedOccupancy;
+use crate::model::{DeviceStatus, DeviceType};
+use super::{RegisterBody, HeartbeatBody, Storage, server_error_builder};
+use rocket::response::status;
 use crate::services::DeviceServiceError::{
     NoSuchTracker,
     NoSuchReceiver,
-    Other
+    NoSuchDisplay
 };
 
 
@@ -31,8 +33,8 @@ use crate::services::DeviceServiceError::{
 * 
 *  */
 #[post("/register", data = "<body>")]
-pub fn register_json(body: Json<RegisterBody>) -> Option<JsonValue> {
-    register(body.loc.clone(), body.tag.clone())
+pub async fn register_json(store: Storage<'_>, occupancy: &State<SharedOccupancy>, body: Json<RegisterBody>) -> Option<Value> {
+    register(store, occupancy, body.loc.clone(), body.tag.clone()).await
 }
 
 
@@ -51,11 +53,11 @@ pub fn register_json(body: Json<RegisterBody>) -> Option<JsonValue> {
  * * `tracker_id` - an identifier String of a tracker
  */
 #[post("/register/<station_id>/<tracker_id>")]
-pub fn register(station_id: String, tracker_id: String) ->  Option<JsonValue> {
-    match block_on(devices::ftr_register_tracker_location(&station_id, &tracker_id)) {
+pub async fn register(store: Storage<'_>, occupancy: &State<SharedOccupancy>, station_id: String, tracker_id: String) ->  Option<Value> {
+    match store.run(|store| devices::ftr_register_tracker_location(store, occupancy, &station_id, &tracker_id)) {
         Ok(()) => 
-            Some(JsonValue(json!({"status": "registered", "tracker_id": tracker_id}))),
-        Err(e) => {println!("{:?}",e); None}
+            Some(json!({"status": "registered", "tracker_id": tracker_id})),
+        Err(e) => {debug!("{:?}",e); None}
     }
 }
 
@@ -77,8 +79,8 @@ pub fn register(station_id: String, tracker_id: String) ->  Option<JsonValue> {
 * 
 *  */
 #[post("/unregister", data = "<body>")]
-pub fn unregister_json(body: Json<RegisterBody>) -> Result<JsonValue, Option<JsonValue>> {
-    unregister(body.loc.clone(), body.tag.clone())
+pub async fn unregister_json(store: Storage<'_>, occupancy: &State<SharedOccupancy>, body: Json<RegisterBody>) -> Result<Value, Option<Value>> {
+    unregister(store, occupancy, body.loc.clone(), body.tag.clone()).await
 }
 
 
@@ -97,11 +99,11 @@ pub fn unregister_json(body: Json<RegisterBody>) -> Result<JsonValue, Option<Jso
  * * `tracker_id` - an identifier String of a tracker
  */
 #[post("/unregister/<station_id>/<tracker_id>")]
-pub fn unregister(station_id: String, tracker_id: String) -> Result<JsonValue, Option<JsonValue>> {
-    match block_on(devices::ftr_unregister_tracker_location(&station_id, &tracker_id)) {
-        Ok(_) =>  Ok(JsonValue(json!({"status": "unregistered", "tracker_id": tracker_id}))),
-        Err(NoSuchReceiver) | Err(NoSuchTracker) => Err(None),
-        Err(Other) => Err(Some(JsonValue(json!({"status": "error", "message": "unknown error performing the request"}))))
+pub async fn unregister(store: Storage<'_>, occupancy: &State<SharedOccupancy>, station_id: String, tracker_id: String) -> Result<Value, Option<Value>> {
+    match store.run(|store| devices::ftr_unregister_tracker_location(store, occupancy, &station_id, &tracker_id)) {
+        Ok(_) =>  Ok(json!({"status": "unregistered", "tracker_id": tracker_id})),
+        Err(NoSuchReceiver) | Err(NoSuchTracker) | Err(NoSuchDisplay) => Err(None),
+        Err(_) => Err(Some(json!({"status": "error", "message": "unknown error performing the request"})))
     }
 }
 
@@ -119,11 +121,104 @@ pub fn unregister(station_id: String, tracker_id: String) -> Result<JsonValue, O
  * * `tracker_id` - an identifier String of a tracker
  */
 #[get("/trackers/<tracker_id>")]
-pub fn get_tracker(tracker_id: String) ->  Option<Result<JsonValue, &'static str>> {
-    match db::get_tracker_by_id(&tracker_id) {
+pub async fn get_tracker(store: Storage<'_>, tracker_id: String) ->  Option<Result<Value, &'static str>> {
+    match store.run(|store| store.get_tracker_by_id(&tracker_id)) {
         Ok(Some(tr)) => 
-        Some(Ok(JsonValue(json!({"id": tr.id, "location": tr.location})))),
+        Some(Ok(json!({"id": tr.id, "location": tr.location}))),
         Ok(None) =>None,
-        Err(e) => {println!("{}", e); Some(Err("Unknown error"))}
+        Err(e) => {error!("{}", e); Some(Err("Unknown error"))}
+    }
+}
+
+/**
+ * Stores a heartbeat for a receiver, granted it exists.
+ *
+ * Responds with:
+ * - 200: if the receiver exists
+ * - 404: if the receiver does not exist
+ *
+ * This is an API endpoint mapped to
+ * - /heartbeat/receivers/<receiver_id> [POST]
+ *
+ * # Arguments
+ * * `receiver_id` - an identifier String of a receiver
+ * * Post body (json), all fields optional:
+ *
+ * `{ uptime_sec: <integer>, firmware: <firmware_version>, free_memory: <integer_bytes>, last_error: <message> }`
+ */
+#[post("/heartbeat/receivers/<receiver_id>", data = "<body>")]
+pub async fn receiver_heartbeat(store: Storage<'_>, receiver_id: String, body: Json<HeartbeatBody>) -> Result<Value, Option<Value>> {
+    heartbeat(store, DeviceType::Receiver, receiver_id, body.into_inner())
+}
+
+/**
+ * Stores a heartbeat for a display, granted it exists.
+ * Requesting a video for a display with /video/<display_id> also counts as a heartbeat.
+ *
+ * Responds with:
+ * - 200: if the display exists
+ * - 404: if the display does not exist
+ *
+ * This is an API endpoint mapped to
+ * - /heartbeat/displays/<display_id> [POST]
+ *
+ * # Arguments
+ * * `display_id` - the id of a display
+ * * Post body (json), all fields optional:
+ *
+ * `{ uptime_sec: <integer>, firmware: <firmware_version>, free_memory: <integer_bytes>, last_error: <message> }`
+ */
+#[post("/heartbeat/displays/<display_id>", data = "<body>")]
+pub async fn display_heartbeat(store: Storage<'_>, display_id: i32, body: Json<HeartbeatBody>) -> Result<Value, Option<Value>> {
+    heartbeat(store, DeviceType::Display, display_id.to_string(), body.into_inner())
+}
+
+fn heartbeat(store: Storage, device_type: DeviceType, device_id: String, body: HeartbeatBody) -> Result<Value, Option<Value>> {
+    let status = DeviceStatus {
+        uptime_sec: body.uptime_sec,
+        firmware: body.firmware,
+        free_memory: body.free_memory,
+        last_error: body.last_error
+    };
+    match store.run(|store| devices::register_heartbeat(store, device_type, &device_id, status)) {
+        Ok(()) => Ok(json!({"status": "success", "message": "heartbeat registered"})),
+        Err(NoSuchReceiver) | Err(NoSuchDisplay) | Err(NoSuchTracker) => Err(None),
+        Err(_) => Err(Some(json!({"status": "error", "message": "unknown error performing the request"})))
+    }
+}
+
+/**
+ * Lists the receivers and displays whose last heartbeat is older than a threshold, or that never sent one.
+ *
+ * Responds with:
+ * - 200: with the stale devices
+ * - 500: if the store fails
+ *
+ * Response body:
+ *
+ * `{"status": "success", "devices": [{"type": <receiver|display>, "id": <device_id>, "last_heartbeat": <epoch_seconds|null>, "uptime_sec": .., "firmware": .., "free_memory": .., "last_error": ..}]}`
+ *
+ * This is an API endpoint mapped to
+ * - /heartbeat/stale?threshold_sec=<seconds> [GET]
+ *
+ * # Arguments
+ * * `threshold_sec` - devices with a heartbeat in the last threshold_sec seconds are considered alive, defaults to 300
+ */
+#[get("/heartbeat/stale?<threshold_sec>")]
+pub async fn stale_devices(store: Storage<'_>, threshold_sec: Option<u64>) -> Result<Value, status::Custom<Value>> {
+    match store.run(|store| devices::find_stale_devices(store, threshold_sec.unwrap_or(300))) {
+        Ok(devices) => Ok(json!({"status": "success", "devices": devices.iter().map(|d| {
+            let status = d.last_heartbeat.as_ref().map(|h| h.status.clone()).unwrap_or_default();
+            json!({
+                "type": d.device_type.as_str(),
+                "id": d.device,
+                "last_heartbeat": d.last_heartbeat.as_ref().map(|h| h.time_epoch),
+                "uptime_sec": status.uptime_sec,
+                "firmware": status.firmware,
+                "free_memory": status.free_memory,
+                "last_error": status.last_error
+            })
+        }).collect::<Vec<serde_json::Value>>()})),
+        Err(_) => Err(server_error_builder())
     }
 }
\ No newline at end of file
@@ -0,0 +1,119 @@
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use rocket::get;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Value;
+use serde_json::json;
+
+use crate::environment;
+use super::Storage;
+
+/**
+ * The maximum time the readiness check waits for the database
+ */
+static READINESS_DB_TIMEOUT: Duration = Duration::from_secs(2);
+
+lazy_static! {
+    static ref STARTED_AT: Instant = Instant::now();
+}
+
+/**
+ * Marks the start of the server, the uptime reported by the health endpoints is counted from here.
+ */
+pub fn mark_started() {
+    lazy_static::initialize(&STARTED_AT);
+}
+
+fn server_info() -> serde_json::Value {
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "environment": environment::get_current_env(),
+        "uptime_sec": STARTED_AT.elapsed().as_secs()
+    })
+}
+
+/**
+ * Checks that the server is running. Does not check any dependencies.
+ *
+ * Responds with:
+ * - 200: `{"status": "alive", "version": <version>, "environment": <environment>, "uptime_sec": <seconds>}`
+ *
+ * This is an API endpoint mapped to
+ * - /health/live [GET]
+ */
+#[get("/health/live")]
+pub fn live() -> Value {
+    let mut body = server_info();
+    body["status"] = json!("alive");
+    body
+}
+
+/**
+ * Checks that the server is ready to handle requests, which means the database answers a trivial query within the readiness timeout.
+ *
+ * Responds with:
+ * - 200: `{"status": "ready", "database": "ok", "version": <version>, "environment": <environment>, "uptime_sec": <seconds>}`
+ * - 503: `{"status": "unavailable", "database": <error>, ...}` if the database does not answer within the timeout
+ *
+ * This is an API endpoint mapped to
+ * - /health/ready [GET]
+ */
+#[get("/health/ready")]
+pub async fn ready(store: Storage<'_>) -> status::Custom<Value> {
+    let mut body = server_info();
+    match store.run(|store| store.ping(READINESS_DB_TIMEOUT)) {
+        Ok(()) => {
+            body["status"] = json!("ready");
+            body["database"] = json!("ok");
+            status::Custom(Status::Ok, body)
+        },
+        Err(e) => {
+            body["status"] = json!("unavailable");
+            body["database"] = json!(e);
+            status::Custom(Status::ServiceUnavailable, body)
+        }
+    }
+}
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use mocktopus::mocking::*;
+    use futures::executor::block_on;
+    use super::*;
+    use crate::persistance::db;
+    use crate::persistance::store::MysqlStore;
+
+    #[test]
+    fn ready_when_database_answers_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::ping.mock_safe(|_| MockResult::Return(Ok(())));
+        let status::Custom(status, body) = block_on(ready(Storage::new(&MysqlStore)));
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["status"], "ready");
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn not_ready_when_database_is_down_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::ping.mock_safe(|_| MockResult::Return(Err("could not connect to database".to_owned())));
+        let status::Custom(status, body) = block_on(ready(Storage::new(&MysqlStore)));
+        assert_eq!(status, Status::ServiceUnavailable);
+        assert_eq!(body["status"], "unavailable");
+        assert_eq!(body["database"], "could not connect to database");
+    }
+
+    #[test]
+    fn live_does_not_touch_database_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::ping.mock_safe(|_| panic!("liveness should not check the database"));
+        let body = live();
+        assert_eq!(body["status"], "alive");
+    }
+}
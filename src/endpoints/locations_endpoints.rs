@@ -0,0 +1,173 @@
+use rocket::{get, State};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Value;
+use serde_json::json;
+
+use super::{Storage, error_builder, server_error_builder};
+use crate::services::occupancy::{self, SharedOccupancy, MAX_HISTORY_BUCKETS};
+use crate::services::OccupancyServiceError::{NoSuchLocation, Other};
+
+/**
+* Returns the audience of a location: the trackers present, their interests summed per interest, highest weight first,
+* and the displays and receivers of the location.
+*
+* Responds with:
+* ### 200: if the location exists
+* Response body:
+*
+* `{"status": "success", "location": <location_id>, "trackers": <number_of_trackers>,
+* "interests": [{"interest": <interest_id>, "weight": <summed_weight>}], "displays": [<display_id>], "receivers": [<receiver_id>]}`
+*
+* ### 404: if the location does not exist
+* ### 500: if the store fails
+*
+* This is an API endpoint mapped to
+* - /locations/<location_id>/audience [GET]
+*
+* # Arguments
+* ## url parameters:
+* - `location_id` - the id of the location.
+*  */
+#[get("/locations/<location_id>/audience")]
+pub async fn get_audience(store: Storage<'_>, occupancy: &State<SharedOccupancy>, location_id: i32) -> Result<Value, Option<status::Custom<Value>>> {
+    match store.run(|store| occupancy::get_audience(store, occupancy, location_id)) {
+        Ok(audience) => Ok(json!({
+            "status": "success",
+            "location": audience.location,
+            "trackers": audience.trackers,
+            "interests": audience.interests.iter().map(|(interest, weight)| json!({"interest": interest, "weight": weight})).collect::<Vec<serde_json::Value>>(),
+            "displays": audience.displays,
+            "receivers": audience.receivers
+        })),
+        Err(NoSuchLocation) => Err(None),
+        Err(Other) => Err(Some(server_error_builder()))
+    }
+}
+
+/**
+* Returns the number of trackers at a location over time, in buckets of time.
+* Every bucket gives the trackers averaged over the bucket and the most trackers during the bucket.
+* The history ends now if `to` is in the future, and the last bucket is cut short at the end of the history.
+*
+* Responds with:
+* ### 200: if the location exists
+* Response body:
+*
+* `{"status": "success", "location": <location_id>, "bucket_sec": <bucket_sec>,
+* "buckets": [{"start": <epoch_seconds>, "average": <average_trackers>, "peak": <most_trackers>}]}`
+*
+* ### 400: if `from` is not before `to`, `bucket_sec` is 0 or the history would have more than 1000 buckets
+* ### 404: if the location does not exist
+* ### 500: if the store fails
+*
+* This is an API endpoint mapped to
+* - /locations/<location_id>/audience/history?from=<epoch_seconds>&to=<epoch_seconds>&bucket_sec=<seconds> [GET]
+*
+* # Arguments
+* ## url parameters:
+* - `location_id` - the id of the location.
+* ## query parameters:
+* - `from` - the start of the history
+* - `to` - the end of the history
+* - `bucket_sec` - the length of the buckets, optional, defaults to 3600
+*  */
+#[get("/locations/<location_id>/audience/history?<from>&<to>&<bucket_sec>")]
+pub async fn get_audience_history(store: Storage<'_>, location_id: i32, from: u64, to: u64, bucket_sec: Option<u64>) -> Result<Value, Option<status::Custom<Value>>> {
+    let bucket_sec = bucket_sec.unwrap_or(3600);
+    if bucket_sec == 0 {
+        return Err(Some(error_builder(Status::BadRequest, format!("bucket_sec must be at least 1"))));
+    }
+    if from >= to {
+        return Err(Some(error_builder(Status::BadRequest, format!("from {} is not before to {}", from, to))));
+    }
+    if (to - from + bucket_sec - 1) / bucket_sec > MAX_HISTORY_BUCKETS {
+        return Err(Some(error_builder(Status::BadRequest, format!("the history can have at most {} buckets", MAX_HISTORY_BUCKETS))));
+    }
+    match store.run(|store| occupancy::get_audience_history(store, location_id, from, to, bucket_sec)) {
+        Ok(buckets) => Ok(json!({
+            "status": "success",
+            "location": location_id,
+            "bucket_sec": bucket_sec,
+            "buckets": buckets.iter().map(|b| json!({"start": b.start_epoch, "average": b.average, "peak": b.peak})).collect::<Vec<serde_json::Value>>()
+        })),
+        Err(NoSuchLocation) => Err(None),
+        Err(Other) => Err(Some(server_error_builder()))
+    }
+}
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use mocktopus::mocking::*;
+    use futures::executor::block_on;
+    use super::*;
+    use crate::persistance::store::MysqlStore;
+    use crate::model::{Audience, OccupancyBucket};
+    use crate::persistance::db;
+
+    #[test]
+    fn get_audience_of_nonexistent_location_gives_404_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        occupancy::get_audience.mock_safe(|_, _, _| MockResult::Return(Err(NoSuchLocation)));
+        assert_eq!(block_on(get_audience(Storage::new(&MysqlStore), State::from(&SharedOccupancy::default()), 1)), Err(None));
+    }
+
+    #[test]
+    fn get_audience_when_the_store_fails_gives_500_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        occupancy::get_audience.mock_safe(|_, _, _| MockResult::Return(Err(Other)));
+        assert_eq!(block_on(get_audience(Storage::new(&MysqlStore), State::from(&SharedOccupancy::default()), 1)), Err(Some(server_error_builder())));
+        assert_eq!(server_error_builder().0, Status::InternalServerError);
+    }
+
+    #[test]
+    fn get_audience_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        occupancy::get_audience.mock_safe(|_, _, location| MockResult::Return(Ok(Audience {
+            location, trackers: 2, interests: vec![(1, 3.0)], displays: vec![1, 2], receivers: vec!["receiver_1".to_owned()]
+        })));
+        assert_eq!(block_on(get_audience(Storage::new(&MysqlStore), State::from(&SharedOccupancy::default()), 1)), Ok(json!({
+            "status": "success",
+            "location": 1,
+            "trackers": 2,
+            "interests": [{"interest": 1, "weight": 3.0}],
+            "displays": [1, 2],
+            "receivers": ["receiver_1"]
+        })));
+    }
+
+    #[test]
+    fn get_audience_history_validates_the_buckets_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        occupancy::get_audience_history.mock_safe(|_, _, _, _, _| panic!("an invalid history should not be looked up"));
+        assert_eq!(block_on(get_audience_history(Storage::new(&MysqlStore), 1, 10, 5, None)),
+            Err(Some(error_builder(Status::BadRequest, "from 10 is not before to 5".to_owned()))));
+        assert_eq!(block_on(get_audience_history(Storage::new(&MysqlStore), 1, 0, 10, Some(0))),
+            Err(Some(error_builder(Status::BadRequest, "bucket_sec must be at least 1".to_owned()))));
+        assert_eq!(block_on(get_audience_history(Storage::new(&MysqlStore), 1, 0, 1001, Some(1))),
+            Err(Some(error_builder(Status::BadRequest, "the history can have at most 1000 buckets".to_owned()))));
+    }
+
+    #[test]
+    fn get_audience_history_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        occupancy::get_audience_history.mock_safe(|_, location, from, to, bucket_sec| {
+            assert_eq!((location, from, to, bucket_sec), (1, 0, 7200, 3600));
+            MockResult::Return(Ok(vec![
+                OccupancyBucket {start_epoch: 0, average: 0.5, peak: 1},
+                OccupancyBucket {start_epoch: 3600, average: 0.0, peak: 0}
+            ]))
+        });
+        assert_eq!(block_on(get_audience_history(Storage::new(&MysqlStore), 1, 0, 7200, None)), Ok(json!({
+            "status": "success",
+            "location": 1,
+            "bucket_sec": 3600,
+            "buckets": [{"start": 0, "average": 0.5, "peak": 1}, {"start": 3600, "average": 0.0, "peak": 0}]
+        })));
+    }
+}
@@ -0,0 +1,162 @@
+use rocket::get;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Value;
+use serde_json::json;
+
+use super::{LogBody, LogMessage, Storage, StrCont, error_builder, server_error_builder};
+use crate::model::{LogEntry, LogFilter, Severity};
+use crate::services::logs;
+
+/**
+* Converts a LogMessage to a LogEntry.
+* The severity is taken from `level` if given, otherwise `error` decides between error and info.
+*  */
+fn to_log_entry(message: LogMessage) -> Result<LogEntry, String> {
+    let severity = match (message.level, message.error) {
+        (Some(level), _) => match Severity::from_str(&level) {
+            Some(val) => val,
+            None => return Err(format!("unknown log level {}", level))
+        },
+        (None, true) => Severity::Error,
+        (None, false) => Severity::Info
+    };
+    Ok(LogEntry {
+        device: message.device,
+        severity,
+        firmware: message.firmware,
+        time_epoch: 0,
+        message: message.message,
+        fields: message.fields.map(|f| serde_json::Value::Object(f).to_string())
+    })
+}
+
+/**
+* Logs a message, error or batch of messages to persistent logs.
+*
+* Returns:
+*
+* `{"status": "success", "message": "message|error logged"}`
+*
+* or for batches:
+*
+* `{"status": "success", "message": "<n> messages logged"}`
+*
+* # Arguments
+* `body` [LogBody](enum.LogBody.html) a single [LogMessage](struct.LogMessage.html) or a batch of them.
+*  */
+fn log_message_json(store: Storage, body: LogBody) -> Result<Value, status::Custom<Value>>  {
+    let (messages, is_batch) = match body {
+        LogBody::Single(message) => (vec![message], false),
+        LogBody::Batch(messages) | LogBody::WrappedBatch { entries: messages } => (messages, true)
+    };
+    let entries = match messages.into_iter().map(to_log_entry).collect::<Result<Vec<LogEntry>, String>>() {
+        Ok(val) => val,
+        Err(e) => return Err(error_builder(Status::BadRequest, e))
+    };
+    let is_error = entries.iter().any(|e| e.severity >= Severity::Error);
+    match store.run(|store| logs::store_log_messages(store, entries)) {
+        Ok(n) if is_batch => Ok(json!({"status": "success", "message": format!("{} messages logged", n)})),
+        Ok(_) if is_error => Ok(json!({"status": "success", "message": "error logged"})),
+        Ok(_) => Ok(json!({"status": "success", "message": "message logged"})),
+        Err(_) => Err(server_error_builder())
+    }
+}
+
+
+/**
+* Logs a message, error or batch of messages to persistent logs.
+*
+* Returns:
+*
+* `{"status": "success", "message": "message|error logged"}`
+*
+* # Arguments
+* ## Post body (json):
+* `{ message: <message_to_log>, level: <trace|debug|info|warn|error|fatal>, device_id: <device_id>, firmware: <firmware_version>, context: {<key>: <value>} }`
+*
+* All fields except `message` are optional. The older `{ error: bool, message: <message_to_log> }` format is still accepted,
+* if `level` is not given and `error` is true the message will be logged as an error.
+*
+* Batches are posted as a json array of messages, or as `{ entries: [<message>, ...] }`.
+*
+* Bodies that are not valid json are logged as a plain message, bodies above the string limit are rejected with 413.
+* Responds with 500 if the store fails.
+*  */
+#[post("/logs", data = "<body>")]
+pub async fn log_message(store: Storage<'_>, body: StrCont) -> Result<Value, status::Custom<Value>>  {
+    match serde_json::from_str::<LogBody>(&body.data[..]) {
+        Ok(val) => log_message_json(store, val),
+        Err(_) => log_message_str(store, body.data)
+    }
+}
+
+/**
+* Logs a string persistent logs.
+*
+* Returns:
+* `{"status": "success", "message": "message logged"}`
+*
+* # Arguments
+* `body` String, the string to be logged.
+*  */
+fn log_message_str(store: Storage, body: String) -> Result<Value, status::Custom<Value>>  {
+    let entry = LogEntry {device: None, severity: Severity::Info, firmware: None, time_epoch: 0, message: body, fields: None};
+    match store.run(|store| logs::store_log_messages(store, vec![entry])) {
+        Ok(_) => Ok(json!({"status": "success", "message": "message logged"})),
+        Err(_) => Err(server_error_builder())
+    }
+}
+
+/**
+* Returns persisted log entries, newest first, optionally filtered by device, severity and time range.
+*
+* Responds with:
+* - 200: with the matching entries
+* - 400: if the severity is not a known severity
+* - 500: if the store fails
+*
+* Response body:
+*
+* `{"status": "success", "logs": [{"device": <device_id|null>, "severity": <severity>, "firmware": <firmware_version|null>, "time": <epoch_seconds>, "message": <message>, "fields": <json_object|null>}]}`
+*
+* This is an API endpoint mapped to
+* - /logs?device=<device_id>&severity=<severity>&min_severity=<severity>&from=<epoch_seconds>&to=<epoch_seconds> [GET]
+*
+* # Arguments
+* ## query parameters (all optional):
+* - `device` - only entries reported by this device
+* - `severity` - only entries with exactly this severity
+* - `min_severity` - only entries with this severity or worse, in the order trace, debug, info, warn, error, fatal
+* - `from` - only entries logged at or after this time
+* - `to` - only entries logged at or before this time
+*  */
+#[get("/logs?<device>&<severity>&<min_severity>&<from>&<to>")]
+pub async fn get_logs(store: Storage<'_>, device: Option<String>, severity: Option<String>, min_severity: Option<String>, from: Option<u64>, to: Option<u64>) -> Result<Value, status::Custom<Value>> {
+    let (severity, min_severity) = match (parse_severity(severity), parse_severity(min_severity)) {
+        (Ok(severity), Ok(min_severity)) => (severity, min_severity),
+        (Err(e), _) | (_, Err(e)) => return Err(error_builder(Status::BadRequest, e))
+    };
+    let filter = LogFilter {device, severity, min_severity, from_epoch: from, to_epoch: to};
+    match store.run(|store| logs::find_log_messages(store, &filter)) {
+        Ok(entries) => Ok(json!({"status": "success", "logs": entries.iter().map(|e| json!({
+            "device": e.device,
+            "severity": e.severity.as_str(),
+            "firmware": e.firmware,
+            "time": e.time_epoch,
+            "message": e.message,
+            "fields": e.fields.as_ref().and_then(|f| serde_json::from_str::<serde_json::Value>(f).ok())
+        })).collect::<Vec<serde_json::Value>>()})),
+        Err(_) => Err(server_error_builder())
+    }
+}
+
+fn parse_severity(severity: Option<String>) -> Result<Option<Severity>, String> {
+    match severity {
+        None => Ok(None),
+        Some(s) => match Severity::from_str(&s) {
+            Some(val) => Ok(Some(val)),
+            None => Err(format!("unknown severity {}", s))
+        }
+    }
+}
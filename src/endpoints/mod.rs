@@ -10,19 +10,47 @@ pub mod devices_endpoints;
  * Endpoint that deal with videos.
  */
 pub mod videos_endpoints;
+/**
+ * Endpoints that deal with device logs.
+ */
+pub mod logs_endpoints;
+/**
+ * Endpoints that deal with orders and their credits.
+ */
+pub mod orders_endpoints;
+/**
+ * Endpoints that deal with locations and their audience.
+ */
+pub mod locations_endpoints;
+/**
+ * Endpoints for administrators, guarded by the admin token.
+ */
+pub mod admin_endpoints;
+/**
+ * Liveness and readiness endpoints.
+ */
+pub mod health_endpoints;
 
 use serde::Deserialize;
-use rocket_contrib::json::{JsonValue};
-use serde_json::json;
-use rocket::data::{self, FromDataSimple};
-use rocket::{Request, Data, Outcome::*};
+use rocket::serde::json::{json, Value};
+use rocket::data::{self, FromData, Limits};
+use rocket::request::{self, FromRequest};
+use rocket::outcome::Outcome;
+use rocket::{Request, Data};
 use rocket::http::Status;
+use rocket::response::{content, status};
+use rocket::tokio::task;
+use crate::config;
+use crate::logging;
+use crate::metrics;
+use crate::services::devices;
+use crate::persistance::store::{SharedStore, Store};
 use std::io;
-use std::io::Read;
 
 #[derive(Deserialize)]
 pub struct VideoBody {
-    length_sec: i32
+    length_sec: i32,
+    token: String
 }
 
 #[derive(Deserialize)]
@@ -31,6 +59,26 @@ pub struct RegisterBody {
     tag: String
 }
 
+#[derive(Deserialize)]
+pub struct HeartbeatBody {
+    uptime_sec: Option<u64>,
+    firmware: Option<String>,
+    free_memory: Option<u64>,
+    last_error: Option<String>
+}
+
+#[derive(Deserialize)]
+pub struct VoidBody {
+    reason: Option<String>
+}
+
+#[derive(Deserialize)]
+pub struct VoidDisplayBody {
+    from: u64,
+    to: u64,
+    reason: Option<String>
+}
+
 #[derive(Deserialize)]
 pub struct StrCont {
     data: String,
@@ -38,22 +86,101 @@ pub struct StrCont {
 
 #[derive(Deserialize)]
 pub struct LogMessage {
+    #[serde(default)]
     error: bool,
+    level: Option<String>,
     message: String,
+    #[serde(alias = "device_id")]
+    device: Option<String>,
+    firmware: Option<String>,
+    #[serde(alias = "context")]
+    fields: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum LogBody {
+    Batch(Vec<LogMessage>),
+    WrappedBatch { entries: Vec<LogMessage> },
+    Single(LogMessage),
 }
 
-impl FromDataSimple for StrCont {
+#[rocket::async_trait]
+impl<'r> FromData<'r> for StrCont {
     type Error = io::Error;
-    #[inline(always)]
-    fn from_data(_: &Request, data: Data) -> data::Outcome<Self,  io::Error> {
-        let mut value = String::new();
-        match data.open().read_to_string(&mut value) {
-            Ok(_) => Success(StrCont{data: value}),
-            Err(e) => Failure((Status::BadRequest, e))
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = request.limits().get("string").unwrap_or(Limits::STRING);
+        match data.open(limit).into_string().await {
+            Ok(value) if !value.is_complete() => Outcome::Error((Status::PayloadTooLarge, io::Error::new(io::ErrorKind::Other, "the body exceeds the string limit"))),
+            Ok(value) => Outcome::Success(StrCont{data: value.into_inner()}),
+            Err(e) => Outcome::Error((Status::BadRequest, e))
         }
     }
 }
 
+/**
+ * Request guard of the admin endpoints, the request must carry the configured admin_token in the X-Admin-Token header.
+ * Fails with 401 if it does not, or if no admin_token is configured.
+ */
+pub struct Admin(());
+
+static ADMIN_TOKEN_HEADER: &'static str = "X-Admin-Token";
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = ();
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Admin, ()> {
+        match (&config::get().admin_token, request.headers().get_one(ADMIN_TOKEN_HEADER)) {
+            (Some(token), Some(given)) if constant_time_eq(token.as_bytes(), given.as_bytes()) => Outcome::Success(Admin(())),
+            _ => Outcome::Error((Status::Unauthorized, ()))
+        }
+    }
+}
+
+/**
+ * Request guard giving the endpoints the store managed by rocket, which they pass on to the services
+ */
+#[derive(Clone, Copy)]
+pub struct Storage<'r> {
+    store: &'r dyn Store,
+    request_id: Option<&'r str>
+}
+
+impl<'r> Storage<'r> {
+    #[cfg(test)]
+    pub fn new(store: &'r dyn Store) -> Storage<'r> {
+        Storage {store, request_id: None}
+    }
+
+    /**
+     * Runs the store work of a request. Stores block, so the work runs with block_in_place:
+     * the worker thread hands its other requests over to the other workers before blocking, instead of holding them up.
+     * Log lines written by the work carry the id of the request.
+     */
+    pub fn run<T, F: FnOnce(&'r dyn Store) -> T>(&self, f: F) -> T {
+        let store = self.store;
+        task::block_in_place(|| logging::with_request_id(self.request_id, || f(store)))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Storage<'r> {
+    type Error = ();
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Storage<'r>, ()> {
+        match request.rocket().state::<SharedStore>() {
+            Some(store) => Outcome::Success(Storage {store: &**store, request_id: Some(logging::request_id(request))}),
+            None => Outcome::Error((Status::InternalServerError, ()))
+        }
+    }
+}
+
+/**
+ * Compares without returning early, so that the time taken does not tell how much of the token was guessed right
+ */
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 /**
  * Gets the current API version / checks if api is alive
  * 
@@ -66,65 +193,39 @@ pub fn default() -> &'static str {
 }
 
 /**
-* Logs a message or error to persistent logs.
-*
-* Returns:
-* 
-* `{"status": "success", "message": "message|error logged"}`
-* 
-* # Arguments
-* `body` [LogMessage](struct.LogMessage.html) if LogMessage.error is true the message will be logged as an error.
-*  */
-fn log_message_json(body: LogMessage) -> Result<JsonValue, JsonValue>  {
-    match body.error {
-        true => {
-            colour::yellow!("\n====LOG ERROR FROM IOT====\n");   
-            colour::dark_red!("{}", body.message);
-            colour::yellow!("\n============================\n");
-            Ok(JsonValue(json!({"status": "success", "message": "error logged"})))
-        } 
-        _ => {
-            colour::yellow!("\n====LOG MESSAGE FROM IOT====\n");   
-            colour::yellow!("{}", body.message);
-            colour::yellow!("\n============================\n");
-            Ok(JsonValue(json!({"status": "success", "message": "message logged"})))
+ * Exposes the app metrics in the prometheus text format
+ *
+ * This is an API endpoint mapped to
+ * - /metrics [GET]
+ */
+#[get("/metrics")]
+pub async fn get_metrics(store: Storage<'_>) -> content::RawText<String> {
+    if let Ok(counts) = store.run(|store| devices::count_active_trackers(store)) {
+        metrics::ACTIVE_TRACKERS.reset();
+        for (location, count) in counts {
+            metrics::ACTIVE_TRACKERS.with_label_values(&[location.to_string().as_str()]).set(count);
         }
     }
+    content::RawText(metrics::render())
 }
 
+/**
+ * Utility function
+ */
+pub fn bad_request_builder(message: String) -> status::BadRequest<Value> {
+    status::BadRequest(json!({"status":"error", "message": format!("{}", message)}))
+}
 
 /**
-* Logs a message or error to persistent logs.
-*
-* Returns:
-* 
-* `{"status": "success", "message": "message|error logged"}`
-* 
-* # Arguments
-* ## Post body (json):
-* `{ error: bool, message: <message_to_log> }`
-* `body` [LogMessage](struct.LogMessage.html) if LogMessage.error is true the message will be logged as an error.
-*  */
-#[post("/logs", data = "<body>")]
-pub fn log_message(body: StrCont) -> Result<JsonValue, JsonValue>  {
-    match serde_json::from_str::<LogMessage>(&body.data[..]) {
-        Ok(val) => log_message_json(val),
-        Err(_) => log_message_str(body.data)
-    }
+ * Utility function, an error response with the given status
+ */
+pub fn error_builder(status: Status, message: String) -> status::Custom<Value> {
+    status::Custom(status, json!({"status":"error", "message": message}))
 }
 
 /**
-* Logs a string persistent logs.
-*
-* Returns:
-* `{"status": "success", "message": "message logged"}`
-* 
-* # Arguments
-* `body` String, the string to be logged.
-*  */
-fn log_message_str(body: String) -> Result<JsonValue, JsonValue>  {    
-    colour::yellow!("\n====LOG MESSAGE FROM IOT====\n");
-    colour::yellow!("{}", body);
-    colour::yellow!("\n============================\n");
-    Ok(JsonValue(json!({"status": "success", "message": "message logged"})))
+ * Utility function, the 500 response of a request that failed on the side of the server, like a failing store
+ */
+pub fn server_error_builder() -> status::Custom<Value> {
+    error_builder(Status::InternalServerError, format!("an internal error occurred"))
 }
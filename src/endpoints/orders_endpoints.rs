@@ -0,0 +1,112 @@
+use rocket::get;
+use rocket::response::status;
+use rocket::serde::json::Value;
+use serde_json::json;
+
+use super::{Storage, server_error_builder};
+use crate::services::ledger;
+use crate::services::LedgerServiceError::NoSuchOrder;
+
+/**
+* Returns the statement of an order, every credit movement recorded in its credit ledger, oldest first.
+*
+* Every play draws a debit entry carrying the played video and the rate applied.
+* The ledger of an order is started with an opening entry at its first play,
+* changes of the order balance made outside of the ledger are recorded as adjustment entries at the next play.
+*
+* Responds with:
+* ### 200: if the order exists
+* Response body:
+*
+* `{"status": "success", "order": <order_id>, "balance": <order_credits>, "ledger_balance": <sum_of_ledger_amounts>, "reconciled": <balance == ledger_balance>,
+* "entries": [{"id": <entry_id>, "time": <epoch_seconds>, "type": <opening|debit|top_up|refund|adjustment>, "amount": <credits, negative for debits>,
+* "balance_after": <credits>, "view": <played_video_id|null>, "rate_card": <rate_card_id|null>, "credits_per_second": <rate|null>, "description": <text|null>}]}`
+*
+* `rate_card` is null for debits charged with the default rate.
+*
+* ### 404: if the order does not exist
+* ### 500: if the store fails
+*
+* This is an API endpoint mapped to
+* - /orders/<order_id>/statement [GET]
+*
+* # Arguments
+* ## url parameters:
+* - `order_id` - the id of the order.
+*  */
+#[get("/orders/<order_id>/statement")]
+pub async fn get_statement(store: Storage<'_>, order_id: String) -> Result<Value, Option<status::Custom<Value>>> {
+    match store.run(|store| ledger::get_order_statement(store, &order_id)) {
+        Ok(statement) => Ok(json!({
+            "status": "success",
+            "order": statement.order.id,
+            "balance": statement.order.credits,
+            "ledger_balance": statement.ledger_balance(),
+            "reconciled": statement.is_reconciled(),
+            "entries": statement.entries.iter().map(|e| json!({
+                "id": e.id,
+                "time": e.time_epoch,
+                "type": e.entry_type.as_str(),
+                "amount": e.amount,
+                "balance_after": e.balance_after,
+                "view": e.played_video,
+                "rate_card": e.rate_card,
+                "credits_per_second": e.credits_per_second,
+                "description": e.description
+            })).collect::<Vec<serde_json::Value>>()
+        })),
+        Err(NoSuchOrder) => Err(None),
+        Err(_) => Err(Some(server_error_builder()))
+    }
+}
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use mocktopus::mocking::*;
+    use futures::executor::block_on;
+    use super::*;
+    use crate::persistance::db;
+    use crate::persistance::store::MysqlStore;
+    use crate::model::{Order, OrderStatement, LedgerEntry, LedgerEntryType};
+
+    #[test]
+    fn get_statement_for_nonexistent_order_gives_404_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        ledger::get_order_statement.mock_safe(|_, _| MockResult::Return(Err(NoSuchOrder)));
+        assert_eq!(block_on(get_statement(Storage::new(&MysqlStore), "order_1".to_owned())), Err(None));
+    }
+
+    #[test]
+    fn get_statement_lists_ledger_entries_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        ledger::get_order_statement.mock_safe(|_, order_id| MockResult::Return(Ok(OrderStatement {
+            order: Order {id: order_id.clone(), credits: 88, user: "user_1".to_owned()},
+            entries: vec![LedgerEntry {
+                id: 7,
+                order: order_id.clone(),
+                time_epoch: 10,
+                entry_type: LedgerEntryType::Debit,
+                amount: -12,
+                balance_after: 88,
+                played_video: Some(3),
+                rate_card: None,
+                credits_per_second: Some(0.125),
+                description: None
+            }]
+        })));
+        assert_eq!(block_on(get_statement(Storage::new(&MysqlStore), "order_1".to_owned())), Ok(json!({
+            "status": "success",
+            "order": "order_1",
+            "balance": 88,
+            "ledger_balance": -12,
+            "reconciled": false,
+            "entries": [{"id": 7, "time": 10, "type": "debit", "amount": -12, "balance_after": 88, "view": 3,
+                "rate_card": null, "credits_per_second": 0.125, "description": null}]
+        })));
+    }
+}
@@ -1,23 +1,48 @@
-use rocket::get;
+use log::error;
+use rocket::{get, State};
+use rocket::http::Status;
 use rocket::response::status;
-use rocket_contrib::json::{Json, JsonValue};
+use rocket::serde::json::{Json, Value};
 use serde_json::json;
 
-use super::VideoBody;
-use crate::persistance::db;
-use crate::services::videos;
+use super::{Storage, VideoBody, bad_request_builder, error_builder, server_error_builder};
+use crate::services::{devices, play_tokens, videos};
+use crate::services::occupancy::SharedOccupancy;
 use crate::services::VideoServiceError::{
-    NoSuchDisplay, NoSuchDisplayLocation, NoSuchOrder, NoSuchVideo, Other,
+    CampaignNotActive, InvalidPlayLength, NoSuchDisplay, NoSuchDisplayLocation, NoSuchOrder, NoSuchVideo,
+    ExpiredPlayToken, InvalidPlayToken, OrderNotForVideo, Other, RedeemedPlayToken,
 };
 
 /**
 * Registers a video view, granted the display_id, video_id, order_id, all exist.
 * If all arguments are valid, a new played_advertisement row will be created in the database, and credits removed from the order_id.
-* 1 credit will be withdrawn for each 8 seconds played.
+* The order must pay for the video in a campaign running at the time the view is registered,
+* and the video's interest must be present among the trackers at the display's location.
+*
+* The play must be proven with the token handed out with the video by `/video/<display_id>`.
+* The token is only valid for that display, video and order, until `play_token_ttl_sec` (default 300) seconds
+* after the video could have finished playing, and can only be used once.
+*
+* The charge is decided by the most specific rate card in the database for the display location and the time of day,
+* cards for the location before cards for all locations, then the card with the shortest time window.
+* A rate card gives credits per second, a minimum charge and whether to round up, down or to the nearest credit.
+* Where no rate card applies 1 credit is withdrawn for each `billing_seconds_per_credit` (default 8) seconds played.
+*
+* The reported length is checked against the length of the video. A play reported at most `play_length_tolerance_sec` (default 2)
+* seconds longer than the video is registered and billed as a complete play of the video length, other plays shorter than the video
+* are registered and billed as partial plays of the reported length.
 * 
 * Responds with:
 * - 200: if the tracker and receiver exist.
+* - 400: if the length is zero or less, or longer than the video allows
+* - 400: if the order does not pay for the video or the campaign is not running
+* - 400: if the token is invalid, expired or already used
 * - 404: if either the receiver or tracker does not exist
+* - 422: if the body is json without a length_sec or token
+*
+* Response body:
+*
+* `{"status": "success", "message": "video play logged", "credits": <credits_charged>, "length_sec": <registered_length>, "complete": <true|false>}`
 *
 * This is an API endpoint mapped to
 * - /views/<display_id>/<video_id>/<order_id> [POST]
@@ -29,12 +54,12 @@ use crate::services::VideoServiceError::{
 * - `order_id` - the id of the order that ordered the video
 * ## Post body (json):
 *
-* `{ length_sec: <integer_length_of_played_video>  }`
+* `{ length_sec: <integer_length_of_played_video>, token: <play_token_from_get_video> }`
 *  */
 #[post("/views/<display_id>/<video_id>/<order_id>", data = "<body>")]
-pub fn register_view( display_id: i32, video_id: i32, order_id: String, body: Json<VideoBody>) -> Result<JsonValue, status::BadRequest<JsonValue>> {
+pub async fn register_view(store: Storage<'_>,  display_id: i32, video_id: i32, order_id: String, body: Json<VideoBody>) -> Result<Value, status::BadRequest<Value>> {
     //TODO: The number of registered people in at the location should affect number of credits
-    match videos::register_video_view(display_id, video_id, &order_id, body.length_sec) {
+    match store.run(|store| videos::register_video_view(store, display_id, video_id, &order_id, body.length_sec, &body.token)) {
         Err(NoSuchVideo) => Err(bad_request_builder(format!(
             "no video with id {} found",
             video_id
@@ -47,25 +72,33 @@ pub fn register_view( display_id: i32, video_id: i32, order_id: String, body: Js
             "no order with id {} found",
             order_id
         ))),
-        Ok(_) => Ok(JsonValue(
-            json!({"status": "success", "message": "video play logged"}),
-        )),
+        Err(InvalidPlayLength(video_length_sec)) => Err(bad_request_builder(format!(
+            "length_sec {} is not a valid play length for video {} of {} seconds",
+            body.length_sec, video_id, video_length_sec
+        ))),
+        Err(OrderNotForVideo) => Err(bad_request_builder(format!(
+            "order {} does not pay for video {}",
+            order_id, video_id
+        ))),
+        Err(CampaignNotActive) => Err(bad_request_builder(format!(
+            "the campaign of order {} for video {} is not running",
+            order_id, video_id
+        ))),
+        Err(InvalidPlayToken) => Err(bad_request_builder(format!(
+            "the play token is not valid for display {}, video {} and order {}",
+            display_id, video_id, order_id
+        ))),
+        Err(ExpiredPlayToken) => Err(bad_request_builder(format!("the play token has expired"))),
+        Err(RedeemedPlayToken) => Err(bad_request_builder(format!("the play token has already been used"))),
+        Ok(played) => Ok(
+            json!({"status": "success", "message": "video play logged", "credits": played.credits, "length_sec": played.length_sec, "complete": played.complete}),
+        ),
         _ => Err(bad_request_builder(format!(
             "An unknown issue with the request"
         ))),
     }
 }
 
-/**
- * Utility function
- */
-fn bad_request_builder(message: String) -> status::BadRequest<JsonValue> {
-    status::BadRequest(Some(JsonValue(
-        json!({"status":"error", "message": format!("{}", message)}),
-    )))
-}
-
-
 /**
 * Get the most appropriate video to play on the screen of specified id
 * Appropriateness depends on the trackers currently registered to the reciver, and their interests
@@ -74,7 +107,8 @@ fn bad_request_builder(message: String) -> status::BadRequest<JsonValue> {
 *
 * 1: Get all the videos that match the interest(s) present at the display location.
 *
-* 2: Filter the results based on which videos have credits left to use
+* 2: Filter the results based on which videos have credits left to use in a running campaign,
+* leaving out orders that have spent more than their pacing target so far (see `pacing_curve`)
 *
 * 3: Find the highest weighted interest in the filtered results
 *
@@ -86,10 +120,14 @@ fn bad_request_builder(message: String) -> status::BadRequest<JsonValue> {
 * ### 200: if the tracker and receiver exist.
 * Response body:
 *
-* `{message: "video found", video :{"length": <video_length_seconds>, order: <associated_order>, url :<video_url>, <videoId>: <video_identifier>}}`
+* `{message: "video found", video :{"length": <video_length_seconds>, order: <associated_order>, url :<video_url>, <videoId>: <video_identifier>, token: <play_token>}}`
+*
+* The token proves the video was handed out to the display, and must be sent when registering the play at `/views`.
 * 
 * ### 404: if the display does not exist
+* ### 500: if the video service fails unexpectedly
 *
+* A request for a video also registers a heartbeat for the display.
 *
 * This is an API endpoint mapped to
 * - /video/<display_id> [GET]
@@ -99,26 +137,35 @@ fn bad_request_builder(message: String) -> status::BadRequest<JsonValue> {
 * - `display_id` - the id of the display to get video for.
 *  */
 #[get("/video/<display_id>")]
-pub fn get_video(display_id: i32) -> Result<JsonValue, Option<status::BadRequest<JsonValue>>> {
-    match db::get_display_by_id(display_id) {
-        Ok(None) => return Err(None),
-        _ => (),
-    };
-    match videos::find_relevant_video(display_id) {
-        Err(NoSuchDisplayLocation) => Err(Some(bad_request_builder(format!(
+pub async fn get_video(store: Storage<'_>, occupancy: &State<SharedOccupancy>, display_id: i32) -> Result<Value, Option<status::Custom<Value>>> {
+    let found = store.run(|store| match store.get_display_by_id(display_id) {
+        Ok(None) => None,
+        _ => {
+            //Asking for a video counts as a heartbeat, a failure to record it should not stop the video
+            let _ = devices::register_display_activity(store, display_id);
+            Some(videos::find_relevant_video(store, occupancy, display_id))
+        }
+    });
+    match found {
+        None => Err(None),
+        Some(Err(NoSuchDisplayLocation)) => Err(Some(error_builder(Status::BadRequest, format!(
             "The display {} did not exist, or does not have a location set",
             display_id
         )))),
-        Ok(Some(v)) => Ok(JsonValue(
-            json!({"video": {"url": v.url, "length": v.length_sec, "order": v.order, "videoId": v.video_id}, "message": "video found"}),
-        )),
-        Ok(None) => Ok(JsonValue(
+        Some(Ok(Some(v))) => Ok(
+            json!({"video": {"url": v.url, "length": v.length_sec, "order": v.order, "videoId": v.video_id,
+                "token": play_tokens::issue_play_token(display_id, v.video_id, &v.order)}, "message": "video found"}),
+        ),
+        Some(Ok(None)) => Ok(
             json!({"video": null, "message": "no trackers registered to location" }),
-        )),
-        Err(Other) => Err(Some(bad_request_builder(format!(
+        ),
+        Some(Err(Other)) => Err(Some(error_builder(Status::BadRequest, format!(
             "un unknown issue with the request"
         )))),
-        Err(e) => panic!("{:?} shouldn't happen here", e),
+        Some(Err(e)) => {
+            error!("{:?} shouldn't happen here", e);
+            Err(Some(server_error_builder()))
+        }
     }
 }
 
@@ -131,8 +178,11 @@ pub fn get_video(display_id: i32) -> Result<JsonValue, Option<status::BadRequest
 #[cfg(test)]
 mod tests {
     use mocktopus::mocking::*;
+    use futures::executor::block_on;
     use super::*;
-    use crate::model::{ Display, AdvertVideoOrder };
+    use crate::persistance::db;
+    use crate::persistance::store::MysqlStore;
+    use crate::model::{ Display, AdvertVideoOrder, DeviceType, OrderBudget, PlayedVideo };
 
     #[test]
     fn get_video_for_nonexistent_display_gives_404_unittest() {
@@ -144,12 +194,13 @@ mod tests {
                 _ => panic!("wrong argument sent to get_display_by_id when asking for display 1"),
             })
         });
-        assert_eq!(get_video(1), Err(None), "Getting video should give none when should get nonexistent")
+        assert_eq!(block_on(get_video(Storage::new(&MysqlStore), State::from(&SharedOccupancy::default()), 1)), Err(None), "Getting video should give none when should get nonexistent")
     }
     
     #[test]
     fn get_video_for_display_no_tracker_at_tisplay_unittest() {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::touch_heartbeat.mock_safe(|_, _, _| MockResult::Return(Ok(())));
 
         db::get_display_by_id.mock_safe(|param| {
             MockResult::Return(match param {
@@ -158,15 +209,15 @@ mod tests {
             })
         });
 
-        videos::find_relevant_video.mock_safe(|param| 
+        videos::find_relevant_video.mock_safe(|_, _, param| 
             MockResult::Return(match param {
                 1 => Ok(None),
                 _ => panic!("wrong argument sent to get_display_by_id when asking for display 1"),
             })
         );
         assert_eq!(
-            get_video(1),
-            Ok(JsonValue(json!({"video": null, "message": "no trackers registered to location" })))
+            block_on(get_video(Storage::new(&MysqlStore), State::from(&SharedOccupancy::default()), 1)),
+            Ok(json!({"video": null, "message": "no trackers registered to location" }))
             ,"Getting video should give none when should get nonexistent"
         )
     }
@@ -174,6 +225,7 @@ mod tests {
     #[test]
     fn get_video_for_display_that_has_trackers_unittest() {
         db::Dbconn::new.mock_safe(|| panic!(""));
+        db::touch_heartbeat.mock_safe(|_, _, _| MockResult::Return(Ok(())));
 
         db::get_display_by_id.mock_safe(|param| {
             MockResult::Return(match param {
@@ -182,52 +234,125 @@ mod tests {
             })
         });
 
-        videos::find_relevant_video.mock_safe(|param| {
+        videos::find_relevant_video.mock_safe(|_, _, param| {
             MockResult::Return(match param {
                 1 => Ok(Some(AdvertVideoOrder {
                     video_id: 1,
                     interest: 1,
                     url: "example.com/video".to_owned(), 
                     length_sec: 1,
-                    order: "order_1".to_owned()
+                    order: "order_1".to_owned(),
+                    budget: OrderBudget {remaining_credits: 100, spent_credits: 0, start_epoch: 0, end_epoch: 0}
                 })),
                 _ => panic!("wrong argument sent to get_display_by_id when asking for display 1"),
             })
         });
+        play_tokens::issue_play_token.mock_safe(|display, video, order| {
+            assert_eq!((display, video, &order[..]), (1, 1, "order_1"));
+            MockResult::Return("play_token".to_owned())
+        });
         assert_eq!(
-            get_video(1),
-            Ok(JsonValue(
+            block_on(get_video(Storage::new(&MysqlStore), State::from(&SharedOccupancy::default()), 1)),
+            Ok(
                 json!(
                     {"video": 
-                    {"url": "example.com/video", "length": 1, "order": "order_1", "videoId": 1}, "message": "video found"})))
+                    {"url": "example.com/video", "length": 1, "order": "order_1", "videoId": 1, "token": "play_token"}, "message": "video found"}))
             ,"Getting video should give none when should get nonexistent"
         )
     }
 
+    #[test]
+    fn get_video_registers_display_heartbeat_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::get_display_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Display {id: 1, location: 1}))));
+        videos::find_relevant_video.mock_safe(|_, _, _| MockResult::Return(Ok(None)));
+        db::touch_heartbeat.mock_safe(|device_type, device_id, _| {
+            assert_eq!(device_type, DeviceType::Display);
+            assert_eq!(device_id, "1");
+            MockResult::Return(Ok(()))
+        });
+        assert!(block_on(get_video(Storage::new(&MysqlStore), State::from(&SharedOccupancy::default()), 1)).is_ok(), "Getting video should succeed when the heartbeat is registered")
+    }
+
+    #[test]
+    fn get_video_with_unexpected_service_error_gives_500_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::touch_heartbeat.mock_safe(|_, _, _| MockResult::Return(Ok(())));
+        db::get_display_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Display {id: 1, location: 1}))));
+        videos::find_relevant_video.mock_safe(|_, _, _| MockResult::Return(Err(NoSuchVideo)));
+        assert_eq!(
+            block_on(get_video(Storage::new(&MysqlStore), State::from(&SharedOccupancy::default()), 1)),
+            Err(Some(server_error_builder()))
+        )
+    }
+
     #[test]
     pub fn register_view_when_nonexistent_video_unittest() {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
-        videos::register_video_view.mock_safe(|_, _, _, _| {MockResult::Return(Err(NoSuchVideo))});
+        videos::register_video_view.mock_safe(|_, _, _, _, _, _| {MockResult::Return(Err(NoSuchVideo))});
 
-        let json_body: Json<VideoBody> = Json(VideoBody { length_sec: 1} );
-        assert_eq!(register_view(1, 1,"order_id".to_owned(), json_body), Err(bad_request_builder("no video with id 1 found".to_owned())));
+        let json_body: Json<VideoBody> = Json(VideoBody { length_sec: 1, token: "token".to_owned() } );
+        assert_eq!(block_on(register_view(Storage::new(&MysqlStore), 1, 1,"order_id".to_owned(), json_body)), Err(bad_request_builder("no video with id 1 found".to_owned())));
     }
 
     #[test]
     pub fn register_view_when_nonexistent_display_unittest() {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
-        videos::register_video_view.mock_safe(|_, _, _, _| {MockResult::Return(Err(NoSuchDisplay))});
+        videos::register_video_view.mock_safe(|_, _, _, _, _, _| {MockResult::Return(Err(NoSuchDisplay))});
 
-        let json_body: Json<VideoBody> = Json(VideoBody { length_sec: 1} );
-        assert_eq!(register_view(1, 1,"order_id".to_owned(), json_body), Err(bad_request_builder("no display with id 1 found".to_owned())));
+        let json_body: Json<VideoBody> = Json(VideoBody { length_sec: 1, token: "token".to_owned() } );
+        assert_eq!(block_on(register_view(Storage::new(&MysqlStore), 1, 1,"order_id".to_owned(), json_body)), Err(bad_request_builder("no display with id 1 found".to_owned())));
     }
 
     #[test]
     pub fn register_view_when_nonexistent_order_unittest() {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
-        videos::register_video_view.mock_safe(|_, _, _, _| {MockResult::Return(Err(NoSuchOrder))});
+        videos::register_video_view.mock_safe(|_, _, _, _, _, _| {MockResult::Return(Err(NoSuchOrder))});
+
+        let json_body: Json<VideoBody> = Json(VideoBody { length_sec: 1, token: "token".to_owned() } );
+        assert_eq!(block_on(register_view(Storage::new(&MysqlStore), 1, 1,"order_id".to_owned(), json_body)), Err(bad_request_builder("no order with id order_id found".to_owned())));
+    }
+
+    #[test]
+    pub fn register_view_returns_charged_credits_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        videos::register_video_view.mock_safe(|_, display, video, order, length_sec, _| {MockResult::Return(Ok(PlayedVideo {
+            video, display, order: order.clone(), time_epoch: 0, length_sec, complete: false, credits: 7
+        }))});
+
+        let json_body: Json<VideoBody> = Json(VideoBody { length_sec: 10, token: "token".to_owned() } );
+        assert_eq!(block_on(register_view(Storage::new(&MysqlStore), 1, 1,"order_id".to_owned(), json_body)),
+            Ok(json!({"status": "success", "message": "video play logged", "credits": 7, "length_sec": 10, "complete": false})));
+    }
+
+    #[test]
+    pub fn register_view_when_order_does_not_pay_for_video_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        videos::register_video_view.mock_safe(|_, _, _, _, _, _| {MockResult::Return(Err(OrderNotForVideo))});
+
+        let json_body: Json<VideoBody> = Json(VideoBody { length_sec: 1, token: "token".to_owned() } );
+        assert_eq!(block_on(register_view(Storage::new(&MysqlStore), 1, 2,"order_id".to_owned(), json_body)), Err(bad_request_builder("order order_id does not pay for video 2".to_owned())));
+    }
+
+    #[test]
+    pub fn register_view_with_used_token_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        videos::register_video_view.mock_safe(|_, _, _, _, _, token| {
+            assert_eq!(token, "token");
+            MockResult::Return(Err(RedeemedPlayToken))
+        });
+
+        let json_body: Json<VideoBody> = Json(VideoBody { length_sec: 1, token: "token".to_owned() } );
+        assert_eq!(block_on(register_view(Storage::new(&MysqlStore), 1, 2,"order_id".to_owned(), json_body)), Err(bad_request_builder("the play token has already been used".to_owned())));
+    }
+
+    #[test]
+    pub fn register_view_with_invalid_length_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        videos::register_video_view.mock_safe(|_, _, _, _, _, _| {MockResult::Return(Err(InvalidPlayLength(15)))});
 
-        let json_body: Json<VideoBody> = Json(VideoBody { length_sec: 1} );
-        assert_eq!(register_view(1, 1,"order_id".to_owned(), json_body), Err(bad_request_builder("no order with id order_id found".to_owned())));
+        let json_body: Json<VideoBody> = Json(VideoBody { length_sec: 10000, token: "token".to_owned() } );
+        assert_eq!(block_on(register_view(Storage::new(&MysqlStore), 1, 2,"order_id".to_owned(), json_body)),
+            Err(bad_request_builder("length_sec 10000 is not a valid play length for video 2 of 15 seconds".to_owned())));
     }
 }
@@ -0,0 +1,151 @@
+/**
+ * Tests of the full http stack against the in-memory store, they need no database and run with
+ * `cargo test memorytest`
+ */
+use crate::config;
+use crate::model::{AdvertVideo, AdvertisementOrder, Order, Receiver, Tracker};
+use crate::persistance::cache;
+use crate::persistance::memory::MemoryStore;
+use crate::persistance::store::Store;
+use crate::services::occupancy::{Occupancy, SharedOccupancy};
+use crate::rocket;
+use super::{is_test_or_panic, view_body};
+
+use rocket::http::{Header, Status};
+use rocket::local::blocking::Client;
+use serde_json::Value;
+use std::sync::Arc;
+
+/**
+ * A client of an app with a location with a display, two receivers and a tracker interested in sport,
+ * and an order of 100 credits paying for a sport video
+ */
+fn memory_client() -> Client {
+    is_test_or_panic();
+    let store = MemoryStore::new();
+    let location = store.add_location("location1");
+    let interest = store.add_interest("sport");
+    store.insert_display(location).unwrap();
+    store.insert_receiver(&Receiver {id: "receiver1".to_owned(), location}).unwrap();
+    store.insert_receiver(&Receiver {id: "receiver2".to_owned(), location}).unwrap();
+    store.insert_tracker(&Tracker {id: "tracker1".to_owned(), location: None}).unwrap();
+    store.set_tracker_interest("tracker1", interest, 100.0);
+    store.add_video(AdvertVideo {interest, url: "interest1_video".to_owned(), length_sec: 120});
+    store.add_user("email@example.com", "agency1");
+    store.add_order(Order {id: "1".to_owned(), credits: 100, user: "email@example.com".to_owned()});
+    store.add_campaign(AdvertisementOrder {video: 1, order: "1".to_owned(), start_time_epoch: 0, end_time_epoch: 2147483647});
+    //Cached like the store of the app, the flows below check that the cache is invalidated
+    let store = cache::with_cache(Arc::new(store), config::get().cache_ttl_sec);
+    let occupancy = SharedOccupancy::new(Occupancy::default());
+    occupancy.rebuild(&*store).unwrap();
+    Client::tracked(rocket(store, occupancy)).unwrap()
+}
+
+fn body_json(body: Option<String>) -> Value {
+    serde_json::from_str(body.unwrap().as_str()).unwrap()
+}
+
+#[test]
+fn memorytest_tracker_registration_decides_the_video() {
+    let client = memory_client();
+    let response = client.get("/video/1").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(body_json(response.into_string())["video"], Value::Null, "no video should be shown without trackers at the location");
+
+    let response = client.post("/register/receiver1/tracker1").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.get("/trackers/tracker1").dispatch();
+    assert_eq!(body_json(response.into_string())["location"], 1);
+
+    let response = client.get("/video/1").dispatch();
+    let response_json = body_json(response.into_string());
+    assert_eq!(response_json["video"]["url"], "interest1_video");
+    assert_eq!(response_json["video"]["order"], "1");
+
+    let response = client.post("/unregister/receiver1/tracker1").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.get("/video/1").dispatch();
+    assert_eq!(body_json(response.into_string())["video"], Value::Null);
+
+    let response = client.get("/video/2").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn memorytest_audience_of_a_location() {
+    let client = memory_client();
+    client.post("/register/receiver1/tracker1").dispatch();
+    let response = client.get("/locations/1/audience").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response_json = body_json(response.into_string());
+    assert_eq!(response_json["trackers"], 1);
+    assert_eq!(response_json["interests"][0]["weight"], 100.0);
+    assert_eq!(response_json["receivers"][0], "receiver1");
+
+    let response = client.get("/locations/1/audience/history?from=0&to=3600").dispatch();
+    assert_eq!(body_json(response.into_string())["buckets"][0]["peak"], 0, "the tracker registered now should not be in the past");
+    let response = client.get("/locations/1/audience/history?from=10&to=0").dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(client.get("/locations/2/audience").dispatch().status(), Status::NotFound);
+}
+
+#[test]
+fn memorytest_tracker_stays_while_a_receiver_at_the_location_sees_it() {
+    let client = memory_client();
+    client.post("/register/receiver1/tracker1").dispatch();
+    client.post("/register/receiver2/tracker1").dispatch();
+    assert_eq!(client.post("/unregister/receiver1/tracker1").dispatch().status(), Status::Ok);
+    let response = client.get("/locations/1/audience").dispatch();
+    assert_eq!(body_json(response.into_string())["trackers"], 1, "receiver2 still sees the tracker");
+    assert_eq!(body_json(client.get("/video/1").dispatch().into_string())["video"]["url"], "interest1_video");
+
+    client.post("/unregister/receiver2/tracker1").dispatch();
+    let response = client.get("/locations/1/audience").dispatch();
+    assert_eq!(body_json(response.into_string())["trackers"], 0);
+    let response = client.get("/trackers/tracker1").dispatch();
+    assert_eq!(body_json(response.into_string())["location"], Value::Null);
+}
+
+#[test]
+fn memorytest_plays_are_charged_and_voided() {
+    let client = memory_client();
+    let body = view_body(100, 1, 1, "1");
+    let response = client.post("/views/1/1/1").body(body.clone()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.post("/views/1/1/1").body(body).dispatch();
+    assert_eq!(response.status(), Status::BadRequest, "a play token should only be redeemed once");
+
+    let response = client.get("/orders/1/statement").dispatch();
+    let response_json = body_json(response.into_string());
+    assert_eq!(response_json["balance"], 88);
+    assert_eq!(response_json["reconciled"], true);
+
+    let admin_token = Header::new("X-Admin-Token", config::get().admin_token.clone().unwrap());
+    let response = client.post("/admin/views/1/void").header(admin_token.clone()).body("{\"reason\": \"bogus\"}").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(body_json(response.into_string())["credits_refunded"], 12);
+    let response = client.post("/admin/views/1/void").header(admin_token).body("{}").dispatch();
+    assert_eq!(response.status(), Status::BadRequest, "a play should only be voided once");
+
+    let response = client.get("/orders/1/statement").dispatch();
+    let response_json = body_json(response.into_string());
+    assert_eq!(response_json["balance"], 100);
+    let movements: Vec<&str> = response_json["entries"].as_array().unwrap().iter().map(|e| e["type"].as_str().unwrap()).collect();
+    assert_eq!(movements, vec!["opening", "debit", "refund"]);
+}
+
+#[test]
+fn memorytest_logs_and_health() {
+    let client = memory_client();
+    let response = client.post("/logs").body("{\"message\": \"rebooted\", \"level\": \"warn\", \"device_id\": \"receiver1\"}").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.get("/logs?device=receiver1").dispatch();
+    let response_json = body_json(response.into_string());
+    assert_eq!(response_json["logs"][0]["message"], "rebooted");
+    assert_eq!(response_json["logs"][0]["severity"], "warn");
+    let response = client.post("/logs").body("x".repeat(64 * 1024)).dispatch();
+    assert_eq!(response.status(), Status::PayloadTooLarge, "a body above the string limit should not be logged truncated");
+
+    let response = client.get("/health/ready").dispatch();
+    assert_eq!(response.status(), Status::Ok, "the in-memory store is always ready");
+}
@@ -26,29 +26,76 @@
  */
 
 use crate::environment;
+use crate::config::{self, DatabaseBackend};
+use crate::persistance::{self, migrations};
+use crate::persistance::cache::CachedStore;
+use crate::persistance::store::Store;
+use crate::cli;
+use crate::services::{devices, ledger, play_tokens, webhooks};
+use crate::services::occupancy::{Occupancy, SharedOccupancy};
 use super::rocket;
 mod test_data;
+mod memory_tests;
 use lazy_static::lazy_static;
-use rocket::http::Status;
-use rocket::local::Client;
+use rocket::http::{Header, Status};
+use rocket::local::blocking::Client;
 use serde_json::Value;
-use std::sync::Mutex;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/**
+ * The connection the tests set up and check the database with, to the MySQL, PostgreSQL or SQLite database of the database url
+ */
+enum TestDb {
+    Mysql(mysql::Pool),
+    Postgres(postgres::Client),
+    Sqlite(rusqlite::Connection)
+}
+
 lazy_static! {
-    static ref CONN: Mutex<mysql::Pool> = Mutex::new(connect());
+    static ref CONN: Mutex<TestDb> = Mutex::new(connect());
+    static ref STORE: Arc<CachedStore> = open_store();
+    static ref OCCUPANCY: SharedOccupancy = SharedOccupancy::new(Occupancy::default());
 }
 
-fn connect() -> mysql::Pool {
+fn connect() -> TestDb {
     is_test_or_panic();
-    let environment = environment::db_environment_values();
-    //Dont connect to aspecific database, it may not exist yet
-    let url = format!(
-        "mysql://{}:{}@{}",
-        environment.user, environment.pass, environment.host
-    );
-    mysql::Pool::new_manual(1, 1, url).expect("error creating pool")
+    let database = &config::get().database;
+    match database.backend {
+        DatabaseBackend::Mysql => {
+            let opts = mysql::Opts::from_url(&database.url).expect("invalid database url");
+            //Dont connect to aspecific database, it may not exist yet
+            let mut builder = mysql::OptsBuilder::from_opts(opts);
+            builder.db_name(None::<String>);
+            TestDb::Mysql(mysql::Pool::new_manual(1, 1, builder).expect("error creating pool"))
+        },
+        DatabaseBackend::Postgres => TestDb::Postgres(postgres::Client::connect(&database.url, postgres::NoTls).expect("error connecting to database")),
+        DatabaseBackend::Sqlite => {
+            let path = database.url.splitn(2, "://").nth(1).unwrap_or("");
+            assert!(path != ":memory:", "the integration tests need a SQLite database file, shared by the tests and the app");
+            TestDb::Sqlite(rusqlite::Connection::open(path).expect("error opening database"))
+        }
+    }
+}
+
+/*The tests change the database behind the back of the store, and clear its cache and rebuild the occupancy when they do*/
+fn open_store() -> Arc<CachedStore> {
+    is_test_or_panic();
+    let store = persistance::open(&config::get().database).expect("error opening store");
+    Arc::new(CachedStore::new(store, Duration::from_secs(config::get().cache_ttl_sec)))
+}
+
+/**
+ * The store of the app under test
+ */
+fn store() -> &'static dyn Store {
+    &**STORE
 }
 
-fn guarded_client() -> rocket::local::Client {
+fn guarded_client() -> Client {
     let ok_env = environment::get_current_env() == environment::TEST_STRING;
     if !ok_env {
         colour::dark_red!("\n### TRYING TO RUN TESTS OUTSIDE TEST ENVIRONMENT ###\n\n");
@@ -56,7 +103,57 @@ fn guarded_client() -> rocket::local::Client {
 
     //Never let integration tests run outside test environment!
     is_test_or_panic();
-    Client::new(rocket()).unwrap()
+    Client::tracked(rocket(STORE.clone(), OCCUPANCY.clone())).unwrap()
+}
+
+/**
+ * A body registering a play, with a play token for the display, video and order as handed out by /video
+ */
+fn view_body(length_sec: i32, display_id: i32, video_id: i32, order_id: &str) -> String {
+    let token = play_tokens::issue_play_token(display_id, video_id, &order_id.to_owned());
+    format!("{{ \"length_sec\": {}, \"token\": \"{}\"}}", length_sec, token)
+}
+
+/**
+ * A local http server standing in for the webhook endpoint of an agency.
+ * Answers one request with each of the status codes, in order, then returns the requests received.
+ */
+fn webhook_stand_in(statuses: Vec<u16>) -> (String, thread::JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let handle = thread::spawn(move || {
+        statuses.into_iter().map(|status| {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = read_http_request(&mut stream);
+            write!(stream, "HTTP/1.1 {} Stand-in\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).unwrap();
+            request
+        }).collect()
+    });
+    (url, handle)
+}
+
+fn read_http_request(stream: &mut TcpStream) -> String {
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line.to_lowercase().starts_with("content-length:") {
+            content_length = line["content-length:".len()..].trim().parse().unwrap();
+        }
+        request.push_str(&line);
+        if line == "\r\n" || line.is_empty() {
+            break;
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).unwrap();
+    request + &String::from_utf8(body).unwrap()
+}
+
+fn now_epoch() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
 }
 
 fn is_test_or_panic() {
@@ -64,25 +161,72 @@ fn is_test_or_panic() {
     assert!(ok_env, "Environment was not set to TEST during test");
 }
 
-/*Reset the database to an empty state*/
+/*Reset the database to an empty state, with the schema of the migrations*/
 fn reset_db() {
-    CONN.lock()
-        .unwrap()
-        .get_conn()
-        .unwrap()
-        .query(format!(r#"{query}"#, query = test_data::CREATE_SQL_STMT))
-        .map(|_| ())
-        .expect("ERROR RESETTING DB")
+    match &mut *CONN.lock().unwrap() {
+        TestDb::Mysql(pool) => {
+            let mut conn = pool.get_conn().unwrap();
+            conn.query(test_data::CREATE_DATABASE_STMT)
+                .map(|_| ())
+                .expect("ERROR RESETTING DB");
+            migrations::migrate(&mut conn, now_epoch()).expect("ERROR MIGRATING DB");
+        },
+        TestDb::Postgres(client) => {
+            client.batch_execute(test_data::CREATE_POSTGRES_SCHEMA_STMT).expect("ERROR RESETTING DB");
+            store().migrate(now_epoch()).expect("ERROR MIGRATING DB");
+        },
+        TestDb::Sqlite(conn) => {
+            let tables: Vec<String> = conn.prepare("select name from sqlite_master where type = 'table' and name not like 'sqlite_%'")
+                .and_then(|mut stmt| stmt.query_map(rusqlite::NO_PARAMS, |row| row.get(0))?.collect())
+                .expect("ERROR RESETTING DB");
+            let drop_tables: String = tables.iter().map(|table| format!("DROP TABLE `{}`;", table)).collect();
+            conn.execute_batch(&format!("PRAGMA foreign_keys = OFF; {} PRAGMA foreign_keys = ON;", drop_tables)).expect("ERROR RESETTING DB");
+            store().migrate(now_epoch()).expect("ERROR MIGRATING DB");
+        }
+    }
+    STORE.clear();
+    OCCUPANCY.rebuild(store()).expect("ERROR REBUILDING OCCUPANCY");
+}
+
+/*Runs a statement, quoted with backticks like the MySQL schema*/
+fn query_db(query: &str) {
+    match &mut *CONN.lock().unwrap() {
+        TestDb::Mysql(pool) => pool.get_conn().unwrap().query(query).map(|_| ()).expect(query),
+        TestDb::Postgres(client) => client.batch_execute(&query.replace('`', "\"")).expect(query),
+        TestDb::Sqlite(conn) => conn.execute_batch(query).expect(query)
+    }
+    STORE.clear();
+    OCCUPANCY.rebuild(store()).expect("ERROR REBUILDING OCCUPANCY");
+}
+
+/*The rows of a query, with the values as text*/
+fn query_rows(query: &str) -> Vec<Vec<Option<String>>> {
+    match &mut *CONN.lock().unwrap() {
+        TestDb::Mysql(pool) => pool.get_conn().unwrap().query(query).expect(query)
+            .map(|row| row.unwrap().unwrap().into_iter().map(mysql::from_value::<Option<String>>).collect())
+            .collect(),
+        TestDb::Postgres(client) => client.simple_query(&query.replace('`', "\"")).expect(query).iter()
+            .filter_map(|message| match message {
+                postgres::SimpleQueryMessage::Row(row) => Some((0..row.len()).map(|i| row.get(i).map(|val| val.to_owned())).collect()),
+                _ => None
+            })
+            .collect(),
+        TestDb::Sqlite(conn) => conn.prepare(query).and_then(|mut stmt| {
+            let columns = stmt.column_count();
+            stmt.query_map(rusqlite::NO_PARAMS, |row| (0..columns).map(|i| row.get::<_, rusqlite::types::Value>(i).map(|val| match val {
+                rusqlite::types::Value::Null => None,
+                rusqlite::types::Value::Integer(val) => Some(val.to_string()),
+                rusqlite::types::Value::Real(val) => Some(val.to_string()),
+                rusqlite::types::Value::Text(val) => Some(val),
+                rusqlite::types::Value::Blob(val) => Some(String::from_utf8_lossy(&val).into_owned())
+            })).collect())?.collect()
+        }).expect(query)
+    }
 }
 
-fn query_db(query: &'static str) {
-    CONN.lock()
-        .unwrap()
-        .get_conn()
-        .unwrap()
-        .query(query)
-        .map(|_| ())
-        .expect(query);
+/*The first value of a query, parsed*/
+fn query_value<T: std::str::FromStr>(query: &str) -> T where T::Err: std::fmt::Debug {
+    query_rows(query)[0][0].as_ref().expect(query).parse().unwrap()
 }
 
 
@@ -93,9 +237,9 @@ fn query_db(query: &'static str) {
 #[test]
 fn integrationtest_rocket_has_launched() {
     let client = guarded_client();
-    let mut response = client.get("/").dispatch();
+    let response = client.get("/").dispatch();
     assert_eq!(response.status(), Status::Ok);
-    assert_eq!(response.body_string(), Some("IoT server v1.0.0".into()));
+    assert_eq!(response.into_string(), Some("IoT server v1.0.0".into()));
 }
 
 #[test]
@@ -111,10 +255,10 @@ fn integrationtest_get_untracked_tracker_info() {
     reset_db();
     query_db("insert into rfid_tracker (id) values(1)");
     let client = guarded_client();
-    let mut response = client.get("/trackers/1").dispatch();
+    let response = client.get("/trackers/1").dispatch();
     assert_eq!(response.status(), Status::from_code(200).unwrap());
     let response_json: Value =
-        serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
     assert_eq!(
         response_json["id"],
         String::from("1"),
@@ -145,11 +289,11 @@ fn integrationtest_register_and_get_tracker() {
     query_db("insert into rfid_receiver (id, location) values(100, 2);");
     let client = guarded_client();
 
-    let mut response = client.post("/register/1/1").dispatch();
+    let response = client.post("/register/1/1").dispatch();
     assert_eq!(response.status(), Status::from_code(200).unwrap());
 
     let response_json: Value =
-        serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
     assert_eq!(
         response_json["status"], "registered",
         "Correct status on register"
@@ -161,10 +305,10 @@ fn integrationtest_register_and_get_tracker() {
     );
 
     client.post("/register/100/1").dispatch();
-    let mut response = client.get("/trackers/1").dispatch();
-    let response_json: Value =
-        serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
+    let response = client.get("/trackers/1").dispatch();
     assert_eq!(response.status(), Status::from_code(200).unwrap());
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
 
     assert_eq!(
         response_json["id"],
@@ -197,12 +341,12 @@ fn integrationtest_unregister_and_get_tracker() {
 
     client.post("/register/1/1").dispatch();
     client.post("/register/100/1").dispatch();
-    let mut response = client.post("/unregister/100/1").dispatch();
+    let response = client.post("/unregister/100/1").dispatch();
 
     assert_eq!(response.status(), Status::from_code(200).unwrap());
 
     let response_json: Value =
-        serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
     assert_eq!(
         response_json["status"], "unregistered",
         "Correct mesg on unregister"
@@ -213,10 +357,10 @@ fn integrationtest_unregister_and_get_tracker() {
         "Correct location on get registered tracker"
     );
 
-    let mut response = client.get("/trackers/1").dispatch();
-    let response_json: Value =
-        serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
+    let response = client.get("/trackers/1").dispatch();
     assert_eq!(response.status(), Status::from_code(200).unwrap());
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
 
     assert_eq!(
         response_json["id"],
@@ -247,10 +391,10 @@ fn integrationtest_get_video_for_display_at_empty_location() {
     query_db("insert into display (location) values(1);");
     let client = guarded_client();
 
-    let mut response = client.get("/video/1").dispatch();
+    let response = client.get("/video/1").dispatch();
     assert_eq!(response.status(), Status::from_code(200).unwrap());
     let response_json: Value =
-        serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
     assert_eq!(
         response_json["message"], "no trackers registered to location",
         "Wrong message on video response"
@@ -281,21 +425,21 @@ fn integrationtest_get_video_for_with_trackers_when_trackers_move_around() {
     query_db("insert into tracker_interest (tracker, interest, weight) values('tracker2', 2, 90);");
     query_db("insert into advertisement_video (url, length_sec, interest) values('https://www.youtube.com/watch?v=oHg5SJYRHA0', 120, 1);");
     query_db("insert into advertisement_video (url, length_sec, interest) values('interest2_video', 10, 2);");
-    query_db("insert into agency (orgnr, name) values(1, \"agency1\");");
-    query_db("insert into users (username, email, pass_hash, agency) values(\"user1\", \"email@example.com\", \"HASH\",1);");
-    query_db("insert into orders (id, credits, user) values(\"1\", 100, \"email@example.com\");");
-    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '1',0, 1);");
-    query_db("insert into orders (id, credits, user) values(\"2\", 20, \"email@example.com\");");
-    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(2, '2',0, 1);");
+    query_db("insert into agency (orgnr, name) values(1, 'agency1');");
+    query_db("insert into users (username, email, pass_hash, agency) values('user1', 'email@example.com', 'HASH',1);");
+    query_db("insert into orders values('1', 100, 'email@example.com');");
+    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '1',0, 2147483647);");
+    query_db("insert into orders values('2', 20, 'email@example.com');");
+    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(2, '2',0, 2147483647);");
 
     let client = guarded_client();
     client.post("/register/receiver1/tracker1").dispatch();
     client.post("/register/receiver1/tracker2").dispatch();
 
-    let mut response = client.get("/video/1").dispatch();
+    let response = client.get("/video/1").dispatch();
     assert_eq!(response.status(), Status::from_code(200).unwrap());
     let response_json: Value =
-        serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
     assert_eq!(
         response_json["video"]["url"],
         String::from("https://www.youtube.com/watch?v=oHg5SJYRHA0")
@@ -306,9 +450,9 @@ fn integrationtest_get_video_for_with_trackers_when_trackers_move_around() {
         Value::String(String::from("video found"))
     );
     client.post("/register/receiver2/tracker1").dispatch();
-    let mut response = client.get("/video/1").dispatch();
+    let response = client.get("/video/1").dispatch();
     let response_json: Value =
-        serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
     assert_eq!(
         response_json["video"]["url"],
         String::from("interest2_video")
@@ -337,21 +481,21 @@ fn integrationtest_when_getting_videos_orders_with_no_credit_are_not_given() {
     query_db("insert into tracker_interest (tracker, interest, weight) values('2', 2, 90);");
     query_db("insert into advertisement_video (url, length_sec, interest) values('https://www.youtube.com/watch?v=oHg5SJYRHA0', 120, 1);");
     query_db("insert into advertisement_video (url, length_sec, interest) values('interest2_video', 10, 2);");
-    query_db("insert into agency (orgnr, name) values(1, \"agency1\");");
-    query_db("insert into users (username, email, pass_hash, agency) values(\"user1\",  \"email@example.com\", \"HASH\",1);");
-    query_db("insert into orders (id, credits, user) values(\"1\", 0, \"email@example.com\");");
-    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, 1,0, 1);");
-    query_db("insert into orders (id, credits, user) values(\"2\", 20, \"email@example.com\");");
-    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(2, 2,0, 1);");
+    query_db("insert into agency (orgnr, name) values(1, 'agency1');");
+    query_db("insert into users (username, email, pass_hash, agency) values('user1',  'email@example.com', 'HASH',1);");
+    query_db("insert into orders values('1', 0, 'email@example.com');");
+    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, 1,0, 2147483647);");
+    query_db("insert into orders values('2', 20, 'email@example.com');");
+    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(2, 2,0, 2147483647);");
 
     let client = guarded_client();
     client.post("/register/1/1").dispatch();
     client.post("/register/1/2").dispatch();
 
-    let mut response = client.get("/video/1").dispatch();
+    let response = client.get("/video/1").dispatch();
     assert_eq!(response.status(), Status::from_code(200).unwrap());
     let response_json: Value =
-        serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
     assert_eq!(
         response_json["video"]["url"],
         String::from("interest2_video")
@@ -363,6 +507,35 @@ fn integrationtest_when_getting_videos_orders_with_no_credit_are_not_given() {
     );
 }
 
+#[test]
+fn integrationtest_when_getting_videos_orders_ahead_of_pacing_are_not_given() {
+    reset_db();
+    query_db("insert into location (name) values('location1');");
+    query_db("insert into display (location) values(1);");
+    query_db("insert into interest (name) values('sport');");
+    query_db("insert into rfid_tracker (id, location) values(1, 1);");
+    query_db("insert into tracker_interest (tracker, interest, weight) values(1, 1, 100);");
+    query_db("insert into advertisement_video (url, length_sec, interest) values('overspent_video', 30, 1);");
+    query_db("insert into advertisement_video (url, length_sec, interest) values('paced_video', 30, 1);");
+    query_db("insert into agency (orgnr, name) values(1, 'agency1');");
+    query_db("insert into users (username, email, pass_hash, agency) values('user1',  'email@example.com', 'HASH',1);");
+    query_db("insert into orders values('1', 10, 'email@example.com');");
+    query_db("insert into orders values('2', 100, 'email@example.com');");
+    //The campaigns are 80% through, order 1 has spent 90% of its budget
+    let now = now_epoch();
+    query_db(&format!("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '1', {}, {});", now - 80, now + 20));
+    query_db(&format!("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(2, '2', {}, {});", now - 80, now + 20));
+    query_db("insert into played_video (video, display, time_epoch, `order`, length_sec, complete, credits) values(1, 1, 0, '1', 30, true, 90);");
+
+    let client = guarded_client();
+    for _ in 0..10 {
+        let response = client.get("/video/1").dispatch();
+        let response_json: Value =
+            serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+        assert_eq!(response_json["video"]["url"], String::from("paced_video"));
+    }
+}
+
 #[test]
 fn integrationtest_when_video_played_order_credits_are_withdrawn() {
     reset_db();
@@ -371,7 +544,7 @@ fn integrationtest_when_video_played_order_credits_are_withdrawn() {
     query_db("insert into display (location) values(1);");
     query_db("insert into interest (name) values('sport');");
     query_db("insert into interest (name) values('movies');");
-    query_db("insert into rfid_tracker (id) values(1);");
+    query_db("insert into rfid_tracker (id, location) values(1, 1);");
     query_db("insert into rfid_tracker (id) values(2);");
     query_db("insert into rfid_receiver (id, location) values(1, 1);");
     query_db("insert into rfid_receiver (id, location) values(2, 2);");
@@ -380,44 +553,353 @@ fn integrationtest_when_video_played_order_credits_are_withdrawn() {
     query_db("insert into tracker_interest (tracker, interest, weight) values(2, 2, 90);");
     query_db("insert into advertisement_video (url, length_sec, interest) values('https://www.youtube.com/watch?v=oHg5SJYRHA0', 120, 1);");
     query_db("insert into advertisement_video (url, length_sec, interest) values('interest2_video', 10, 2);");
-    query_db("insert into agency (orgnr, name) values(1, \"agency1\");");
-    query_db("insert into users (username, email, pass_hash, agency) values(\"user1\",  \"email@example.com\", \"HASH\",1);");
-    query_db("insert into orders (id, credits, user) values(\"1\", 100, \"email@example.com\");");
-    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '1',0, 1);");
+    query_db("insert into agency (orgnr, name) values(1, 'agency1');");
+    query_db("insert into users (username, email, pass_hash, agency) values('user1',  'email@example.com', 'HASH',1);");
+    query_db("insert into orders values('1', 100, 'email@example.com');");
+    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '1',0, 2147483647);");
 
     let client = guarded_client();
-    let mut response = client
+    let response = client
         .post("/views/1/1/1")
-        .body("{ \"length_sec\": 100}")
+        .body(view_body(100, 1, 1, "1"))
         .dispatch();
-    let response_json: Value =
-        serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
     assert_eq!(response.status(), Status::from_code(200).unwrap());
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
     assert_eq!(response_json["status"], String::from("success"));
     assert_eq!(response_json["message"], String::from("video play logged"));
+    assert_eq!(response_json["credits"], 12);
 
-    let sql_res: i32 = CONN
-        .lock()
-        .unwrap()
-        .get_conn()
-        .unwrap()
-        .first("select credits from orders where id = '1'")
-        .unwrap()
-        .unwrap();
+    let sql_res: i32 = query_value("select credits from orders where id = '1'");
     assert_eq!(sql_res, 88);
 }
 
+#[test]
+fn integrationtest_when_video_played_rate_card_decides_charge() {
+    reset_db();
+    query_db("insert into location (name) values('location1');");
+    query_db("insert into location (name) values('location2');");
+    query_db("insert into display (location) values(1);");
+    query_db("insert into display (location) values(2);");
+    query_db("insert into interest (name) values('sport');");
+    query_db("insert into rfid_tracker (id, location) values(1, 1);");
+    query_db("insert into rfid_tracker (id, location) values(2, 2);");
+    query_db("insert into tracker_interest (tracker, interest, weight) values(1, 1, 100);");
+    query_db("insert into tracker_interest (tracker, interest, weight) values(2, 1, 100);");
+    query_db("insert into advertisement_video (url, length_sec, interest) values('interest1_video', 120, 1);");
+    query_db("insert into agency (orgnr, name) values(1, 'agency1');");
+    query_db("insert into users (username, email, pass_hash, agency) values('user1',  'email@example.com', 'HASH',1);");
+    query_db("insert into orders values('1', 100, 'email@example.com');");
+    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '1',0, 2147483647);");
+    query_db("insert into rate_card (location, start_minute, end_minute, credits_per_second, min_credits, rounding) values(null, 0, 1440, 2, 1, 'down');");
+    query_db("insert into rate_card (location, start_minute, end_minute, credits_per_second, min_credits, rounding) values(1, 0, 1440, 0.3, 3, 'up');");
+
+    let client = guarded_client();
+    let response = client
+        .post("/views/1/1/1")
+        .body(view_body(15, 1, 1, "1"))
+        .dispatch();
+    assert_eq!(response.status(), Status::from_code(200).unwrap());
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["credits"], 5);
+
+    let response = client
+        .post("/views/1/1/1")
+        .body(view_body(1, 1, 1, "1"))
+        .dispatch();
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["credits"], 3);
+
+    let response = client
+        .post("/views/2/1/1")
+        .body(view_body(10, 2, 1, "1"))
+        .dispatch();
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["credits"], 20);
+
+    let order_credits: i32 = query_value("select credits from orders where id = '1'");
+    assert_eq!(order_credits, 72);
+    let played_credits: i32 = query_value("select sum(credits) from played_video");
+    assert_eq!(played_credits, 28);
+}
+
+#[test]
+fn integrationtest_credit_movements_are_recorded_in_order_statement() {
+    reset_db();
+    query_db("insert into location (name) values('location1');");
+    query_db("insert into display (location) values(1);");
+    query_db("insert into interest (name) values('sport');");
+    query_db("insert into rfid_tracker (id, location) values(1, 1);");
+    query_db("insert into tracker_interest (tracker, interest, weight) values(1, 1, 100);");
+    query_db("insert into advertisement_video (url, length_sec, interest) values('interest1_video', 120, 1);");
+    query_db("insert into agency (orgnr, name) values(1, 'agency1');");
+    query_db("insert into users (username, email, pass_hash, agency) values('user1',  'email@example.com', 'HASH',1);");
+    query_db("insert into orders values('1', 100, 'email@example.com');");
+    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '1',0, 2147483647);");
+
+    let client = guarded_client();
+    let response = client.get("/orders/2/statement").dispatch();
+    assert_eq!(response.status(), Status::from_code(404).unwrap());
+
+    let response = client.get("/orders/1/statement").dispatch();
+    let response_json: Value = serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["balance"], 100);
+    assert_eq!(response_json["reconciled"], true);
+    assert_eq!(response_json["entries"].as_array().unwrap().len(), 0);
+
+    let response = client.post("/views/1/1/1").body(view_body(100, 1, 1, "1")).dispatch();
+    assert_eq!(response.status(), Status::from_code(200).unwrap());
+    //A top up made directly in the database, outside of the ledger
+    query_db("update orders set credits = credits + 50 where id = '1';");
+
+    let response = client.get("/orders/1/statement").dispatch();
+    let response_json: Value = serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["balance"], 138);
+    assert_eq!(response_json["ledger_balance"], 88);
+    assert_eq!(response_json["reconciled"], false);
+
+    let response = client.post("/views/1/1/1").body(view_body(8, 1, 1, "1")).dispatch();
+    assert_eq!(response.status(), Status::from_code(200).unwrap());
+
+    let response = client.get("/orders/1/statement").dispatch();
+    assert_eq!(response.status(), Status::from_code(200).unwrap());
+    let response_json: Value = serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["balance"], 137);
+    assert_eq!(response_json["ledger_balance"], 137);
+    assert_eq!(response_json["reconciled"], true);
+    let entries = response_json["entries"].as_array().unwrap();
+    let movements: Vec<(&str, i64, i64)> = entries.iter()
+        .map(|e| (e["type"].as_str().unwrap(), e["amount"].as_i64().unwrap(), e["balance_after"].as_i64().unwrap()))
+        .collect();
+    assert_eq!(movements, vec![("opening", 100, 100), ("debit", -12, 88), ("adjustment", 50, 138), ("debit", -1, 137)]);
+    assert_eq!(entries[1]["view"], 1);
+    assert_eq!(entries[3]["view"], 2);
+    assert_eq!(entries[1]["rate_card"], Value::Null);
+    assert_eq!(entries[1]["credits_per_second"], 0.125);
+    assert_eq!(entries[0]["view"], Value::Null);
+}
+
+#[test]
+fn integrationtest_admins_void_plays_and_credits_are_refunded() {
+    reset_db();
+    query_db("insert into location (name) values('location1');");
+    query_db("insert into display (location) values(1);");
+    query_db("insert into display (location) values(1);");
+    query_db("insert into interest (name) values('sport');");
+    query_db("insert into rfid_tracker (id, location) values(1, 1);");
+    query_db("insert into tracker_interest (tracker, interest, weight) values(1, 1, 100);");
+    query_db("insert into advertisement_video (url, length_sec, interest) values('interest1_video', 120, 1);");
+    query_db("insert into agency (orgnr, name) values(1, 'agency1');");
+    query_db("insert into users (username, email, pass_hash, agency) values('user1',  'email@example.com', 'HASH',1);");
+    query_db("insert into orders values('1', 100, 'email@example.com');");
+    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '1',0, 2147483647);");
+
+    let client = guarded_client();
+    for (display, length_sec) in vec![(1, 100), (1, 16), (1, 24), (2, 8)] {
+        let response = client.post(format!("/views/{}/1/1", display)).body(view_body(length_sec, display, 1, "1")).dispatch();
+        assert_eq!(response.status(), Status::from_code(200).unwrap());
+    }
+    let admin_token = Header::new("X-Admin-Token", config::get().admin_token.clone().unwrap());
+
+    let response = client.post("/admin/views/1/void").body("{\"reason\": \"bogus\"}").dispatch();
+    assert_eq!(response.status(), Status::from_code(401).unwrap());
+    let response = client.post("/admin/views/1/void").header(Header::new("X-Admin-Token", "wrong")).body("{}").dispatch();
+    assert_eq!(response.status(), Status::from_code(401).unwrap());
+    let response = client.post("/admin/views/9/void").header(admin_token.clone()).body("{}").dispatch();
+    assert_eq!(response.status(), Status::from_code(404).unwrap());
+
+    let response = client.post("/admin/views/1/void").header(admin_token.clone()).body("{\"reason\": \"bogus\"}").dispatch();
+    assert_eq!(response.status(), Status::from_code(200).unwrap());
+    let response_json: Value = serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["credits_refunded"], 12);
+    let response = client.post("/admin/views/1/void").header(admin_token.clone()).body("{}").dispatch();
+    assert_eq!(response.status(), Status::from_code(400).unwrap(), "a play should only be voided once");
+
+    let now = now_epoch();
+    let response = client.post("/admin/displays/3/void").header(admin_token.clone())
+        .body(format!("{{\"from\": 0, \"to\": {}}}", now)).dispatch();
+    assert_eq!(response.status(), Status::from_code(404).unwrap());
+    let response = client.post("/admin/displays/1/void").header(admin_token.clone())
+        .body(format!("{{\"from\": 0, \"to\": {}, \"reason\": \"display malfunction\"}}", now)).dispatch();
+    assert_eq!(response.status(), Status::from_code(200).unwrap());
+    let response_json: Value = serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    let voided: Vec<i64> = response_json["voided"].as_array().unwrap().iter().map(|v| v["view"].as_i64().unwrap()).collect();
+    assert_eq!(voided, vec![2, 3], "plays already voided and plays of other displays should be left out");
+    assert_eq!(response_json["credits_refunded"], 5);
+
+    let order_credits: i32 = query_value("select credits from orders where id = '1'");
+    assert_eq!(order_credits, 99);
+    let played: i32 = query_value("select count(*) from played_video");
+    let voided: i32 = query_value("select count(*) from played_video where voided");
+    assert_eq!((played, voided), (4, 3), "voided plays should be kept");
+    let reason: String = query_value("select void_reason from played_video where id = 2");
+    assert_eq!(reason, "display malfunction");
+
+    let response = client.get("/orders/1/statement").dispatch();
+    let response_json: Value = serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["reconciled"], true);
+    let refunds: Vec<(i64, i64)> = response_json["entries"].as_array().unwrap().iter()
+        .filter(|e| e["type"] == "refund")
+        .map(|e| (e["view"].as_i64().unwrap(), e["amount"].as_i64().unwrap()))
+        .collect();
+    assert_eq!(refunds, vec![(1, 12), (2, 2), (3, 3)]);
+}
+
+#[test]
+fn integrationtest_play_length_is_validated_against_video_length() {
+    reset_db();
+    query_db("insert into location (name) values('location1');");
+    query_db("insert into display (location) values(1);");
+    query_db("insert into interest (name) values('sport');");
+    query_db("insert into rfid_tracker (id, location) values(1, 1);");
+    query_db("insert into tracker_interest (tracker, interest, weight) values(1, 1, 100);");
+    query_db("insert into advertisement_video (url, length_sec, interest) values('interest1_video', 16, 1);");
+    query_db("insert into agency (orgnr, name) values(1, 'agency1');");
+    query_db("insert into users (username, email, pass_hash, agency) values('user1',  'email@example.com', 'HASH',1);");
+    query_db("insert into orders values('1', 100, 'email@example.com');");
+    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '1',0, 2147483647);");
+
+    let client = guarded_client();
+    let response = client
+        .post("/views/1/1/1")
+        .body(view_body(10000, 1, 1, "1"))
+        .dispatch();
+    assert_eq!(response.status(), Status::from_code(400).unwrap());
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["message"], String::from("length_sec 10000 is not a valid play length for video 1 of 16 seconds"));
+
+    let response = client
+        .post("/views/1/1/1")
+        .body(view_body(17, 1, 1, "1"))
+        .dispatch();
+    assert_eq!(response.status(), Status::from_code(200).unwrap());
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["length_sec"], 16);
+    assert_eq!(response_json["complete"], true);
+    assert_eq!(response_json["credits"], 2);
+
+    let response = client
+        .post("/views/1/1/1")
+        .body(view_body(8, 1, 1, "1"))
+        .dispatch();
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["complete"], false);
+    assert_eq!(response_json["credits"], 1);
+
+    let plays: Vec<Vec<Option<String>>> =
+        query_rows("select display, length_sec, case when complete then 'complete' else 'partial' end from played_video order by id");
+    let plays: Vec<Vec<&str>> = plays.iter().map(|row| row.iter().map(|val| val.as_ref().unwrap().as_str()).collect()).collect();
+    assert_eq!(plays, vec![vec!["1", "16", "complete"], vec!["1", "8", "partial"]]);
+    let order_credits: i32 = query_value("select credits from orders where id = '1'");
+    assert_eq!(order_credits, 97);
+}
+
+#[test]
+fn integrationtest_views_must_match_order_and_campaign() {
+    reset_db();
+    query_db("insert into location (name) values('location1');");
+    query_db("insert into location (name) values('location2');");
+    query_db("insert into display (location) values(1);");
+    query_db("insert into display (location) values(2);");
+    query_db("insert into interest (name) values('sport');");
+    query_db("insert into rfid_tracker (id, location) values(1, 1);");
+    query_db("insert into tracker_interest (tracker, interest, weight) values(1, 1, 100);");
+    query_db("insert into advertisement_video (url, length_sec, interest) values('interest1_video', 30, 1);");
+    query_db("insert into advertisement_video (url, length_sec, interest) values('other_video', 30, 1);");
+    query_db("insert into agency (orgnr, name) values(1, 'agency1');");
+    query_db("insert into users (username, email, pass_hash, agency) values('user1',  'email@example.com', 'HASH',1);");
+    query_db("insert into orders values('1', 100, 'email@example.com');");
+    query_db("insert into orders values('2', 100, 'email@example.com');");
+    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '1',0, 2147483647);");
+    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '2',0, 1);");
+
+    let client = guarded_client();
+    let cases = vec![
+        (1, 2, "1", "order 1 does not pay for video 2"),
+        (1, 1, "2", "the campaign of order 2 for video 1 is not running"),
+    ];
+    for (display, video, order, message) in cases {
+        let response = client
+            .post(format!("/views/{}/{}/{}", display, video, order))
+            .body(view_body(10, display, video, order))
+            .dispatch();
+        assert_eq!(response.status(), Status::from_code(400).unwrap());
+        let response_json: Value =
+            serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+        assert_eq!(response_json["message"], String::from(message));
+    }
+
+    let response = client.post("/views/1/1/1").body(view_body(10, 1, 1, "1")).dispatch();
+    assert_eq!(response.status(), Status::from_code(200).unwrap());
+    //The play token proves the video was served to the display, whoever is at its location when the play is reported
+    let response = client.post("/views/2/1/1").body(view_body(10, 2, 1, "1")).dispatch();
+    assert_eq!(response.status(), Status::from_code(200).unwrap());
+    let sql_res: i32 = query_value("select count(*) from played_video");
+    assert_eq!(sql_res, 2);
+}
+
+#[test]
+fn integrationtest_views_are_proven_with_play_tokens_from_get_video() {
+    reset_db();
+    query_db("insert into location (name) values('location1');");
+    query_db("insert into display (location) values(1);");
+    query_db("insert into interest (name) values('sport');");
+    query_db("insert into rfid_tracker (id, location) values(1, 1);");
+    query_db("insert into tracker_interest (tracker, interest, weight) values(1, 1, 100);");
+    query_db("insert into advertisement_video (url, length_sec, interest) values('interest1_video', 30, 1);");
+    query_db("insert into agency (orgnr, name) values(1, 'agency1');");
+    query_db("insert into users (username, email, pass_hash, agency) values('user1',  'email@example.com', 'HASH',1);");
+    query_db("insert into orders values('1', 100, 'email@example.com');");
+    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '1',0, 2147483647);");
+
+    let client = guarded_client();
+    let response = client.get("/video/1").dispatch();
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    let token = response_json["video"]["token"].as_str().unwrap().to_owned();
+    let body = format!("{{ \"length_sec\": 30, \"token\": \"{}\"}}", token);
+
+    let response = client.post("/views/1/1/1").body(&body).dispatch();
+    assert_eq!(response.status(), Status::from_code(200).unwrap());
+
+    let response = client.post("/views/1/1/1").body(&body).dispatch();
+    assert_eq!(response.status(), Status::from_code(400).unwrap());
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["message"], String::from("the play token has already been used"));
+
+    let response = client
+        .post("/views/1/1/1")
+        .body(format!("{{ \"length_sec\": 30, \"token\": \"{}x\"}}", token))
+        .dispatch();
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["message"], String::from("the play token is not valid for display 1, video 1 and order 1"));
+
+    let response = client.post("/views/1/1/1").body("{ \"length_sec\": 30}").dispatch();
+    assert_eq!(response.status(), Status::from_code(422).unwrap(), "a body without a token should not be processed");
+
+    let sql_res: i32 = query_value("select credits from orders where id = '1'");
+    assert_eq!(sql_res, 97);
+}
+
 #[test]
 fn integrationtest_correct_response_on_malformed_body() {
     reset_db();
     let client = guarded_client();
-    let mut response = client
+    let response = client
         .post("/views/1/1/1")
         .body("{ length_sec: 100}")
         .dispatch();
-    let response_json: Value =
-        serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
     assert_eq!(response.status(), Status::from_code(400).unwrap());
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
     assert_eq!(response_json["status"], String::from("error"));
     assert_eq!(
         response_json["message"],
@@ -429,13 +911,13 @@ fn integrationtest_correct_response_on_malformed_body() {
 fn integrationtest_correct_error_on_register_nonexistent_video() {
     reset_db();
     let client = guarded_client();
-    let mut response = client
+    let response = client
         .post("/views/1/1/1")
-        .body("{ \"length_sec\": 10}")
+        .body("{ \"length_sec\": 10, \"token\": \"token\"}")
         .dispatch();
-    let response_json: Value =
-        serde_json::from_str(response.body_string().unwrap().as_str()).unwrap();
     assert_eq!(response.status(), Status::from_code(400).unwrap());
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
     assert_eq!(response_json["status"], String::from("error"));
     assert_eq!(
         response_json["message"],
@@ -461,3 +943,385 @@ fn integrationtest_get_with_invalid_input_should_not_sql_error() {
     let response = client.post("/trackers/1/%5C%22%27%60r%60%27").dispatch();
     assert_eq!(response.status(), Status::from_code(404).unwrap());
 }
+
+#[test]
+fn integrationtest_logged_messages_are_persisted_and_queryable() {
+    reset_db();
+    let client = guarded_client();
+
+    let response = client
+        .post("/logs")
+        .body("{ \"error\": true, \"message\": \"sensor failure\", \"device\": \"receiver1\", \"fields\": {\"code\": 17}}")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["message"], String::from("error logged"));
+
+    client
+        .post("/logs")
+        .body("{ \"error\": false, \"message\": \"booted\", \"device\": \"display1\"}")
+        .dispatch();
+    client.post("/logs").body("plain text message").dispatch();
+
+    let response = client.get("/logs").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["logs"].as_array().unwrap().len(), 3, "All messages should be persisted");
+
+    let response = client.get("/logs?device=receiver1&severity=error").dispatch();
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    let logs = response_json["logs"].as_array().unwrap();
+    assert_eq!(logs.len(), 1, "Only the error from receiver1 should match");
+    assert_eq!(logs[0]["message"], String::from("sensor failure"));
+    assert_eq!(logs[0]["severity"], String::from("error"));
+    assert_eq!(logs[0]["fields"]["code"], 17);
+
+    let response = client.get("/logs?from=0&to=1").dispatch();
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["logs"].as_array().unwrap().len(), 0, "No messages were logged in 1970");
+}
+
+#[test]
+fn integrationtest_query_logs_with_unknown_severity() {
+    reset_db();
+    let client = guarded_client();
+    let response = client.get("/logs?severity=loud").dispatch();
+    assert_eq!(response.status(), Status::from_code(400).unwrap());
+}
+
+#[test]
+fn integrationtest_log_batches_with_levels_and_context() {
+    reset_db();
+    let client = guarded_client();
+
+    let response = client
+        .post("/logs")
+        .body("[{ \"level\": \"debug\", \"message\": \"scan started\", \"device_id\": \"receiver1\", \"firmware\": \"1.4.2\"},
+                { \"level\": \"warn\", \"message\": \"weak signal\", \"device_id\": \"receiver1\", \"context\": {\"rssi\": -91}},
+                { \"level\": \"fatal\", \"message\": \"antenna lost\", \"device_id\": \"receiver1\"}]")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["message"], String::from("3 messages logged"));
+
+    let response = client.get("/logs?device=receiver1&min_severity=warn").dispatch();
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    let logs = response_json["logs"].as_array().unwrap();
+    assert_eq!(logs.len(), 2, "Only warn and fatal entries should match");
+    assert!(logs.iter().any(|l| l["severity"] == "warn" && l["fields"]["rssi"] == -91));
+    assert!(logs.iter().any(|l| l["severity"] == "fatal"));
+
+    let response = client.get("/logs?severity=debug").dispatch();
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["logs"][0]["firmware"], String::from("1.4.2"));
+}
+
+#[test]
+fn integrationtest_log_with_unknown_level() {
+    reset_db();
+    let client = guarded_client();
+    let response = client
+        .post("/logs")
+        .body("{ \"level\": \"loud\", \"message\": \"hello\"}")
+        .dispatch();
+    assert_eq!(response.status(), Status::from_code(400).unwrap());
+}
+
+#[test]
+fn integrationtest_heartbeats_and_stale_devices() {
+    reset_db();
+    query_db("insert into location (name) values('location1');");
+    query_db("insert into display (location) values(1);");
+    query_db("insert into display (location) values(1);");
+    query_db("insert into rfid_receiver (id, location) values('receiver1', 1);");
+    query_db("insert into rfid_receiver (id, location) values('receiver2', 1);");
+    let client = guarded_client();
+
+    let response = client
+        .post("/heartbeat/receivers/receiver1")
+        .body("{ \"uptime_sec\": 3600, \"firmware\": \"1.0.3\", \"free_memory\": 20480}")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.post("/heartbeat/displays/1").body("{}").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    client.get("/video/2").dispatch();
+
+    let response = client.post("/heartbeat/receivers/receiver3").body("{}").dispatch();
+    assert_eq!(response.status(), Status::from_code(404).unwrap());
+    let response = client.post("/heartbeat/displays/3").body("{}").dispatch();
+    assert_eq!(response.status(), Status::from_code(404).unwrap());
+
+    let response = client.get("/heartbeat/stale?threshold_sec=60").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    let devices = response_json["devices"].as_array().unwrap();
+    assert_eq!(devices.len(), 1, "Only receiver2 has not sent a heartbeat");
+    assert_eq!(devices[0]["id"], String::from("receiver2"));
+    assert_eq!(devices[0]["last_heartbeat"], Value::Null);
+
+    query_db("update device_heartbeat set time_epoch = 0 where device = 'receiver1'");
+    let response = client.get("/heartbeat/stale?threshold_sec=60").dispatch();
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    let devices = response_json["devices"].as_array().unwrap();
+    assert_eq!(devices.len(), 2);
+    let receiver1 = devices.iter().find(|d| d["id"] == "receiver1").unwrap();
+    assert_eq!(receiver1["firmware"], String::from("1.0.3"));
+    assert_eq!(receiver1["free_memory"], 20480);
+}
+
+#[test]
+fn integrationtest_responses_carry_request_id() {
+    let client = guarded_client();
+    let response = client
+        .get("/")
+        .header(rocket::http::Header::new("X-Request-Id", "test-request-1"))
+        .dispatch();
+    assert_eq!(response.headers().get_one("X-Request-Id"), Some("test-request-1"));
+
+    let response = client.get("/").dispatch();
+    let generated_id = response.headers().get_one("X-Request-Id");
+    assert!(generated_id.is_some(), "A request id should be generated when the client sends none");
+    assert_eq!(generated_id.unwrap().len(), 16);
+}
+
+#[test]
+fn integrationtest_metrics_are_exposed() {
+    reset_db();
+    query_db("insert into location (name) values('location1');");
+    query_db("insert into display (location) values(1);");
+    query_db("insert into rfid_tracker (id) values('tracker1');");
+    query_db("insert into rfid_receiver (id, location) values('receiver1', 1);");
+    let client = guarded_client();
+    client.post("/register/receiver1/tracker1").dispatch();
+    client.get("/video/1").dispatch();
+
+    let response = client.get("/metrics").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().unwrap();
+    assert!(body.contains("iot_http_requests_total{method=\"GET\",route=\"/video/<display_id>\",status=\"200\"}"));
+    assert!(body.contains("iot_db_query_duration_seconds_count{query=\"get_display_by_id\"}"));
+    assert!(body.contains("iot_video_selections_total{outcome=\"no_trackers\"}"));
+    assert!(body.contains("iot_active_trackers{location=\"1\"} 1"));
+}
+
+#[test]
+fn integrationtest_health_endpoints() {
+    let client = guarded_client();
+    let response = client.get("/health/live").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["status"], String::from("alive"));
+
+    let response = client.get("/health/ready").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response_json: Value =
+        serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["status"], String::from("ready"));
+    assert_eq!(response_json["database"], String::from("ok"));
+    assert_eq!(response_json["environment"], String::from(environment::TEST_STRING));
+}
+
+#[test]
+fn integrationtest_low_credit_and_exhausted_orders_are_notified() {
+    reset_db();
+    query_db("insert into location (name) values('location1');");
+    query_db("insert into display (location) values(1);");
+    query_db("insert into interest (name) values('sport');");
+    query_db("insert into rfid_tracker (id, location) values(1, 1);");
+    query_db("insert into tracker_interest (tracker, interest, weight) values(1, 1, 100);");
+    query_db("insert into advertisement_video (url, length_sec, interest) values('interest1_video', 16, 1);");
+    query_db("insert into agency (orgnr, name) values(1, 'agency1');");
+    query_db("insert into users (username, email, pass_hash, agency) values('user1',  'email@example.com', 'HASH',1);");
+    query_db("insert into orders values('1', 6, 'email@example.com');");
+    query_db("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '1',0, 2147483647);");
+
+    //The first delivery fails and is retried
+    let (url, stand_in) = webhook_stand_in(vec![500, 200, 200]);
+    query_db(&format!("insert into agency_webhook (agency, url, secret, low_credit_threshold) values(1, '{}', 'secret', 5)", url));
+
+    let client = guarded_client();
+    for _ in 0..3 {
+        let response = client.post("/views/1/1/1").body(view_body(16, 1, 1, "1")).dispatch();
+        assert_eq!(response.status(), Status::from_code(200).unwrap());
+    }
+
+    let now = now_epoch();
+    assert_eq!(webhooks::deliver_due(store(), now), 2);
+    assert_eq!(webhooks::deliver_due(store(), now), 0, "the failed delivery should not be retried before its retry delay");
+    assert_eq!(webhooks::deliver_due(store(), now + 3600), 1);
+
+    let requests = stand_in.join().unwrap();
+    assert!(requests[0].contains("\"event\":\"low_credit\""), "{}", requests[0]);
+    assert!(requests[1].contains("\"event\":\"order_exhausted\""), "{}", requests[1]);
+    assert!(requests[2].contains("\"event\":\"low_credit\""), "{}", requests[2]);
+    let body = requests[1].splitn(2, "\r\n\r\n").nth(1).unwrap().to_owned();
+    let signature = format!("sha256={}", webhooks::sign(&"secret".to_owned(), &body));
+    assert!(requests[1].to_lowercase().contains(&signature), "{}", requests[1]);
+
+    let deliveries = query_rows("select event, status, attempts from webhook_delivery order by id");
+    assert_eq!(deliveries, vec![
+        vec![Some("low_credit".to_owned()), Some("delivered".to_owned()), Some("2".to_owned())],
+        vec![Some("order_exhausted".to_owned()), Some("delivered".to_owned()), Some("1".to_owned())]
+    ]);
+}
+
+#[test]
+fn integrationtest_campaign_start_and_end_are_notified_once() {
+    reset_db();
+    query_db("insert into interest (name) values('sport');");
+    query_db("insert into advertisement_video (url, length_sec, interest) values('interest1_video', 16, 1);");
+    query_db("insert into agency (orgnr, name) values(1, 'agency1');");
+    query_db("insert into users (username, email, pass_hash, agency) values('user1',  'email@example.com', 'HASH',1);");
+    query_db("insert into orders values('1', 100, 'email@example.com');");
+
+    let now = now_epoch();
+    let (url, stand_in) = webhook_stand_in(vec![200, 200]);
+    query_db(&format!("insert into agency_webhook (agency, url, events) values(1, '{}', 'campaign_started,campaign_ended')", url));
+    query_db(&format!("insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '1', {}, {})", now - 100, now + 100));
+
+    assert_eq!(webhooks::queue_campaign_events(store(), now - 200, now), 1);
+    assert_eq!(webhooks::queue_campaign_events(store(), now - 200, now), 0, "a campaign event should only be queued once");
+    assert_eq!(webhooks::queue_campaign_events(store(), now, now + 200), 1);
+    assert_eq!(webhooks::deliver_due(store(), now + 200), 2);
+
+    let requests = stand_in.join().unwrap();
+    assert!(requests[0].contains("\"event\":\"campaign_started\""), "{}", requests[0]);
+    assert!(requests[1].contains("\"event\":\"campaign_ended\""), "{}", requests[1]);
+    assert!(requests[1].contains("\"video\":1"), "{}", requests[1]);
+}
+
+#[test]
+fn integrationtest_migrations_are_applied_once_and_never_changed() {
+    reset_db();
+    let backend_migrations = match config::get().database.backend {
+        DatabaseBackend::Postgres => migrations::POSTGRES_MIGRATIONS,
+        DatabaseBackend::Sqlite => migrations::SQLITE_MIGRATIONS,
+        DatabaseBackend::Mysql => migrations::MIGRATIONS
+    };
+    let applied: usize = query_value("select count(*) from schema_version");
+    assert_eq!(applied, backend_migrations.len());
+    assert_eq!(store().migrate(now_epoch()).unwrap(), Vec::<u32>::new(), "an up to date schema should not be migrated");
+
+    //Only the MySQL initial schema creates the tables it finds missing, as databases from before the migrations have them
+    if config::get().database.backend == DatabaseBackend::Mysql {
+        query_db("delete from schema_version where version = 1");
+        assert_eq!(store().migrate(now_epoch()).unwrap(), vec![1], "the initial schema should apply over the existing tables");
+    }
+
+    query_db(&format!("update schema_version set checksum = 'changed' where version = {}", backend_migrations.last().unwrap().version));
+    assert!(store().migrate(now_epoch()).unwrap_err().contains("has been changed"));
+}
+
+#[test]
+fn integrationtest_audience_follows_registered_trackers() {
+    reset_db();
+    query_db("insert into location (name) values('location1');");
+    query_db("insert into display (location) values(1);");
+    query_db("insert into interest (name) values('sport');");
+    query_db("insert into rfid_receiver (id, location) values('receiver1', 1);");
+    query_db("insert into rfid_tracker (id) values('tracker1');");
+    query_db("insert into rfid_tracker (id) values('tracker2');");
+    query_db("insert into tracker_interest (tracker, interest, weight) values('tracker1', 1, 10);");
+    query_db("insert into tracker_interest (tracker, interest, weight) values('tracker2', 1, 5);");
+    let client = guarded_client();
+    assert_eq!(client.get("/locations/2/audience").dispatch().status(), Status::NotFound);
+    let started = now_epoch();
+    client.post("/register/receiver1/tracker1").dispatch();
+    client.post("/register/receiver1/tracker2").dispatch();
+
+    let response = client.get("/locations/1/audience").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response_json: Value = serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["trackers"], 2);
+    assert_eq!(response_json["interests"], serde_json::json!([{"interest": 1, "weight": 15.0}]));
+    assert_eq!(response_json["displays"], serde_json::json!([1]));
+    assert_eq!(response_json["receivers"], serde_json::json!(["receiver1"]));
+
+    client.post("/unregister/receiver1/tracker1").dispatch();
+    let counts = query_rows("select trackers from occupancy_history where location = 1 order by id");
+    assert_eq!(counts, vec![vec![Some("1".to_owned())], vec![Some("2".to_owned())], vec![Some("1".to_owned())]]);
+    let response = client.get(format!("/locations/1/audience/history?from={}&to={}&bucket_sec=3600", started - 3600, started)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response_json: Value = serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["buckets"], serde_json::json!([{"start": started - 3600, "average": 0.0, "peak": 0}]),
+        "there should be no trackers before they were registered");
+}
+
+#[test]
+fn integrationtest_tracker_leaves_when_every_receiver_at_the_location_lost_it() {
+    reset_db();
+    query_db("insert into location (name) values('location1');");
+    query_db("insert into location (name) values('location2');");
+    query_db("insert into rfid_receiver (id, location) values('receiver1', 1);");
+    query_db("insert into rfid_receiver (id, location) values('receiver2', 1);");
+    query_db("insert into rfid_receiver (id, location) values('receiver3', 2);");
+    query_db("insert into rfid_tracker (id) values('tracker1');");
+    let client = guarded_client();
+    client.post("/register/receiver1/tracker1").dispatch();
+    client.post("/register/receiver2/tracker1").dispatch();
+    assert_eq!(client.post("/unregister/receiver1/tracker1").dispatch().status(), Status::Ok);
+    assert_eq!(query_value::<i32>("select location from rfid_tracker where id = 'tracker1'"), 1, "receiver2 still sees the tracker");
+    let response = client.get("/locations/1/audience").dispatch();
+    let response_json: Value = serde_json::from_str(response.into_string().unwrap().as_str()).unwrap();
+    assert_eq!(response_json["trackers"], 1);
+
+    client.post("/unregister/receiver2/tracker1").dispatch();
+    assert_eq!(query_rows("select location from rfid_tracker where id = 'tracker1'"), vec![vec![None]]);
+    assert_eq!(query_value::<i64>("select count(*) from tracker_presence"), 0);
+
+    client.post("/register/receiver1/tracker1").dispatch();
+    client.post("/register/receiver3/tracker1").dispatch();
+    assert_eq!(query_rows("select receiver from tracker_presence"), vec![vec![Some("receiver3".to_owned())]],
+        "moving to another location should forget the receivers of the previous one");
+    client.post("/unregister/receiver1/tracker1").dispatch();
+    assert_eq!(query_value::<i32>("select location from rfid_tracker where id = 'tracker1'"), 2);
+}
+
+#[test]
+fn integrationtest_expire_trackers_unregisters_only_old_registrations() {
+    reset_db();
+    query_db("insert into location (name) values('location1');");
+    query_db("insert into rfid_receiver (id, location) values('receiver1', 1);");
+    query_db("insert into rfid_tracker (id, location) values('unknown_time', 1);");
+    query_db("insert into rfid_tracker (id) values('recent');");
+    let client = guarded_client();
+    assert_eq!(client.post("/register/receiver1/recent").dispatch().status(), Status::Ok);
+
+    assert_eq!(devices::expire_trackers(store(), 3600).unwrap(), 1, "only the tracker without a registration time should be expired");
+    assert_eq!(cli::execute(cli::Command::ExpireTrackers {older_than_sec: 3600}, store()), exitcode::OK);
+    let counts = query_rows("select trackers from occupancy_history where location = 1 order by id");
+    assert_eq!(counts, vec![vec![Some("2".to_owned())], vec![Some("1".to_owned())]], "the expiry should be recorded once");
+    let locations = query_rows("select id, location from rfid_tracker order by id");
+    assert_eq!(locations, vec![
+        vec![Some("recent".to_owned()), Some("1".to_owned())],
+        vec![Some("unknown_time".to_owned()), None]
+    ]);
+}
+
+#[test]
+fn integrationtest_seed_and_create_devices_from_the_command_line() {
+    reset_db();
+    assert_eq!(cli::execute(cli::Command::Seed {file: None, force: false}, store()), exitcode::OK);
+    let orders = ledger::list_orders(store()).unwrap();
+    assert_eq!(orders.len(), 1);
+    assert_eq!((orders[0].order.id.as_str(), orders[0].plays, orders[0].spent_credits), ("demo-order", 0, 0));
+    assert_eq!(cli::execute(cli::Command::Seed {file: None, force: false}, store()), exitcode::DATAERR, "seeding twice should fail without loading anything");
+    assert_eq!(ledger::list_orders(store()).unwrap().len(), 1);
+
+    assert_eq!(cli::execute(cli::Command::CreateDevice {kind: cli::DeviceKind::Receiver, location: Some(2), id: Some("receiver3".to_owned())}, store()), exitcode::OK);
+    assert_eq!(cli::execute(cli::Command::CreateDevice {kind: cli::DeviceKind::Receiver, location: Some(2), id: Some("receiver3".to_owned())}, store()), exitcode::DATAERR);
+    assert_eq!(cli::execute(cli::Command::CreateDevice {kind: cli::DeviceKind::Display, location: Some(99), id: None}, store()), exitcode::DATAERR);
+    assert_eq!(cli::execute(cli::Command::CreateDevice {kind: cli::DeviceKind::Display, location: Some(1), id: None}, store()), exitcode::OK);
+    let client = guarded_client();
+    assert_eq!(client.get("/video/3").dispatch().status(), Status::Ok, "the created display should get videos");
+}
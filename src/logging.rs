@@ -0,0 +1,152 @@
+use std::cell::RefCell;
+use std::env;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use rand::Rng;
+use rocket::{Data, Request, Response};
+use rocket::fairing::{Fairing, Info, Kind};
+use serde_json::json;
+
+static LOG_LEVEL_VAR:  &'static str = "RUST_IOT_LOG_LEVEL";
+static LOG_FORMAT_VAR: &'static str = "RUST_IOT_LOG_FORMAT";
+pub static REQUEST_ID_HEADER: &'static str = "X-Request-Id";
+
+thread_local! {
+    //Set around the blocking store work of a request, see with_request_id
+    static CURRENT_REQUEST_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json
+}
+
+/**
+ * Logger writing one line per record, as plain text or as a json object.
+ * Every line includes the id of the request being handled, if any.
+ */
+struct IotLogger {
+    level: LevelFilter,
+    format: LogFormat
+}
+
+impl Log for IotLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+        let request_id = current_request_id();
+        let line = match self.format {
+            LogFormat::Json => json!({
+                "time_ms": time as u64,
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "request_id": request_id,
+                "message": record.args().to_string()
+            }).to_string(),
+            LogFormat::Text => format!("{}.{:03} {:<5} [{}] {}: {}",
+                time / 1000, time % 1000, record.level(), request_id.unwrap_or_else(|| "-".to_owned()), record.target(), record.args())
+        };
+        match record.level() {
+            Level::Error | Level::Warn => { let _ = writeln!(std::io::stderr(), "{}", line); },
+            _ => { let _ = writeln!(std::io::stdout(), "{}", line); }
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/**
+ * Reads the log level from RUST_IOT_LOG_LEVEL (error|warn|info|debug|trace|off), defaults to info
+ */
+pub fn level_from_env() -> LevelFilter {
+    env::var(LOG_LEVEL_VAR).ok().and_then(|l| l.parse::<LevelFilter>().ok()).unwrap_or(LevelFilter::Info)
+}
+
+/**
+ * Reads the log format from RUST_IOT_LOG_FORMAT (text|json), defaults to text
+ */
+pub fn format_from_env() -> LogFormat {
+    match env::var(LOG_FORMAT_VAR).map(|f| f.to_lowercase()) {
+        Ok(ref f) if f == "json" => LogFormat::Json,
+        _ => LogFormat::Text
+    }
+}
+
+/**
+ * Installs the app logger. Must be called before rocket is launched, rocket will then log through it as well.
+ */
+pub fn init(level: LevelFilter, format: LogFormat) {
+    match log::set_boxed_logger(Box::new(IotLogger {level, format})) {
+        Ok(()) => log::set_max_level(level),
+        Err(e) => eprintln!("Logger failed to initialize: {}", e)
+    }
+}
+
+/**
+ * Returns the id of the request currently handled by this thread, if any
+ */
+pub fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST_ID.with(|id| id.borrow().clone())
+}
+
+/**
+ * Runs f with the given request id set for this thread, so the log lines f writes carry it.
+ * Handlers are async and may move between threads while awaiting, f must therefore not await.
+ */
+pub fn with_request_id<T, F: FnOnce() -> T>(id: Option<&str>, f: F) -> T {
+    let previous = CURRENT_REQUEST_ID.with(|current| current.replace(id.map(|id| id.to_owned())));
+    let result = f();
+    CURRENT_REQUEST_ID.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/**
+ * The id of a request, cached in the request.
+ */
+pub struct RequestId(pub String);
+
+/**
+ * Returns the id the RequestIdFairing assigned to the request
+ */
+pub fn request_id<'r>(request: &'r Request<'_>) -> &'r str {
+    &request.local_cache(|| RequestId(String::from("-"))).0
+}
+
+/**
+ * Fairing assigning an id to each request. The id is taken from the X-Request-Id header if the client sent one,
+ * it is included in the log lines written by the store work of the request and returned in the X-Request-Id response header.
+ */
+#[derive(Default)]
+pub struct RequestIdFairing {}
+
+#[rocket::async_trait]
+impl Fairing for RequestIdFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request id",
+            kind: Kind::Request | Kind::Response
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let id = request.headers().get_one(REQUEST_ID_HEADER)
+            .map(|id| id.to_owned())
+            .unwrap_or_else(|| format!("{:016x}", rand::thread_rng().gen::<u64>()));
+        request.local_cache(|| RequestId(id));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        response.set_raw_header(REQUEST_ID_HEADER, request_id(request).to_owned());
+    }
+}
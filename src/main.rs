@@ -1,4 +1,4 @@
-#![feature(proc_macro_hygiene, decl_macro)]
+#![cfg_attr(test, feature(proc_macro_hygiene))]
 /**
  * Interface layer
  * Magnus Fredriksson
@@ -6,7 +6,6 @@
 #[macro_use] extern crate rocket;
 #[cfg(test)]
 mod integration_tests;
-extern crate futures;
 
 /**
  * Persistence layer
@@ -16,6 +15,15 @@ mod persistance;
  * App configuration
  */
 mod environment;
+mod config;
+/**
+ * App logging, levels, formats and request ids
+ */
+mod logging;
+/**
+ * App metrics, exposed for prometheus
+ */
+mod metrics;
 /**
  * App model, database / request to struct mapping
  */
@@ -28,6 +36,10 @@ mod services;
  * App public facing API endpoints
  */
 mod endpoints;
+/**
+ * Command line interface for operations tasks
+ */
+mod cli;
 
 use rocket::http::Method;
 use rocket_cors;
@@ -35,18 +47,42 @@ use rocket::routes;
 use rocket_cors::{AllowedHeaders, AllowedOrigins};
 use rocket::{Request, Response};
 use rocket::fairing::{Fairing, Info, Kind};
+use log::{error, info, warn};
+use persistance::store::{SharedStore, Store};
+use services::occupancy::{Occupancy, SharedOccupancy};
 
 /**
  *  Program entrypoint, initializes rocket with the public endpoints
  */ 
 fn main() {
+    logging::init(logging::level_from_env(), logging::format_from_env());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match cli::parse(&args) {
+        Ok(cli::Command::Serve) => (),
+        Ok(command) => std::process::exit(cli::run(command)),
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(exitcode::USAGE);
+        }
+    }
+    check_config();
+    let store = open_store();
     check_env();
-    rocket().launch();
+    if config::get().migrate_on_startup {
+        migrate(&*store);
+    }
+    services::webhooks::start_dispatcher(store.clone());
+    let occupancy = open_occupancy(&store);
+    if let Err(e) = rocket::execute(rocket(store, occupancy).launch()) {
+        error!("server stopped: {}", e);
+        std::process::exit(exitcode::SOFTWARE);
+    }
 }
 
 #[derive(Default)]
 struct ResponsePostProcessor {}
 
+#[rocket::async_trait]
 impl Fairing for ResponsePostProcessor {
     fn info(&self) -> Info {
         Info {
@@ -54,34 +90,61 @@ impl Fairing for ResponsePostProcessor {
             kind: Kind::Request | Kind::Response
         }
     }
-    fn on_response(&self, _request: &Request, response: &mut Response) {
-        response.set_raw_header("Access-Control-Allow-Origin", "*");
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let config = config::get();
+        if config.allows_all_origins() {
+            response.set_raw_header("Access-Control-Allow-Origin", "*");
+        } else if let Some(origin) = request.headers().get_one("Origin").filter(|o| config.cors_origins.iter().any(|c| c == o)) {
+            response.set_raw_header("Access-Control-Allow-Origin", origin.to_owned());
+        }
     }
 }
 
-fn rocket() -> rocket::Rocket {
-    rocket::ignite()
+fn rocket(store: SharedStore, occupancy: SharedOccupancy) -> rocket::Rocket<rocket::Build> {
+    endpoints::health_endpoints::mark_started();
+    rocket::build()
+    .attach(logging::RequestIdFairing{})
+    .attach(metrics::MetricsFairing{})
     .attach(ResponsePostProcessor{})
     .mount("/", routes![
         endpoints::default, 
-        endpoints::log_message,
+        endpoints::get_metrics,
+        endpoints::health_endpoints::live,
+        endpoints::health_endpoints::ready,
+        endpoints::logs_endpoints::log_message,
+        endpoints::logs_endpoints::get_logs,
         endpoints::devices_endpoints::register, 
         endpoints::devices_endpoints::register_json, 
         endpoints::devices_endpoints::get_tracker, 
         endpoints::devices_endpoints::unregister, 
         endpoints::devices_endpoints::unregister_json, 
+        endpoints::devices_endpoints::receiver_heartbeat,
+        endpoints::devices_endpoints::display_heartbeat,
+        endpoints::devices_endpoints::stale_devices,
         endpoints::videos_endpoints::register_view,
-        endpoints::videos_endpoints::get_video]) 
-    .register( catchers![
+        endpoints::videos_endpoints::get_video,
+        endpoints::orders_endpoints::get_statement,
+        endpoints::locations_endpoints::get_audience,
+        endpoints::locations_endpoints::get_audience_history,
+        endpoints::admin_endpoints::void_view,
+        endpoints::admin_endpoints::void_display_views]) 
+    .register("/", catchers![
         endpoints::catchers::not_found, 
         endpoints::catchers::bad_request, 
+        endpoints::catchers::unauthorized,
         endpoints::catchers::unproc_request])
     .mount("/", rocket_cors::catch_all_options_routes())
     .manage(cors())
+    .manage(store)
+    .manage(occupancy)
 }
 
 fn cors() -> rocket_cors::Cors {
-    let allowed_origins = AllowedOrigins::all();
+    let config = config::get();
+    let allowed_origins = match config.allows_all_origins() {
+        true => AllowedOrigins::all(),
+        false => AllowedOrigins::some_exact(&config.cors_origins)
+    };
     rocket_cors::CorsOptions {
         allowed_origins,
         allowed_methods: vec![Method::Get].into_iter().map(From::from).collect(),
@@ -91,9 +154,62 @@ fn cors() -> rocket_cors::Cors {
     }.to_cors().unwrap()
 }
 
+/**
+ * Validates the configuration, exits listing every error found if it is invalid
+ */
+fn check_config() {
+    if let Err(errors) = config::load() {
+        for e in errors.iter() {
+            error!("configuration error: {}", e);
+        }
+        std::process::exit(exitcode::CONFIG);
+    }
+}
+
+/**
+ * Opens the store of the configured database with the configured cache in front of it, exits if it can not be opened
+ */
+fn open_store() -> SharedStore {
+    match persistance::open(&config::get().database) {
+        Ok(store) => persistance::cache::with_cache(store, config::get().cache_ttl_sec),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(exitcode::UNAVAILABLE);
+        }
+    }
+}
+
+/**
+ * Builds the occupancy of the locations from the store and keeps it resynced, exits if it can not be built
+ */
+fn open_occupancy(store: &SharedStore) -> SharedOccupancy {
+    let occupancy = SharedOccupancy::new(Occupancy::default());
+    match occupancy.rebuild(&**store) {
+        Ok(_) => (),
+        Err(e) => {
+            error!("could not build the occupancy: {}", e);
+            std::process::exit(exitcode::UNAVAILABLE);
+        }
+    }
+    if config::get().occupancy_resync_sec > 0 {
+        services::occupancy::start_resync(occupancy.clone(), store.clone(), config::get().occupancy_resync_sec);
+    }
+    occupancy
+}
+
+/**
+ * Applies the pending schema migrations, exits if migrating fails
+ */
+fn migrate(store: &dyn Store) {
+    let code = cli::migrate(store);
+    if code != exitcode::OK {
+        std::process::exit(code);
+    }
+}
+
 fn check_env() {
-    match String::from(environment::PRODUCTION_STRING) == environment::get_current_env() {
-        false => colour::yellow!("\n### USING STAGING ENVIRONMENT (not an error) ###\n\n"),
-        true =>  colour::dark_red!("\n### WARNING! USING PRODUCTION ENVIRONMENT ###\n\n")
+    match String::from(environment::PRODUCTION_STRING) == config::get().environment {
+        false => info!("### USING STAGING ENVIRONMENT (not an error) ###"),
+        true =>  warn!("### WARNING! USING PRODUCTION ENVIRONMENT ###")
     }
 }
@@ -0,0 +1,87 @@
+use std::time::Instant;
+use lazy_static::lazy_static;
+use prometheus::{Encoder, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder};
+use prometheus::{register_histogram_vec, register_int_counter_vec, register_int_gauge_vec};
+use rocket::{Data, Request, Response};
+use rocket::fairing::{Fairing, Info, Kind};
+
+lazy_static! {
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "iot_http_requests_total", "Number of handled http requests by route and status", &["method", "route", "status"]).unwrap();
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "iot_http_request_duration_seconds", "Time spent handling http requests by route", &["method", "route"]).unwrap();
+    pub static ref DB_QUERY_DURATION: HistogramVec = register_histogram_vec!(
+        "iot_db_query_duration_seconds", "Time spent in database functions by function", &["query"]).unwrap();
+    pub static ref VIDEO_SELECTIONS: IntCounterVec = register_int_counter_vec!(
+        "iot_video_selections_total", "Number of video selections by outcome", &["outcome"]).unwrap();
+    pub static ref CREDITS_DRAWN: IntCounterVec = register_int_counter_vec!(
+        "iot_credits_drawn_total", "Number of credits drawn from orders by order", &["order"]).unwrap();
+    pub static ref CREDITS_REFUNDED: IntCounterVec = register_int_counter_vec!(
+        "iot_credits_refunded_total", "Number of credits given back to orders for voided plays by order", &["order"]).unwrap();
+    pub static ref CACHE_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "iot_cache_lookups_total", "Number of store cache lookups by cache and outcome", &["cache", "outcome"]).unwrap();
+    pub static ref ACTIVE_TRACKERS: IntGaugeVec = register_int_gauge_vec!(
+        "iot_active_trackers", "Number of trackers currently registered by location", &["location"]).unwrap();
+}
+
+/**
+ * Outcomes of selecting a video for a display
+ */
+pub static SELECTION_FOUND: &'static str = "found";
+pub static SELECTION_NO_TRACKERS: &'static str = "no_trackers";
+pub static SELECTION_NO_ELIGIBLE_VIDEO: &'static str = "no_eligible_video";
+pub static SELECTION_NO_DISPLAY_LOCATION: &'static str = "no_display_location";
+pub static SELECTION_ERROR: &'static str = "error";
+
+/**
+ * Runs a database function and records its latency under the given name
+ */
+pub fn time_query<T, F: FnOnce() -> T>(query: &str, f: F) -> T {
+    let timer = DB_QUERY_DURATION.with_label_values(&[query]).start_timer();
+    let res = f();
+    timer.observe_duration();
+    res
+}
+
+/**
+ * Renders all registered metrics in the prometheus text format
+ */
+pub fn render() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer).unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+/**
+ * The time a request arrived, cached in the request.
+ */
+struct RequestStart(Instant);
+
+/**
+ * Fairing counting requests per route and status and recording their duration.
+ */
+#[derive(Default)]
+pub struct MetricsFairing {}
+
+#[rocket::async_trait]
+impl Fairing for MetricsFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request metrics",
+            kind: Kind::Request | Kind::Response
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let route = request.route().map(|r| r.uri.path().to_string()).unwrap_or_else(|| "unmatched".to_owned());
+        let method = request.method().as_str();
+        let RequestStart(start) = request.local_cache(|| RequestStart(Instant::now()));
+        let status = response.status().code.to_string();
+        HTTP_REQUESTS.with_label_values(&[method, route.as_str(), status.as_str()]).inc();
+        HTTP_REQUEST_DURATION.with_label_values(&[method, route.as_str()]).observe(start.elapsed().as_secs_f64());
+    }
+}
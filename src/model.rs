@@ -19,7 +19,26 @@ pub struct AdvertVideoOrder {
     pub interest: i32,
     pub url: String, 
     pub length_sec: i32,
-    pub order: String
+    pub order: String,
+    pub budget: OrderBudget
+}
+
+/**
+ * What is known about the budget of an order when deciding if it may be shown.
+ * The campaign runs from the earliest start to the latest end of the order's advertisement_order rows.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrderBudget {
+    pub remaining_credits: i32,
+    pub spent_credits: i32,
+    pub start_epoch: u64,
+    pub end_epoch: u64
+}
+
+impl OrderBudget {
+    pub fn total_credits(&self) -> i32 {
+        self.remaining_credits + self.spent_credits
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -34,6 +53,40 @@ pub struct Receiver {
     pub location: i32
 }
 
+/**
+ * A tracker registered at a location, with the weights of its interests
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct PresentTracker {
+    pub id: String,
+    pub location: i32,
+    pub interests: Vec<(i32, f32)>
+}
+
+/**
+ * The trackers at a location with their interests summed per interest, highest weight first,
+ * and the displays and receivers of the location
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audience {
+    pub location: i32,
+    pub trackers: usize,
+    pub interests: Vec<(i32, f32)>,
+    pub displays: Vec<i32>,
+    pub receivers: Vec<String>
+}
+
+/**
+ * The trackers at a location during a bucket of time starting at start_epoch,
+ * averaged over the time of the bucket and at their highest
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct OccupancyBucket {
+    pub start_epoch: u64,
+    pub average: f64,
+    pub peak: i64
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Display {
     pub id: i32,
@@ -44,4 +97,399 @@ pub struct Display {
 pub struct Agency {
     pub name: String,
     pub orgnr: String
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal
+}
+
+impl Severity {
+    pub fn all() -> Vec<Severity> {
+        vec![Severity::Trace, Severity::Debug, Severity::Info, Severity::Warn, Severity::Error, Severity::Fatal]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Trace => "trace",
+            Severity::Debug => "debug",
+            Severity::Info => "info",
+            Severity::Warn => "warn",
+            Severity::Error => "error",
+            Severity::Fatal => "fatal"
+        }
+    }
+
+    pub fn from_str(severity: &str) -> Option<Severity> {
+        match &severity.to_lowercase()[..] {
+            "trace" => Some(Severity::Trace),
+            "debug" => Some(Severity::Debug),
+            "info" => Some(Severity::Info),
+            "warn" | "warning" => Some(Severity::Warn),
+            "error" => Some(Severity::Error),
+            "fatal" => Some(Severity::Fatal),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub device: Option<String>,
+    pub severity: Severity,
+    pub firmware: Option<String>,
+    pub time_epoch: u64,
+    pub message: String,
+    pub fields: Option<String>
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    pub device: Option<String>,
+    pub severity: Option<Severity>,
+    pub min_severity: Option<Severity>,
+    pub from_epoch: Option<u64>,
+    pub to_epoch: Option<u64>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceType {
+    Receiver,
+    Display
+}
+
+impl DeviceType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceType::Receiver => "receiver",
+            DeviceType::Display => "display"
+        }
+    }
+
+    pub fn from_str(device_type: &str) -> Option<DeviceType> {
+        match device_type {
+            "receiver" => Some(DeviceType::Receiver),
+            "display" => Some(DeviceType::Display),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DeviceStatus {
+    pub uptime_sec: Option<u64>,
+    pub firmware: Option<String>,
+    pub free_memory: Option<u64>,
+    pub last_error: Option<String>
+}
+
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    pub device_type: DeviceType,
+    pub device: String,
+    pub time_epoch: u64,
+    pub status: DeviceStatus
+}
+
+#[derive(Debug, Clone)]
+pub struct StaleDevice {
+    pub device_type: DeviceType,
+    pub device: String,
+    pub last_heartbeat: Option<Heartbeat>
+}
+
+/**
+ * How a fractional charge is turned into whole credits
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    Up,
+    Down,
+    Nearest
+}
+
+impl Rounding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rounding::Up => "up",
+            Rounding::Down => "down",
+            Rounding::Nearest => "nearest"
+        }
+    }
+
+    pub fn from_str(rounding: &str) -> Option<Rounding> {
+        match rounding {
+            "up" => Some(Rounding::Up),
+            "down" => Some(Rounding::Down),
+            "nearest" => Some(Rounding::Nearest),
+            _ => None
+        }
+    }
+}
+
+/**
+ * The price of played video at a location, or at all locations if location is None,
+ * between start_minute (inclusive) and end_minute (exclusive) of the day.
+ * A window with end_minute before start_minute wraps past midnight.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateCard {
+    pub id: i32,
+    pub location: Option<i32>,
+    pub start_minute: u32,
+    pub end_minute: u32,
+    pub credits_per_second: f64,
+    pub min_credits: i32,
+    pub rounding: Rounding
+}
+
+/**
+ * A registered play of a video, length_sec is the validated number of seconds played
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayedVideo {
+    pub video: i32,
+    pub display: i32,
+    pub order: String,
+    pub time_epoch: u64,
+    pub length_sec: i32,
+    pub complete: bool,
+    pub credits: i32
+}
+
+/**
+ * The link between an order and a video it pays for, the campaign runs between the start and end times
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdvertisementOrder {
+    pub video: i32,
+    pub order: String,
+    pub start_time_epoch: u64,
+    pub end_time_epoch: u64
+}
+
+/**
+ * The claims of a verified play token, the signature is unique per token
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayToken {
+    pub display: i32,
+    pub video: i32,
+    pub order: String,
+    pub issued_at: u64,
+    pub signature: String
+}
+
+/**
+ * Events agencies can subscribe to with webhooks
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WebhookEvent {
+    LowCredit,
+    OrderExhausted,
+    CampaignStarted,
+    CampaignEnded
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::LowCredit => "low_credit",
+            WebhookEvent::OrderExhausted => "order_exhausted",
+            WebhookEvent::CampaignStarted => "campaign_started",
+            WebhookEvent::CampaignEnded => "campaign_ended"
+        }
+    }
+
+    pub fn from_str(event: &str) -> Option<WebhookEvent> {
+        match event {
+            "low_credit" => Some(WebhookEvent::LowCredit),
+            "order_exhausted" => Some(WebhookEvent::OrderExhausted),
+            "campaign_started" => Some(WebhookEvent::CampaignStarted),
+            "campaign_ended" => Some(WebhookEvent::CampaignEnded),
+            _ => None
+        }
+    }
+}
+
+/**
+ * A webhook of an agency, events is None if the webhook is subscribed to all events
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Webhook {
+    pub id: i32,
+    pub agency: String,
+    pub url: String,
+    pub secret: Option<String>,
+    pub events: Option<Vec<WebhookEvent>>,
+    pub low_credit_threshold: i32
+}
+
+impl Webhook {
+    pub fn is_subscribed_to(&self, event: WebhookEvent) -> bool {
+        self.events.as_ref().map_or(true, |events| events.contains(&event))
+    }
+}
+
+/**
+ * A pending delivery of an event to a webhook
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub url: String,
+    pub secret: Option<String>,
+    pub event: WebhookEvent,
+    pub payload: String,
+    pub attempts: u32
+}
+
+/**
+ * The outcome of a delivery attempt
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed"
+        }
+    }
+}
+
+/**
+ * The kind of a credit movement in the credit ledger
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LedgerEntryType {
+    /**
+     * The balance of the order when its ledger was started
+     */
+    Opening,
+    /**
+     * Credits drawn for a played video
+     */
+    Debit,
+    /**
+     * Credits added to the order
+     */
+    TopUp,
+    /**
+     * Credits given back for a voided play
+     */
+    Refund,
+    /**
+     * A change of the order balance made outside of the ledger, found when reconciling
+     */
+    Adjustment
+}
+
+impl LedgerEntryType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LedgerEntryType::Opening => "opening",
+            LedgerEntryType::Debit => "debit",
+            LedgerEntryType::TopUp => "top_up",
+            LedgerEntryType::Refund => "refund",
+            LedgerEntryType::Adjustment => "adjustment"
+        }
+    }
+
+    pub fn from_str(entry_type: &str) -> Option<LedgerEntryType> {
+        match entry_type {
+            "opening" => Some(LedgerEntryType::Opening),
+            "debit" => Some(LedgerEntryType::Debit),
+            "top_up" => Some(LedgerEntryType::TopUp),
+            "refund" => Some(LedgerEntryType::Refund),
+            "adjustment" => Some(LedgerEntryType::Adjustment),
+            _ => None
+        }
+    }
+}
+
+/**
+ * A credit movement of an order, amount is negative for debits.
+ * Debits carry the played video and the rate applied, rate_card is None for the default rate card.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerEntry {
+    pub id: i32,
+    pub order: String,
+    pub time_epoch: u64,
+    pub entry_type: LedgerEntryType,
+    pub amount: i32,
+    pub balance_after: i32,
+    pub played_video: Option<i32>,
+    pub rate_card: Option<i32>,
+    pub credits_per_second: Option<f64>,
+    pub description: Option<String>
+}
+
+/**
+ * The credit ledger of an order together with the order itself
+ */
+#[derive(Debug, Clone)]
+pub struct OrderStatement {
+    pub order: Order,
+    pub entries: Vec<LedgerEntry>
+}
+
+impl OrderStatement {
+    /**
+     * The balance derived from the ledger, the order balance if the ledger of the order has not been started
+     */
+    pub fn ledger_balance(&self) -> i32 {
+        match self.entries.is_empty() {
+            true => self.order.credits,
+            false => self.entries.iter().map(|e| e.amount).sum()
+        }
+    }
+
+    /**
+     * Whether the order balance matches the balance derived from the ledger
+     */
+    pub fn is_reconciled(&self) -> bool {
+        self.ledger_balance() == self.order.credits
+    }
+}
+
+/**
+ * The plays to void: a single play, or every play of a display between two times (inclusive)
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlaySelection {
+    Single(i32),
+    Display {display: i32, from_epoch: u64, to_epoch: u64}
+}
+
+/**
+ * A voided play and the credits given back to its order
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoidedPlay {
+    pub id: i32,
+    pub order: String,
+    pub credits: i32
+}
+
+/**
+ * An order with the number of plays it paid for and the credits they cost, voided plays left out
+ */
+#[derive(Debug, Clone)]
+pub struct OrderSummary {
+    pub order: Order,
+    pub plays: i64,
+    pub spent_credits: i64
+}
@@ -0,0 +1,412 @@
+/**
+ * Store caching the lookups of video selection in front of another store.
+ *
+ * Display and receiver locations and the eligible videos per interest are kept for a time to live.
+ * The writes through this store that change them invalidate them: adding a receiver invalidates its location,
+ * drawing and refunding credits invalidate the eligible videos.
+ * Changes made to the database behind the back of the app are seen once the cached values have lived out their time.
+ */
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::metrics;
+use crate::model::*;
+use super::store::{SharedStore, Store};
+
+/**
+ * Values cached for a time to live, with the generation counting the invalidations.
+ * A value loaded before an invalidation is not cached, it may already be out of date.
+ */
+struct TtlCache<K, V> {
+    name: &'static str,
+    ttl: Duration,
+    state: Mutex<(u64, HashMap<K, (Instant, V)>)>
+}
+
+impl<K: Eq + Hash, V: Clone> TtlCache<K, V> {
+    fn new(name: &'static str, ttl: Duration) -> TtlCache<K, V> {
+        TtlCache {name, ttl, state: Mutex::new((0, HashMap::new()))}
+    }
+
+    /**
+     * The cached value of the key if it is still alive, and the generation to cache a loaded value with otherwise
+     */
+    fn get(&self, key: &K) -> Result<V, u64> {
+        let mut state = self.state.lock().unwrap();
+        let generation = state.0;
+        let found = match state.1.get(key).map(|(cached_at, value)| (cached_at.elapsed() < self.ttl, value.clone())) {
+            Some((true, value)) => Some(value),
+            Some((false, _)) => {state.1.remove(key); None},
+            None => None
+        };
+        let outcome = if found.is_some() {"hit"} else {"miss"};
+        metrics::CACHE_LOOKUPS.with_label_values(&[self.name, outcome]).inc();
+        found.ok_or(generation)
+    }
+
+    fn insert(&self, generation: u64, key: K, value: V) {
+        let mut state = self.state.lock().unwrap();
+        if state.0 == generation {
+            state.1.insert(key, (Instant::now(), value));
+        }
+    }
+
+    fn remove(&self, key: &K) {
+        let mut state = self.state.lock().unwrap();
+        state.0 += 1;
+        state.1.remove(key);
+    }
+
+    fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.0 += 1;
+        state.1.clear();
+    }
+}
+
+pub struct CachedStore {
+    inner: SharedStore,
+    displays: TtlCache<i32, Display>,
+    receivers: TtlCache<String, Receiver>,
+    videos: TtlCache<i32, Vec<AdvertVideoOrder>>
+}
+
+/**
+ * Puts a cache with the given time to live in seconds in front of a store, returns the store as is if the time to live is 0
+ */
+pub fn with_cache(store: SharedStore, ttl_sec: u64) -> SharedStore {
+    match ttl_sec {
+        0 => store,
+        _ => Arc::new(CachedStore::new(store, Duration::from_secs(ttl_sec)))
+    }
+}
+
+impl CachedStore {
+    pub fn new(inner: SharedStore, ttl: Duration) -> CachedStore {
+        CachedStore {
+            inner,
+            displays: TtlCache::new("displays", ttl),
+            receivers: TtlCache::new("receivers", ttl),
+            videos: TtlCache::new("videos", ttl)
+        }
+    }
+
+    /**
+     * Drops every cached value, for when the database was changed behind the back of the app
+     */
+    pub fn clear(&self) {
+        self.displays.clear();
+        self.receivers.clear();
+        self.videos.clear();
+    }
+}
+
+impl Store for CachedStore {
+    fn unregister_tracker_from_receiver(&self, receiver_id: &String, tracker_id: &String) -> Result<bool, String> {
+        self.inner.unregister_tracker_from_receiver(receiver_id, tracker_id)
+    }
+
+    fn register_tracker_to_receiver(&self, receiver_id: &String, tracker_id: &String) -> Result<(), String> {
+        self.inner.register_tracker_to_receiver(receiver_id, tracker_id)
+    }
+
+    fn expire_trackers(&self, before_epoch: u64) -> Result<u64, String> {
+        self.inner.expire_trackers(before_epoch)
+    }
+
+    fn location_exists(&self, location: i32) -> Result<bool, String> {
+        self.inner.location_exists(location)
+    }
+
+    fn insert_tracker(&self, tracker: &Tracker) -> Result<(), String> {
+        self.inner.insert_tracker(tracker)
+    }
+
+    fn insert_receiver(&self, receiver: &Receiver) -> Result<(), String> {
+        let res = self.inner.insert_receiver(receiver);
+        self.receivers.remove(&receiver.id);
+        res
+    }
+
+    fn insert_display(&self, location: i32) -> Result<i32, String> {
+        self.inner.insert_display(location)
+    }
+
+    fn get_tracker_by_id(&self, tracker_id: &String) -> Result<Option<Tracker>, String> {
+        self.inner.get_tracker_by_id(tracker_id)
+    }
+
+    fn get_receiver_by_id(&self, receiver_id: &String) -> Result<Option<Receiver>, String> {
+        let generation = match self.receivers.get(receiver_id) {
+            Ok(receiver) => return Ok(Some(receiver)),
+            Err(generation) => generation
+        };
+        let receiver = self.inner.get_receiver_by_id(receiver_id)?;
+        if let Some(ref receiver) = receiver {
+            self.receivers.insert(generation, receiver_id.clone(), receiver.clone());
+        }
+        Ok(receiver)
+    }
+
+    fn get_display_location(&self, display_id: i32) -> Option<i32> {
+        self.get_display_by_id(display_id).ok().and_then(|display| display.map(|d| d.location))
+    }
+
+    fn get_display_by_id(&self, display_id: i32) -> Result<Option<Display>, String> {
+        let generation = match self.displays.get(&display_id) {
+            Ok(display) => return Ok(Some(display)),
+            Err(generation) => generation
+        };
+        let display = self.inner.get_display_by_id(display_id)?;
+        if let Some(display) = display {
+            self.displays.insert(generation, display_id, display);
+        }
+        Ok(display)
+    }
+
+    fn get_tracker_interests(&self, tracker_id: &String) -> Result<Vec<(i32, f32)>, String> {
+        self.inner.get_tracker_interests(tracker_id)
+    }
+
+    fn find_present_trackers(&self) -> Result<Vec<PresentTracker>, String> {
+        self.inner.find_present_trackers()
+    }
+
+    fn get_advertisement_video_by_id(&self, video_id: i32) -> Result<Option<AdvertVideo>, String> {
+        self.inner.get_advertisement_video_by_id(video_id)
+    }
+
+    fn get_order_by_id(&self, order_id: &String) -> Result<Option<Order>, String> {
+        self.inner.get_order_by_id(order_id)
+    }
+
+    fn get_advertisement_order(&self, video_id: i32, order_id: &String) -> Result<Option<AdvertisementOrder>, String> {
+        self.inner.get_advertisement_order(video_id, order_id)
+    }
+
+    fn register_play(&self, played: &PlayedVideo, rate_card: &RateCard, play_token: &String) -> Result<Option<i32>, String> {
+        //The credits drawn may take the order out of the eligible videos, or put it ahead of its pacing target
+        let res = self.inner.register_play(played, rate_card, play_token);
+        self.videos.clear();
+        res
+    }
+
+    fn get_play_voided(&self, played_video_id: i32) -> Result<Option<bool>, String> {
+        self.inner.get_play_voided(played_video_id)
+    }
+
+    fn void_plays(&self, selection: &PlaySelection, reason: &Option<String>, time_epoch: u64) -> Result<Vec<VoidedPlay>, String> {
+        let res = self.inner.void_plays(selection, reason, time_epoch);
+        self.videos.clear();
+        res
+    }
+
+    fn find_ledger_entries(&self, order_id: &String) -> Result<Vec<LedgerEntry>, String> {
+        self.inner.find_ledger_entries(order_id)
+    }
+
+    fn get_rate_cards_for_location(&self, location: i32) -> Result<Vec<RateCard>, String> {
+        self.inner.get_rate_cards_for_location(location)
+    }
+
+    /**
+     * The eligible videos are cached per interest, only the interests without cached videos are queried.
+     * Cached videos are left out once the campaign window of their order has ended, campaigns starting are seen
+     * once the cached videos have lived out their time. The pacing targets are applied by the caller to the budgets cached with the videos.
+     */
+    fn find_eligible_videos_by_interest(&self, interests: Vec<i32>, time_epoch: u64) -> Result<Option<Vec<AdvertVideoOrder>>, String> {
+        let mut videos: Vec<AdvertVideoOrder> = vec![];
+        let mut missing: Vec<(i32, u64)> = vec![];
+        for interest in interests {
+            match self.videos.get(&interest) {
+                Ok(cached) => videos.extend(cached.into_iter().filter(|v| v.budget.start_epoch <= time_epoch && time_epoch <= v.budget.end_epoch)),
+                Err(generation) => missing.push((interest, generation))
+            }
+        }
+        if !missing.is_empty() {
+            let loaded = self.inner.find_eligible_videos_by_interest(missing.iter().map(|m| m.0).collect(), time_epoch)?.unwrap_or_default();
+            for (interest, generation) in missing {
+                self.videos.insert(generation, interest, loaded.iter().filter(|v| v.interest == interest).cloned().collect());
+            }
+            videos.extend(loaded);
+        }
+        match videos.len() {
+            0 => Ok(None),
+            _ => Ok(Some(videos))
+        }
+    }
+
+    fn find_webhooks_for_order(&self, order_id: &String) -> Result<Vec<Webhook>, String> {
+        self.inner.find_webhooks_for_order(order_id)
+    }
+
+    fn insert_webhook_delivery(&self, webhook_id: i32, event: WebhookEvent, order_id: &String, payload: &String, time_epoch: u64) -> Result<(), String> {
+        self.inner.insert_webhook_delivery(webhook_id, event, order_id, payload, time_epoch)
+    }
+
+    fn find_due_webhook_deliveries(&self, time_epoch: u64, limit: u32) -> Result<Vec<WebhookDelivery>, String> {
+        self.inner.find_due_webhook_deliveries(time_epoch, limit)
+    }
+
+    fn update_webhook_delivery(&self, delivery_id: i32, status: DeliveryStatus, attempts: u32, next_attempt_epoch: u64,
+                               response_code: Option<u16>, error: Option<String>, time_epoch: u64) -> Result<(), String> {
+        self.inner.update_webhook_delivery(delivery_id, status, attempts, next_attempt_epoch, response_code, error, time_epoch)
+    }
+
+    fn find_unrecorded_campaign_events(&self, after_epoch: u64, until_epoch: u64) -> Result<Vec<(AdvertisementOrder, WebhookEvent)>, String> {
+        self.inner.find_unrecorded_campaign_events(after_epoch, until_epoch)
+    }
+
+    fn record_campaign_event(&self, campaign: &AdvertisementOrder, event: WebhookEvent, time_epoch: u64) -> Result<bool, String> {
+        self.inner.record_campaign_event(campaign, event, time_epoch)
+    }
+
+    fn insert_log_entries(&self, entries: &Vec<LogEntry>) -> Result<(), String> {
+        self.inner.insert_log_entries(entries)
+    }
+
+    fn find_log_entries(&self, filter: &LogFilter, limit: u32) -> Result<Vec<LogEntry>, String> {
+        self.inner.find_log_entries(filter, limit)
+    }
+
+    fn upsert_heartbeat(&self, heartbeat: &Heartbeat) -> Result<(), String> {
+        self.inner.upsert_heartbeat(heartbeat)
+    }
+
+    fn touch_heartbeat(&self, device_type: DeviceType, device_id: &String, time_epoch: u64) -> Result<(), String> {
+        self.inner.touch_heartbeat(device_type, device_id, time_epoch)
+    }
+
+    fn find_stale_devices(&self, before_epoch: u64) -> Result<Vec<StaleDevice>, String> {
+        self.inner.find_stale_devices(before_epoch)
+    }
+
+    fn count_trackers_per_location(&self) -> Result<Vec<(i32, i64)>, String> {
+        self.inner.count_trackers_per_location()
+    }
+
+    fn find_displays_at_location(&self, location: i32) -> Result<Vec<Display>, String> {
+        self.inner.find_displays_at_location(location)
+    }
+
+    fn find_receivers_at_location(&self, location: i32) -> Result<Vec<Receiver>, String> {
+        self.inner.find_receivers_at_location(location)
+    }
+
+    fn insert_occupancy(&self, location: i32, trackers: i64, time_epoch: u64) -> Result<(), String> {
+        self.inner.insert_occupancy(location, trackers, time_epoch)
+    }
+
+    fn find_occupancy_history(&self, location: i32, from_epoch: u64, to_epoch: u64) -> Result<Vec<(u64, i64)>, String> {
+        self.inner.find_occupancy_history(location, from_epoch, to_epoch)
+    }
+
+    fn ping(&self, timeout: Duration) -> Result<(), String> {
+        self.inner.ping(timeout)
+    }
+
+    fn list_orders(&self) -> Result<Vec<OrderSummary>, String> {
+        self.inner.list_orders()
+    }
+
+    fn execute_statements(&self, statements: &Vec<String>) -> Result<(), String> {
+        let res = self.inner.execute_statements(statements);
+        self.clear();
+        res
+    }
+
+    fn migrate(&self, time_epoch: u64) -> Result<Vec<u32>, String> {
+        let res = self.inner.migrate(time_epoch);
+        self.clear();
+        res
+    }
+}
+
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistance::memory::MemoryStore;
+
+    /**
+     * A cached memory store with a display and a receiver at a location, a tracker interested in sport
+     * and an order paying for a sport video, and the memory store behind it
+     */
+    fn cached_store(ttl: Duration) -> (Arc<MemoryStore>, CachedStore) {
+        let memory = Arc::new(MemoryStore::new());
+        let location = memory.add_location("location1");
+        let interest = memory.add_interest("sport");
+        memory.insert_display(location).unwrap();
+        memory.insert_receiver(&Receiver {id: "receiver1".to_owned(), location}).unwrap();
+        memory.insert_tracker(&Tracker {id: "tracker1".to_owned(), location: None}).unwrap();
+        memory.set_tracker_interest("tracker1", interest, 10.0);
+        memory.add_video(AdvertVideo {interest, url: "video1".to_owned(), length_sec: 10});
+        memory.add_user("email@example.com", "agency1");
+        memory.add_order(Order {id: "1".to_owned(), credits: 100, user: "email@example.com".to_owned()});
+        memory.add_campaign(AdvertisementOrder {video: 1, order: "1".to_owned(), start_time_epoch: 0, end_time_epoch: 2147483647});
+        let cached = CachedStore::new(memory.clone(), ttl);
+        (memory, cached)
+    }
+
+    fn rate_card() -> RateCard {
+        RateCard {id: 0, location: None, start_minute: 0, end_minute: 1440, credits_per_second: 1.0, min_credits: 1, rounding: Rounding::Down}
+    }
+
+    #[test]
+    fn eligible_videos_are_invalidated_by_drawn_credits_unittest() {
+        let (memory, cached) = cached_store(Duration::from_secs(60));
+        assert_eq!(cached.find_eligible_videos_by_interest(vec![1, 2], 100).unwrap().unwrap().len(), 1);
+        memory.add_order(Order {id: "1".to_owned(), credits: 0, user: "email@example.com".to_owned()});
+        assert_eq!(cached.find_eligible_videos_by_interest(vec![1], 100).unwrap().unwrap().len(), 1, "a change behind the back of the cache should not be seen until it expires");
+
+        cached.void_plays(&PlaySelection::Single(1), &None, 100).unwrap();
+        assert!(cached.find_eligible_videos_by_interest(vec![1], 100).unwrap().is_none(), "an order out of credits should have no eligible videos");
+
+        memory.add_order(Order {id: "1".to_owned(), credits: 100, user: "email@example.com".to_owned()});
+        let played = PlayedVideo {video: 1, display: 1, order: "1".to_owned(), time_epoch: 100, length_sec: 10, complete: true, credits: 1};
+        cached.register_play(&played, &rate_card(), &"signature".to_owned()).unwrap();
+        assert_eq!(cached.find_eligible_videos_by_interest(vec![1], 100).unwrap().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn cached_videos_are_left_out_after_their_campaign_ends_unittest() {
+        let memory = Arc::new(MemoryStore::new());
+        let interest = memory.add_interest("sport");
+        memory.add_video(AdvertVideo {interest, url: "video1".to_owned(), length_sec: 10});
+        memory.add_user("email@example.com", "agency1");
+        memory.add_order(Order {id: "1".to_owned(), credits: 100, user: "email@example.com".to_owned()});
+        memory.add_campaign(AdvertisementOrder {video: 1, order: "1".to_owned(), start_time_epoch: 0, end_time_epoch: 200});
+        let cached = CachedStore::new(memory.clone(), Duration::from_secs(60));
+        assert_eq!(cached.find_eligible_videos_by_interest(vec![interest], 100).unwrap().unwrap().len(), 1);
+        assert_eq!(cached.find_eligible_videos_by_interest(vec![interest], 200).unwrap().unwrap().len(), 1, "the campaign should run until its end");
+        assert!(cached.find_eligible_videos_by_interest(vec![interest], 201).unwrap().is_none(), "a cached video should not be handed out after its campaign ended");
+    }
+
+    #[test]
+    fn values_expire_after_their_time_to_live_unittest() {
+        let (memory, cached) = cached_store(Duration::from_secs(0));
+        assert_eq!(cached.find_eligible_videos_by_interest(vec![1], 100).unwrap().unwrap().len(), 1);
+        memory.add_order(Order {id: "1".to_owned(), credits: 0, user: "email@example.com".to_owned()});
+        assert!(cached.find_eligible_videos_by_interest(vec![1], 100).unwrap().is_none());
+        assert_eq!(cached.get_display_location(1), Some(1));
+        assert!(cached.get_display_by_id(2).unwrap().is_none());
+    }
+
+    #[test]
+    fn values_loaded_before_an_invalidation_are_not_cached_unittest() {
+        let cache: TtlCache<i32, i32> = TtlCache::new("test", Duration::from_secs(60));
+        let generation = cache.get(&1).unwrap_err();
+        cache.clear();
+        cache.insert(generation, 1, 10);
+        assert!(cache.get(&1).is_err());
+        let generation = cache.get(&1).unwrap_err();
+        cache.insert(generation, 1, 10);
+        assert_eq!(cache.get(&1), Ok(10));
+    }
+}
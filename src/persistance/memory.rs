@@ -0,0 +1,667 @@
+/**
+ * In-memory store, for running the tests without a database.
+ *
+ * Behaves like the MySQL store for every query. Data that the app only reads, locations, interests,
+ * videos, users, orders, campaigns, rate cards and webhooks, is added with the add_* functions.
+ */
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::model::*;
+use super::store::Store;
+
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: Mutex<Tables>
+}
+
+#[derive(Default)]
+struct Tables {
+    locations: Vec<String>,
+    interests: Vec<String>,
+    //tracker id -> location and registration time
+    trackers: BTreeMap<String, (Option<i32>, Option<u64>)>,
+    tracker_interests: Vec<(String, i32, f32)>,
+    receivers: BTreeMap<String, i32>,
+    displays: Vec<i32>,
+    videos: Vec<AdvertVideo>,
+    //user email -> agency
+    users: BTreeMap<String, String>,
+    orders: BTreeMap<String, Order>,
+    campaigns: Vec<AdvertisementOrder>,
+    plays: Vec<Play>,
+    ledger: Vec<LedgerEntry>,
+    rate_cards: Vec<RateCard>,
+    redeemed_play_tokens: HashSet<String>,
+    webhooks: Vec<(Webhook, bool)>,
+    deliveries: Vec<Delivery>,
+    campaign_events: HashSet<(i32, String, &'static str)>,
+    logs: Vec<LogEntry>,
+    heartbeats: BTreeMap<(&'static str, String), Heartbeat>,
+    //location, time, trackers
+    occupancy_history: Vec<(i32, u64, i64)>,
+    //tracker, receiver -> time the receiver last saw the tracker
+    presences: BTreeMap<(String, String), u64>
+}
+
+struct Play {
+    played: PlayedVideo,
+    voided: bool
+}
+
+struct Delivery {
+    id: i32,
+    webhook: i32,
+    event: WebhookEvent,
+    payload: String,
+    status: DeliveryStatus,
+    attempts: u32,
+    next_attempt_epoch: u64
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    fn tables(&self) -> MutexGuard<Tables> {
+        self.tables.lock().unwrap()
+    }
+
+    /**
+     * Adds a location, returns its id
+     */
+    pub fn add_location(&self, name: &str) -> i32 {
+        let mut tables = self.tables();
+        tables.locations.push(name.to_owned());
+        tables.locations.len() as i32
+    }
+
+    /**
+     * Adds an interest, returns its id
+     */
+    pub fn add_interest(&self, name: &str) -> i32 {
+        let mut tables = self.tables();
+        tables.interests.push(name.to_owned());
+        tables.interests.len() as i32
+    }
+
+    /**
+     * Sets the weight of an interest of a tracker
+     */
+    pub fn set_tracker_interest(&self, tracker: &str, interest: i32, weight: f32) {
+        let mut tables = self.tables();
+        tables.tracker_interests.retain(|(t, i, _)| !(t == tracker && *i == interest));
+        tables.tracker_interests.push((tracker.to_owned(), interest, weight));
+    }
+
+    /**
+     * Adds an advertisement video, returns its id
+     */
+    pub fn add_video(&self, video: AdvertVideo) -> i32 {
+        let mut tables = self.tables();
+        tables.videos.push(video);
+        tables.videos.len() as i32
+    }
+
+    /**
+     * Adds a user of an agency, orders are placed by users
+     */
+    pub fn add_user(&self, email: &str, agency: &str) {
+        self.tables().users.insert(email.to_owned(), agency.to_owned());
+    }
+
+    /**
+     * Adds an order, or replaces the order with the same id
+     */
+    pub fn add_order(&self, order: Order) {
+        self.tables().orders.insert(order.id.clone(), order);
+    }
+
+    /**
+     * Adds a campaign, paying for a video with an order
+     */
+    pub fn add_campaign(&self, campaign: AdvertisementOrder) {
+        self.tables().campaigns.push(campaign);
+    }
+
+    /**
+     * Adds a rate card, its id is the one given
+     */
+    pub fn add_rate_card(&self, rate_card: RateCard) {
+        self.tables().rate_cards.push(rate_card);
+    }
+
+    /**
+     * Adds an active webhook, its id is the one given
+     */
+    pub fn add_webhook(&self, webhook: Webhook) {
+        self.tables().webhooks.push((webhook, true));
+    }
+}
+
+impl Tables {
+    /**
+     * The balance of an order, starting its ledger or recording an adjustment as in the MySQL store
+     */
+    fn reconcile_ledger(&mut self, order_id: &String, time_epoch: u64) -> i32 {
+        let balance = self.orders.get(order_id).map_or(0, |o| o.credits);
+        let ledger_balance = self.ledger.iter().rev().find(|e| &e.order == order_id).map(|e| e.balance_after);
+        match ledger_balance {
+            None => self.add_ledger_entry(balance_entry(order_id, time_epoch, LedgerEntryType::Opening, balance, balance, "opening balance")),
+            Some(val) if val != balance => self.add_ledger_entry(balance_entry(order_id, time_epoch, LedgerEntryType::Adjustment, balance - val, balance,
+                "balance changed outside of the ledger")),
+            Some(_) => ()
+        };
+        balance
+    }
+
+    /**
+     * Appends an entry to the ledger, numbering it
+     */
+    fn add_ledger_entry(&mut self, entry: LedgerEntry) {
+        let id = self.ledger.len() as i32 + 1;
+        self.ledger.push(LedgerEntry {id, ..entry});
+    }
+
+    fn spent_credits(&self, order_id: &String) -> i32 {
+        self.plays.iter().filter(|p| &p.played.order == order_id && !p.voided).map(|p| p.played.credits).sum()
+    }
+}
+
+/**
+ * A ledger entry not tied to a play
+ */
+fn balance_entry(order: &String, time_epoch: u64, entry_type: LedgerEntryType, amount: i32, balance_after: i32, description: &str) -> LedgerEntry {
+    LedgerEntry {id: 0, order: order.clone(), time_epoch, entry_type, amount, balance_after, played_video: None, rate_card: None,
+        credits_per_second: None, description: Some(description.to_owned())}
+}
+
+fn now_epoch() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(e) => panic!("ERROR GETTING SYSTEM TIME!, ERROR\n{}", e),
+    }
+}
+
+impl Store for MemoryStore {
+    fn unregister_tracker_from_receiver(&self, receiver_id: &String, tracker_id: &String) -> Result<bool, String> {
+        let mut tables = self.tables();
+        let tables = &mut *tables;
+        tables.presences.remove(&(tracker_id.clone(), receiver_id.clone()));
+        let location = match (tables.trackers.get(tracker_id), tables.receivers.get(receiver_id)) {
+            (Some((Some(location), _)), Some(receiver_location)) if location == receiver_location => *location,
+            _ => return Ok(false)
+        };
+        let receivers = &tables.receivers;
+        if tables.presences.keys().any(|(tracker, receiver)| tracker == tracker_id && receivers.get(receiver) == Some(&location)) {
+            return Ok(false);
+        }
+        if let Some(tracker) = tables.trackers.get_mut(tracker_id) {
+            tracker.0 = None;
+        }
+        Ok(true)
+    }
+
+    fn register_tracker_to_receiver(&self, receiver_id: &String, tracker_id: &String) -> Result<(), String> {
+        let mut tables = self.tables();
+        let tables = &mut *tables;
+        let location = match tables.receivers.get(receiver_id) {
+            Some(location) => *location,
+            None => return Ok(())
+        };
+        if let Some(tracker) = tables.trackers.get_mut(tracker_id) {
+            *tracker = (Some(location), Some(now_epoch()));
+            let receivers = &tables.receivers;
+            tables.presences.retain(|(tracker, receiver), _| tracker != tracker_id || receivers.get(receiver) == Some(&location));
+            tables.presences.insert((tracker_id.clone(), receiver_id.clone()), now_epoch());
+        }
+        Ok(())
+    }
+
+    fn expire_trackers(&self, before_epoch: u64) -> Result<u64, String> {
+        let mut tables = self.tables();
+        let tables = &mut *tables;
+        let mut expired = HashSet::new();
+        for (id, tracker) in tables.trackers.iter_mut().filter(|(_, t)| t.0.is_some() && t.1.unwrap_or(0) < before_epoch) {
+            tracker.0 = None;
+            expired.insert(id.clone());
+        }
+        tables.presences.retain(|(tracker, _), _| !expired.contains(tracker));
+        Ok(expired.len() as u64)
+    }
+
+    fn location_exists(&self, location: i32) -> Result<bool, String> {
+        Ok(location >= 1 && location as usize <= self.tables().locations.len())
+    }
+
+    fn insert_tracker(&self, tracker: &Tracker) -> Result<(), String> {
+        let mut tables = self.tables();
+        if tables.trackers.contains_key(&tracker.id) {
+            return Err(format!("duplicate tracker {}", tracker.id));
+        }
+        tables.trackers.insert(tracker.id.clone(), (tracker.location, tracker.location.map(|_| now_epoch())));
+        Ok(())
+    }
+
+    fn insert_receiver(&self, receiver: &Receiver) -> Result<(), String> {
+        let mut tables = self.tables();
+        if tables.receivers.contains_key(&receiver.id) {
+            return Err(format!("duplicate receiver {}", receiver.id));
+        }
+        tables.receivers.insert(receiver.id.clone(), receiver.location);
+        Ok(())
+    }
+
+    fn insert_display(&self, location: i32) -> Result<i32, String> {
+        let mut tables = self.tables();
+        tables.displays.push(location);
+        Ok(tables.displays.len() as i32)
+    }
+
+    fn get_tracker_by_id(&self, tracker_id: &String) -> Result<Option<Tracker>, String> {
+        Ok(self.tables().trackers.get(tracker_id).map(|t| Tracker {id: tracker_id.clone(), location: t.0}))
+    }
+
+    fn get_receiver_by_id(&self, receiver_id: &String) -> Result<Option<Receiver>, String> {
+        Ok(self.tables().receivers.get(receiver_id).map(|location| Receiver {id: receiver_id.clone(), location: *location}))
+    }
+
+    fn get_display_location(&self, display_id: i32) -> Option<i32> {
+        self.get_display_by_id(display_id).ok().and_then(|d| d).map(|d| d.location)
+    }
+
+    fn get_display_by_id(&self, display_id: i32) -> Result<Option<Display>, String> {
+        match display_id {
+            id if id < 1 => Ok(None),
+            id => Ok(self.tables().displays.get(id as usize - 1).map(|location| Display {id, location: *location}))
+        }
+    }
+
+    fn get_tracker_interests(&self, tracker_id: &String) -> Result<Vec<(i32, f32)>, String> {
+        Ok(self.tables().tracker_interests.iter()
+            .filter(|(tracker, _, _)| tracker == tracker_id)
+            .map(|(_, interest, weight)| (*interest, *weight))
+            .collect())
+    }
+
+    fn find_present_trackers(&self) -> Result<Vec<PresentTracker>, String> {
+        let tables = self.tables();
+        Ok(tables.trackers.iter()
+            .filter_map(|(id, tracker)| tracker.0.map(|location| PresentTracker {
+                id: id.clone(),
+                location,
+                interests: tables.tracker_interests.iter()
+                    .filter(|(tracker, _, _)| tracker == id)
+                    .map(|(_, interest, weight)| (*interest, *weight))
+                    .collect()
+            }))
+            .collect())
+    }
+
+    fn get_advertisement_video_by_id(&self, video_id: i32) -> Result<Option<AdvertVideo>, String> {
+        match video_id {
+            id if id < 1 => Ok(None),
+            id => Ok(self.tables().videos.get(id as usize - 1).cloned())
+        }
+    }
+
+    fn get_order_by_id(&self, order_id: &String) -> Result<Option<Order>, String> {
+        Ok(self.tables().orders.get(order_id).cloned())
+    }
+
+    fn get_advertisement_order(&self, video_id: i32, order_id: &String) -> Result<Option<AdvertisementOrder>, String> {
+        Ok(self.tables().campaigns.iter().find(|c| c.video == video_id && &c.order == order_id).cloned())
+    }
+
+    fn register_play(&self, played: &PlayedVideo, rate_card: &RateCard, play_token: &String) -> Result<Option<i32>, String> {
+        let mut tables = self.tables();
+        if !tables.redeemed_play_tokens.insert(play_token.clone()) {
+            return Ok(None);
+        }
+        let balance = tables.reconcile_ledger(&played.order, played.time_epoch);
+        tables.plays.push(Play {played: played.clone(), voided: false});
+        let played_id = tables.plays.len() as i32;
+        if let Some(order) = tables.orders.get_mut(&played.order) {
+            order.credits -= played.credits;
+        }
+        tables.add_ledger_entry(LedgerEntry {id: 0, order: played.order.clone(), time_epoch: played.time_epoch, entry_type: LedgerEntryType::Debit,
+            amount: -played.credits, balance_after: balance - played.credits, played_video: Some(played_id),
+            rate_card: Some(rate_card.id).filter(|id| *id != 0), credits_per_second: Some(rate_card.credits_per_second), description: None});
+        Ok(Some(balance))
+    }
+
+    fn get_play_voided(&self, played_video_id: i32) -> Result<Option<bool>, String> {
+        match played_video_id {
+            id if id < 1 => Ok(None),
+            id => Ok(self.tables().plays.get(id as usize - 1).map(|p| p.voided))
+        }
+    }
+
+    fn void_plays(&self, selection: &PlaySelection, reason: &Option<String>, time_epoch: u64) -> Result<Vec<VoidedPlay>, String> {
+        let mut tables = self.tables();
+        let plays: Vec<VoidedPlay> = tables.plays.iter().enumerate()
+            .map(|(i, p)| (i as i32 + 1, p))
+            .filter(|(id, p)| !p.voided && match selection {
+                PlaySelection::Single(single) => id == single,
+                PlaySelection::Display {display, from_epoch, to_epoch} =>
+                    p.played.display == *display && p.played.time_epoch >= *from_epoch && p.played.time_epoch <= *to_epoch
+            })
+            .map(|(id, p)| VoidedPlay {id, order: p.played.order.clone(), credits: p.played.credits})
+            .collect();
+        let mut orders: Vec<&String> = plays.iter().map(|p| &p.order).collect();
+        orders.sort();
+        orders.dedup();
+        for order_id in orders {
+            let mut balance = tables.reconcile_ledger(order_id, time_epoch);
+            for play in plays.iter().filter(|p| &p.order == order_id) {
+                tables.plays[play.id as usize - 1].voided = true;
+                balance += play.credits;
+                tables.add_ledger_entry(LedgerEntry {played_video: Some(play.id),
+                    ..balance_entry(order_id, time_epoch, LedgerEntryType::Refund, play.credits, balance, reason.as_deref().unwrap_or("voided play"))});
+            }
+            if let Some(order) = tables.orders.get_mut(order_id) {
+                order.credits = balance;
+            }
+        }
+        Ok(plays)
+    }
+
+    fn find_ledger_entries(&self, order_id: &String) -> Result<Vec<LedgerEntry>, String> {
+        Ok(self.tables().ledger.iter().filter(|e| &e.order == order_id).cloned().collect())
+    }
+
+    fn get_rate_cards_for_location(&self, location: i32) -> Result<Vec<RateCard>, String> {
+        Ok(self.tables().rate_cards.iter().filter(|r| r.location.map_or(true, |l| l == location)).cloned().collect())
+    }
+
+    fn find_eligible_videos_by_interest(&self, interests: Vec<i32>, time_epoch: u64) -> Result<Option<Vec<AdvertVideoOrder>>, String> {
+        let tables = self.tables();
+        let videos: Vec<AdvertVideoOrder> = tables.campaigns.iter()
+            .filter(|c| c.start_time_epoch <= time_epoch && c.end_time_epoch >= time_epoch)
+            .filter_map(|c| {
+                let video = tables.videos.get((c.video - 1) as usize).filter(|v| interests.contains(&v.interest))?;
+                let order = tables.orders.get(&c.order).filter(|o| o.credits > 0)?;
+                let campaigns = tables.campaigns.iter().filter(|other| other.order == c.order);
+                let budget = OrderBudget {
+                    remaining_credits: order.credits,
+                    spent_credits: tables.spent_credits(&order.id),
+                    start_epoch: campaigns.clone().map(|other| other.start_time_epoch).min().unwrap_or(0),
+                    end_epoch: campaigns.map(|other| other.end_time_epoch).max().unwrap_or(0)
+                };
+                Some(AdvertVideoOrder {video_id: c.video, interest: video.interest, url: video.url.clone(), length_sec: video.length_sec, order: order.id.clone(), budget})
+            })
+            .collect();
+        Ok(Some(videos).filter(|v| !v.is_empty()))
+    }
+
+    fn find_webhooks_for_order(&self, order_id: &String) -> Result<Vec<Webhook>, String> {
+        let tables = self.tables();
+        let agency = match tables.orders.get(order_id).and_then(|o| tables.users.get(&o.user)) {
+            Some(agency) => agency,
+            None => return Ok(vec![])
+        };
+        Ok(tables.webhooks.iter().filter(|(w, active)| *active && &w.agency == agency).map(|(w, _)| w.clone()).collect())
+    }
+
+    fn insert_webhook_delivery(&self, webhook_id: i32, event: WebhookEvent, _order_id: &String, payload: &String, time_epoch: u64) -> Result<(), String> {
+        let mut tables = self.tables();
+        let id = tables.deliveries.len() as i32 + 1;
+        tables.deliveries.push(Delivery {id, webhook: webhook_id, event, payload: payload.clone(), status: DeliveryStatus::Pending, attempts: 0, next_attempt_epoch: time_epoch});
+        Ok(())
+    }
+
+    fn find_due_webhook_deliveries(&self, time_epoch: u64, limit: u32) -> Result<Vec<WebhookDelivery>, String> {
+        let tables = self.tables();
+        let mut due: Vec<&Delivery> = tables.deliveries.iter().filter(|d| d.status == DeliveryStatus::Pending && d.next_attempt_epoch <= time_epoch).collect();
+        due.sort_by_key(|d| (d.next_attempt_epoch, d.id));
+        Ok(due.into_iter()
+            .filter_map(|d| tables.webhooks.iter().find(|(w, _)| w.id == d.webhook).map(|(w, _)| WebhookDelivery {
+                id: d.id, url: w.url.clone(), secret: w.secret.clone(), event: d.event, payload: d.payload.clone(), attempts: d.attempts
+            }))
+            .take(limit as usize)
+            .collect())
+    }
+
+    fn update_webhook_delivery(&self, delivery_id: i32, status: DeliveryStatus, attempts: u32, next_attempt_epoch: u64,
+                               _response_code: Option<u16>, _error: Option<String>, _time_epoch: u64) -> Result<(), String> {
+        if let Some(delivery) = self.tables().deliveries.iter_mut().find(|d| d.id == delivery_id) {
+            delivery.status = status;
+            delivery.attempts = attempts;
+            delivery.next_attempt_epoch = next_attempt_epoch;
+        }
+        Ok(())
+    }
+
+    fn find_unrecorded_campaign_events(&self, after_epoch: u64, until_epoch: u64) -> Result<Vec<(AdvertisementOrder, WebhookEvent)>, String> {
+        let tables = self.tables();
+        let unrecorded = |c: &AdvertisementOrder, time_epoch: u64, event: WebhookEvent|
+            time_epoch > after_epoch && time_epoch <= until_epoch && !tables.campaign_events.contains(&(c.video, c.order.clone(), event.as_str()));
+        let started = tables.campaigns.iter().filter(|c| unrecorded(c, c.start_time_epoch, WebhookEvent::CampaignStarted))
+            .map(|c| (c.clone(), WebhookEvent::CampaignStarted));
+        let ended = tables.campaigns.iter().filter(|c| unrecorded(c, c.end_time_epoch, WebhookEvent::CampaignEnded))
+            .map(|c| (c.clone(), WebhookEvent::CampaignEnded));
+        Ok(started.chain(ended).collect())
+    }
+
+    fn record_campaign_event(&self, campaign: &AdvertisementOrder, event: WebhookEvent, _time_epoch: u64) -> Result<bool, String> {
+        Ok(self.tables().campaign_events.insert((campaign.video, campaign.order.clone(), event.as_str())))
+    }
+
+    fn insert_log_entries(&self, entries: &Vec<LogEntry>) -> Result<(), String> {
+        self.tables().logs.extend(entries.iter().cloned());
+        Ok(())
+    }
+
+    fn find_log_entries(&self, filter: &LogFilter, limit: u32) -> Result<Vec<LogEntry>, String> {
+        let tables = self.tables();
+        let mut entries: Vec<&LogEntry> = tables.logs.iter()
+            .filter(|e| filter.device.as_ref().map_or(true, |d| e.device.as_ref() == Some(d)))
+            .filter(|e| filter.severity.map_or(true, |s| e.severity == s))
+            .filter(|e| filter.min_severity.map_or(true, |s| e.severity >= s))
+            .filter(|e| filter.from_epoch.map_or(true, |from| e.time_epoch >= from))
+            .filter(|e| filter.to_epoch.map_or(true, |to| e.time_epoch <= to))
+            .collect();
+        //Newest first, the last inserted first among entries of the same time
+        entries.reverse();
+        entries.sort_by(|a, b| b.time_epoch.cmp(&a.time_epoch));
+        Ok(entries.into_iter().take(limit as usize).cloned().collect())
+    }
+
+    fn upsert_heartbeat(&self, heartbeat: &Heartbeat) -> Result<(), String> {
+        self.tables().heartbeats.insert((heartbeat.device_type.as_str(), heartbeat.device.clone()), heartbeat.clone());
+        Ok(())
+    }
+
+    fn touch_heartbeat(&self, device_type: DeviceType, device_id: &String, time_epoch: u64) -> Result<(), String> {
+        self.tables().heartbeats.entry((device_type.as_str(), device_id.clone()))
+            .and_modify(|h| h.time_epoch = time_epoch)
+            .or_insert_with(|| Heartbeat {device_type, device: device_id.clone(), time_epoch, status: DeviceStatus::default()});
+        Ok(())
+    }
+
+    fn find_stale_devices(&self, before_epoch: u64) -> Result<Vec<StaleDevice>, String> {
+        let tables = self.tables();
+        let receivers = tables.receivers.keys().map(|id| (DeviceType::Receiver, id.clone()));
+        let displays = (1..=tables.displays.len()).map(|id| (DeviceType::Display, id.to_string()));
+        Ok(receivers.chain(displays)
+            .map(|(device_type, device)| {
+                let last_heartbeat = tables.heartbeats.get(&(device_type.as_str(), device.clone())).cloned();
+                StaleDevice {device_type, device, last_heartbeat}
+            })
+            .filter(|d| d.last_heartbeat.as_ref().map_or(true, |h| h.time_epoch < before_epoch))
+            .collect())
+    }
+
+    fn count_trackers_per_location(&self) -> Result<Vec<(i32, i64)>, String> {
+        let mut counts: BTreeMap<i32, i64> = BTreeMap::new();
+        for location in self.tables().trackers.values().filter_map(|t| t.0) {
+            *counts.entry(location).or_insert(0) += 1;
+        }
+        Ok(counts.into_iter().collect())
+    }
+
+    fn find_displays_at_location(&self, location: i32) -> Result<Vec<Display>, String> {
+        Ok(self.tables().displays.iter().enumerate()
+            .filter(|(_, l)| **l == location)
+            .map(|(i, l)| Display {id: i as i32 + 1, location: *l})
+            .collect())
+    }
+
+    fn find_receivers_at_location(&self, location: i32) -> Result<Vec<Receiver>, String> {
+        Ok(self.tables().receivers.iter()
+            .filter(|(_, l)| **l == location)
+            .map(|(id, l)| Receiver {id: id.clone(), location: *l})
+            .collect())
+    }
+
+    fn insert_occupancy(&self, location: i32, trackers: i64, time_epoch: u64) -> Result<(), String> {
+        self.tables().occupancy_history.push((location, time_epoch, trackers));
+        Ok(())
+    }
+
+    fn find_occupancy_history(&self, location: i32, from_epoch: u64, to_epoch: u64) -> Result<Vec<(u64, i64)>, String> {
+        let tables = self.tables();
+        let mut records: Vec<(u64, i64)> = tables.occupancy_history.iter()
+            .filter(|(l, _, _)| *l == location)
+            .map(|(_, time, trackers)| (*time, *trackers))
+            .collect();
+        //Stable, records of the same time stay in the order they were recorded
+        records.sort_by_key(|r| r.0);
+        let before = records.iter().rposition(|r| r.0 <= from_epoch).unwrap_or(0);
+        Ok(records.into_iter().skip(before).filter(|r| r.0 <= from_epoch || r.0 < to_epoch).collect())
+    }
+
+    fn ping(&self, _timeout: Duration) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn list_orders(&self) -> Result<Vec<OrderSummary>, String> {
+        let tables = self.tables();
+        Ok(tables.orders.values().map(|order| OrderSummary {
+            order: order.clone(),
+            plays: tables.plays.iter().filter(|p| p.played.order == order.id && !p.voided).count() as i64,
+            spent_credits: tables.spent_credits(&order.id) as i64
+        }).collect())
+    }
+
+    fn execute_statements(&self, _statements: &Vec<String>) -> Result<(), String> {
+        Err("sql statements can not be run against the in-memory store".to_owned())
+    }
+
+    fn migrate(&self, _time_epoch: u64) -> Result<Vec<u32>, String> {
+        Ok(vec![])
+    }
+}
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store_with_order(credits: i32) -> MemoryStore {
+        let store = MemoryStore::new();
+        let location = store.add_location("location1");
+        let interest = store.add_interest("sport");
+        store.insert_display(location).unwrap();
+        store.insert_receiver(&Receiver {id: "receiver1".to_owned(), location}).unwrap();
+        store.insert_tracker(&Tracker {id: "tracker1".to_owned(), location: None}).unwrap();
+        store.set_tracker_interest("tracker1", interest, 10.0);
+        store.add_video(AdvertVideo {interest, url: "video1".to_owned(), length_sec: 10});
+        store.add_user("email@example.com", "agency1");
+        store.add_order(Order {id: "1".to_owned(), credits, user: "email@example.com".to_owned()});
+        store.add_campaign(AdvertisementOrder {video: 1, order: "1".to_owned(), start_time_epoch: 0, end_time_epoch: 2147483647});
+        store
+    }
+
+    fn play(credits: i32) -> PlayedVideo {
+        PlayedVideo {video: 1, display: 1, order: "1".to_owned(), time_epoch: 100, length_sec: 10, complete: true, credits}
+    }
+
+    #[test]
+    fn trackers_are_counted_at_their_location_unittest() {
+        let store = store_with_order(100);
+        assert_eq!(store.count_trackers_per_location().unwrap(), vec![]);
+        store.register_tracker_to_receiver(&"receiver1".to_owned(), &"tracker1".to_owned()).unwrap();
+        assert_eq!(store.count_trackers_per_location().unwrap(), vec![(1, 1)]);
+        assert!(store.unregister_tracker_from_receiver(&"receiver1".to_owned(), &"tracker1".to_owned()).unwrap());
+        assert_eq!(store.count_trackers_per_location().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn trackers_leave_a_location_when_every_receiver_lost_them_unittest() {
+        let store = store_with_order(100);
+        let location2 = store.add_location("location2");
+        store.insert_receiver(&Receiver {id: "receiver2".to_owned(), location: 1}).unwrap();
+        store.insert_receiver(&Receiver {id: "receiver3".to_owned(), location: location2}).unwrap();
+        store.insert_tracker(&Tracker {id: "tracker2".to_owned(), location: Some(1)}).unwrap();
+        let location = |tracker: &str| store.get_tracker_by_id(&tracker.to_owned()).unwrap().unwrap().location;
+        store.register_tracker_to_receiver(&"receiver1".to_owned(), &"tracker1".to_owned()).unwrap();
+        store.register_tracker_to_receiver(&"receiver2".to_owned(), &"tracker1".to_owned()).unwrap();
+        assert!(!store.unregister_tracker_from_receiver(&"receiver1".to_owned(), &"tracker1".to_owned()).unwrap());
+        assert_eq!(location("tracker1"), Some(1), "receiver2 still sees the tracker");
+        assert!(store.unregister_tracker_from_receiver(&"receiver2".to_owned(), &"tracker1".to_owned()).unwrap());
+        assert_eq!(location("tracker1"), None);
+
+        store.register_tracker_to_receiver(&"receiver1".to_owned(), &"tracker1".to_owned()).unwrap();
+        store.register_tracker_to_receiver(&"receiver3".to_owned(), &"tracker1".to_owned()).unwrap();
+        assert!(!store.unregister_tracker_from_receiver(&"receiver1".to_owned(), &"tracker1".to_owned()).unwrap(),
+            "the tracker has moved to the location of receiver3");
+        assert!(store.unregister_tracker_from_receiver(&"receiver3".to_owned(), &"tracker1".to_owned()).unwrap());
+
+        assert!(store.unregister_tracker_from_receiver(&"receiver1".to_owned(), &"tracker2".to_owned()).unwrap(),
+            "a tracker registered without presences should be unregistered by any receiver at its location");
+        assert_eq!(location("tracker2"), None);
+    }
+
+    #[test]
+    fn plays_are_debited_and_refunded_in_the_ledger_unittest() {
+        let store = store_with_order(100);
+        assert_eq!(store.register_play(&play(10), &RateCard {id: 0, location: None, start_minute: 0, end_minute: 1440,
+            credits_per_second: 1.0, min_credits: 1, rounding: Rounding::Down}, &"signature".to_owned()).unwrap(), Some(100));
+        assert_eq!(store.get_order_by_id(&"1".to_owned()).unwrap().unwrap().credits, 90);
+        assert_eq!(store.get_play_voided(1).unwrap(), Some(false));
+
+        let voided = store.void_plays(&PlaySelection::Single(1), &None, 200).unwrap();
+        assert_eq!(voided, vec![VoidedPlay {id: 1, order: "1".to_owned(), credits: 10}]);
+        assert!(store.void_plays(&PlaySelection::Single(1), &None, 200).unwrap().is_empty(), "a play should only be voided once");
+        assert_eq!(store.get_order_by_id(&"1".to_owned()).unwrap().unwrap().credits, 100);
+        let entries: Vec<(LedgerEntryType, i32, i32)> = store.find_ledger_entries(&"1".to_owned()).unwrap().iter()
+            .map(|e| (e.entry_type, e.amount, e.balance_after)).collect();
+        assert_eq!(entries, vec![(LedgerEntryType::Opening, 100, 100), (LedgerEntryType::Debit, -10, 90), (LedgerEntryType::Refund, 10, 100)]);
+    }
+
+    #[test]
+    fn eligible_videos_need_credits_and_a_running_campaign_unittest() {
+        let store = store_with_order(100);
+        assert_eq!(store.find_eligible_videos_by_interest(vec![1], 100).unwrap().unwrap()[0].order, "1");
+        assert!(store.find_eligible_videos_by_interest(vec![2], 100).unwrap().is_none());
+        let empty = store_with_order(0);
+        assert!(empty.find_eligible_videos_by_interest(vec![1], 100).unwrap().is_none());
+    }
+
+    #[test]
+    fn log_entries_are_newest_first_unittest() {
+        let store = MemoryStore::new();
+        let entry = |time_epoch: u64, message: &str| LogEntry {device: Some("d".to_owned()), severity: Severity::Info, firmware: None,
+            time_epoch, message: message.to_owned(), fields: None};
+        store.insert_log_entries(&vec![entry(1, "a"), entry(2, "b"), entry(2, "c")]).unwrap();
+        let messages: Vec<String> = store.find_log_entries(&LogFilter::default(), 2).unwrap().into_iter().map(|e| e.message).collect();
+        assert_eq!(messages, vec!["c", "b"]);
+    }
+
+    #[test]
+    fn occupancy_history_starts_at_the_last_record_before_unittest() {
+        let store = store_with_order(100);
+        for (location, time_epoch, trackers) in vec![(1, 50, 2), (1, 130, 4), (2, 100, 7), (1, 150, 0), (1, 150, 3)] {
+            store.insert_occupancy(location, trackers, time_epoch).unwrap();
+        }
+        assert_eq!(store.find_occupancy_history(1, 100, 150).unwrap(), vec![(50, 2), (130, 4)]);
+        assert_eq!(store.find_occupancy_history(1, 0, 200).unwrap(), vec![(50, 2), (130, 4), (150, 0), (150, 3)]);
+        assert_eq!(store.find_occupancy_history(1, 150, 200).unwrap(), vec![(150, 3)], "the last of the records at the same time should count");
+    }
+}
@@ -0,0 +1,214 @@
+/**
+ * Versioned schema migrations, embedded in the binary.
+ *
+ * Migrations are applied in version order, each at most once. The applied versions are recorded
+ * in the schema_version table together with a checksum of their sql, so a migration edited after it
+ * was applied is found instead of silently leaving databases with different schemas.
+ *
+ * New migrations are added as migrations/<version>_<name>.sql and listed in MIGRATIONS,
+ * applied migrations must never be changed. SQLite and PostgreSQL databases have their own migrations, in migrations/sqlite
+ * and SQLITE_MIGRATIONS and in migrations/postgres and POSTGRES_MIGRATIONS, a schema change is added to all of them.
+ */
+use log::info;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug)]
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str
+}
+
+impl Migration {
+    /**
+     * The hex encoded SHA-256 of the sql, line endings normalized so that checkouts with crlf line endings agree
+     */
+    pub fn checksum(&self) -> String {
+        Sha256::digest(self.sql.replace("\r\n", "\n").as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /**
+     * The statements of the sql, see split_statements
+     */
+    pub fn statements(&self) -> Vec<String> {
+        split_statements(self.sql)
+    }
+}
+
+/**
+ * Every migration, in version order
+ */
+pub static MIGRATIONS: &[Migration] = &[
+    Migration {version: 1, name: "initial_schema", sql: include_str!("../../migrations/0001_initial_schema.sql")},
+    Migration {version: 2, name: "device_logs", sql: include_str!("../../migrations/0002_device_logs.sql")},
+    Migration {version: 3, name: "device_heartbeats", sql: include_str!("../../migrations/0003_device_heartbeats.sql")},
+    Migration {version: 4, name: "play_billing", sql: include_str!("../../migrations/0004_play_billing.sql")},
+    Migration {version: 5, name: "webhooks", sql: include_str!("../../migrations/0005_webhooks.sql")},
+    Migration {version: 6, name: "credit_ledger", sql: include_str!("../../migrations/0006_credit_ledger.sql")},
+    Migration {version: 7, name: "tracker_registration_time", sql: include_str!("../../migrations/0007_tracker_registration_time.sql")},
+    Migration {version: 8, name: "occupancy_history", sql: include_str!("../../migrations/0008_occupancy_history.sql")},
+    Migration {version: 9, name: "tracker_presence", sql: include_str!("../../migrations/0009_tracker_presence.sql")},
+];
+
+/**
+ * Every SQLite migration, in version order
+ */
+pub static SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration {version: 1, name: "initial_schema", sql: include_str!("../../migrations/sqlite/0001_initial_schema.sql")},
+    Migration {version: 2, name: "occupancy_history", sql: include_str!("../../migrations/sqlite/0002_occupancy_history.sql")},
+    Migration {version: 3, name: "tracker_presence", sql: include_str!("../../migrations/sqlite/0003_tracker_presence.sql")},
+];
+
+/**
+ * Every PostgreSQL migration, in version order
+ */
+pub static POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {version: 1, name: "initial_schema", sql: include_str!("../../migrations/postgres/0001_initial_schema.sql")},
+    Migration {version: 2, name: "occupancy_history", sql: include_str!("../../migrations/postgres/0002_occupancy_history.sql")},
+    Migration {version: 3, name: "tracker_presence", sql: include_str!("../../migrations/postgres/0003_tracker_presence.sql")},
+];
+
+/**
+ * Splits sql into its statements, without comment lines.
+ * Statements are run one at a time, as the error of a failing statement in a multi statement query can be lost.
+ */
+pub fn split_statements(sql: &str) -> Vec<String> {
+    sql.split(';')
+        .map(|statement| statement.lines().filter(|line| !line.trim_start().starts_with("--")).collect::<Vec<&str>>().join("\n"))
+        .map(|statement| statement.trim().to_owned())
+        .filter(|statement| !statement.is_empty())
+        .collect()
+}
+
+pub static CREATE_SCHEMA_VERSION: &'static str = "create table if not exists `schema_version` (`version` integer NOT NULL ,
+`name` varchar(128) NOT NULL ,
+`checksum` varchar(64) NOT NULL ,
+`applied_epoch` integer NOT NULL ,
+ PRIMARY KEY( `version`));";
+
+pub static CREATE_POSTGRES_SCHEMA_VERSION: &'static str = "create table if not exists \"schema_version\" (\"version\" integer NOT NULL ,
+\"name\" varchar(128) NOT NULL ,
+\"checksum\" varchar(64) NOT NULL ,
+\"applied_epoch\" bigint NOT NULL ,
+ PRIMARY KEY( \"version\"));";
+
+//Held while migrating so that instances starting at the same time do not apply the same migration twice
+static MIGRATION_LOCK: &'static str = "iot_schema_migration";
+pub static MIGRATION_LOCK_TIMEOUT_SEC: u32 = 60;
+
+/**
+ * Applies the migrations not yet applied to the database of the connection.
+ * Returns the versions applied, none if the schema was up to date.
+ *
+ * Fails without applying anything if an applied migration has been changed,
+ * or if the database has been migrated by a newer version of the app.
+ *
+ * # Arguments
+ * `conn` - a connection to the database to migrate
+ * `time_epoch` - the time recorded for the applied migrations
+ */
+pub fn migrate(conn: &mut mysql::PooledConn, time_epoch: u64) -> Result<Vec<u32>, String> {
+    let locked: Option<Option<i32>> = conn.first_exec("SELECT GET_LOCK(?, ?)", (MIGRATION_LOCK, MIGRATION_LOCK_TIMEOUT_SEC)).map_err(|e| format!("{}", e))?;
+    if locked != Some(Some(1)) {
+        return Err(format!("could not get the migration lock within {} seconds, is another instance migrating?", MIGRATION_LOCK_TIMEOUT_SEC));
+    }
+    let res = migrate_locked(conn, time_epoch);
+    let _ = conn.first_exec::<_, _, Option<i32>>("SELECT RELEASE_LOCK(?)", (MIGRATION_LOCK,));
+    res
+}
+
+fn migrate_locked(conn: &mut mysql::PooledConn, time_epoch: u64) -> Result<Vec<u32>, String> {
+    let applied = applied_migrations(conn)?;
+    let pending = pending_migrations(MIGRATIONS, &applied)?;
+    let mut versions = vec![];
+    for migration in pending {
+        info!("applying migration {} {}", migration.version, migration.name);
+        //Statements of a failed migration up to the failing one stay applied, mysql can not roll back schema changes
+        for statement in migration.statements() {
+            conn.query(&statement).map_err(|e| format!("migration {} {} failed: {}", migration.version, migration.name, e))?;
+        }
+        conn.prep_exec("INSERT INTO schema_version (version, name, checksum, applied_epoch) values(?, ?, ?, ?)",
+            (migration.version, migration.name, migration.checksum(), time_epoch)).map_err(|e| format!("{}", e))?;
+        versions.push(migration.version);
+    }
+    Ok(versions)
+}
+
+/**
+ * Returns the versions and checksums of the migrations applied to the database of the connection,
+ * creating the schema_version table if it does not exist.
+ */
+pub fn applied_migrations(conn: &mut mysql::PooledConn) -> Result<Vec<(u32, String)>, String> {
+    conn.query(CREATE_SCHEMA_VERSION).map_err(|e| format!("{}", e))?;
+    conn.query("SELECT version, checksum FROM schema_version ORDER BY version")
+        .map(|result| result.map(|x| x.unwrap()).map(mysql::from_row).collect())
+        .map_err(|e| format!("{}", e))
+}
+
+/**
+ * Returns the migrations not yet applied, in version order.
+ * Fails if an applied migration has been changed or is not known.
+ *
+ * # Arguments
+ * `migrations` - every migration, in version order
+ * `applied` - the versions and checksums of the applied migrations
+ */
+pub fn pending_migrations<'a>(migrations: &'a [Migration], applied: &Vec<(u32, String)>) -> Result<Vec<&'a Migration>, String> {
+    for (version, checksum) in applied.iter() {
+        match migrations.iter().find(|m| m.version == *version) {
+            None => return Err(format!("the database has migration {} applied, which this version of the app does not know, was it migrated by a newer version?", version)),
+            Some(m) if &m.checksum() != checksum => return Err(format!("migration {} {} has been changed since it was applied", m.version, m.name)),
+            Some(_) => ()
+        }
+    }
+    Ok(migrations.iter().filter(|m| !applied.iter().any(|(version, _)| *version == m.version)).collect())
+}
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrations_are_in_strictly_increasing_version_order_unittest() {
+        for migrations in vec![MIGRATIONS, SQLITE_MIGRATIONS, POSTGRES_MIGRATIONS] {
+            assert!(migrations.windows(2).all(|w| w[0].version < w[1].version), "migration versions must be unique and in order");
+            assert_eq!(migrations[0].version, 1);
+        }
+    }
+
+    #[test]
+    fn pending_migrations_unittest() {
+        let applied = vec![(1, MIGRATIONS[0].checksum()), (2, MIGRATIONS[1].checksum())];
+        let pending: Vec<u32> = pending_migrations(MIGRATIONS, &applied).unwrap().iter().map(|m| m.version).collect();
+        assert_eq!(pending, MIGRATIONS[2..].iter().map(|m| m.version).collect::<Vec<u32>>());
+        assert_eq!(pending_migrations(MIGRATIONS, &vec![]).unwrap().len(), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn changed_or_unknown_migrations_are_refused_unittest() {
+        let changed = vec![(1, Migration {version: 1, name: "initial_schema", sql: "drop table orders;"}.checksum())];
+        assert!(pending_migrations(MIGRATIONS, &changed).unwrap_err().contains("has been changed"));
+        let unknown = vec![(9999, "checksum".to_owned())];
+        assert!(pending_migrations(MIGRATIONS, &unknown).unwrap_err().contains("does not know"));
+    }
+
+    #[test]
+    fn statements_are_split_without_comments_unittest() {
+        let migration = Migration {version: 1, name: "m", sql: "-- a comment\ncreate table a (id integer);\n\nalter table a add column b integer ;\n-- trailing comment\n"};
+        assert_eq!(migration.statements(), vec!["create table a (id integer)", "alter table a add column b integer"]);
+        assert!(MIGRATIONS.iter().chain(SQLITE_MIGRATIONS.iter()).chain(POSTGRES_MIGRATIONS.iter()).all(|m| !m.statements().is_empty()));
+    }
+
+    #[test]
+    fn checksum_ignores_line_endings_unittest() {
+        let unix = Migration {version: 1, name: "m", sql: "create table a (id integer);\nselect 1;"};
+        let windows = Migration {version: 1, name: "m", sql: "create table a (id integer);\r\nselect 1;"};
+        assert_eq!(unix.checksum(), windows.checksum());
+        assert_eq!(unix.checksum().len(), 64);
+    }
+}
@@ -4,6 +4,53 @@
 #[cfg(test)]
 use mocktopus::macros::*;
 
+/**
+ * Versioned schema migrations
+ */
+pub mod migrations;
+/**
+ * The storage interface of the services, and its MySQL implementation
+ */
+pub mod store;
+/**
+ * In-memory storage for the tests
+ */
+#[cfg(test)]
+pub mod memory;
+/**
+ * SQLite storage
+ */
+pub mod sqlite;
+/**
+ * PostgreSQL storage
+ */
+pub mod postgres;
+/**
+ * Caching of the lookups of video selection, in front of any store
+ */
+pub mod cache;
+
+use std::sync::Arc;
+use crate::config::{DatabaseBackend, DatabaseConfig};
+use store::{MysqlStore, SharedStore};
+
+/**
+ * Opens the store of the configured database
+ *
+ * # Arguments
+ * `database` - the database configuration, its backend is chosen by the scheme of the url
+ */
+pub fn open(database: &DatabaseConfig) -> Result<SharedStore, String> {
+    match database.backend {
+        DatabaseBackend::Mysql => Ok(Arc::new(MysqlStore)),
+        DatabaseBackend::Sqlite => {
+            let path = database.url.splitn(2, "://").nth(1).unwrap_or("");
+            sqlite::SqliteStore::open(path).map(|store| Arc::new(store) as SharedStore)
+        }
+        DatabaseBackend::Postgres => postgres::PostgresStore::open(&database.url, database.pool_min, database.pool_max)
+            .map(|store| Arc::new(store) as SharedStore)
+    }
+}
 
 /**
  * Handles app MySql integation
@@ -11,9 +58,21 @@ use mocktopus::macros::*;
 #[cfg_attr(test, mockable)]
 pub mod db {
     use std::sync::Mutex;
+    use std::time::Duration;
     use lazy_static::lazy_static;
+    use log::{error, trace};
     use crate::model::*;
-    use crate::environment;
+    use crate::config;
+    use crate::metrics;
+
+    /**
+     * The longest a pooled connection waits to connect to the database
+     */
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+    /**
+     * The longest a pooled connection waits for the database to answer or accept a query
+     */
+    const READ_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
 
     /**
      * Database connection pool structure
@@ -26,35 +85,79 @@ pub mod db {
         /**
          * Creates a auto reconnecting pool
          */
-        pub fn get_conn(&self) -> mysql::PooledConn {
-            self.conn.get_conn().unwrap()   
-        } 
-        pub fn new() -> Dbconn {
-            let environment = environment::db_environment_values();
-            let url = format!("mysql://{}:{}@{}/{}", environment.user, environment.pass, environment.host, environment.db_name);
-            let pool = mysql::Pool::new_manual(1, 1, url).expect("error creating pool");
-            Dbconn {
+        pub fn new() -> Result<Dbconn, String> {
+            let database = &config::get().database;
+            let mut opts = mysql::OptsBuilder::from_opts(mysql::Opts::from_url(&database.url).map_err(|e| format!("invalid database url: {}", e))?);
+            opts.tcp_connect_timeout(Some(CONNECT_TIMEOUT))
+                .read_timeout(Some(READ_WRITE_TIMEOUT))
+                .write_timeout(Some(READ_WRITE_TIMEOUT));
+            let pool = mysql::Pool::new_manual(database.pool_min, database.pool_max, opts).map_err(|e| format!("error creating pool: {}", e))?;
+            Ok(Dbconn {
                 conn: pool
-            }
+            })
         }
     }
     
-    //lazy initialized singleton reference to the connection.
-    lazy_static! { static ref DB: Mutex<Dbconn> = Mutex::new(Dbconn::new()) ;}
+    //lazy initialized singleton reference to the connection, the pool is created on first use and retried until it succeeds.
+    lazy_static! { static ref DB: Mutex<Option<Dbconn>> = Mutex::new(None) ;}
 
     /**
-     * Sets the location of a tracker to null of exists by id
-     * 
+     * Returns the connection pool, creating it if it does not exist yet
+     */
+    fn pool() -> Result<mysql::Pool, String> {
+        let mut db = DB.lock().map_err(|e| format!("{}", e))?;
+        if let Some(existing) = &*db {
+            return Ok(existing.conn.clone());
+        }
+        let created = Dbconn::new()?;
+        let pool = created.conn.clone();
+        *db = Some(created);
+        Ok(pool)
+    }
+
+    /**
+     * Gets a connection of the pool
+     */
+    fn get_conn() -> mysql::PooledConn {
+        pool().unwrap().get_conn().unwrap()
+    }
+
+    /**
+     * Records that a receiver lost a tracker, and unregisters the tracker from its location
+     * if no other receiver at the location still sees it.
+     * Returns whether the tracker was unregistered.
+     *
      * # Arguments
+     * `receiver_id` - a String representing a receiver id
      * `tracker_id` - a String representing a tracker id
      */
-    pub fn unregister_tracker(tracker_id: &String) -> Result<(), String> {
-        match DB.lock().unwrap().get_conn().prep_exec("update rfid_tracker set location = null where id = ?", vec![tracker_id]) {
-            Ok(_) => Ok(()),
-            Err(e) => e.print_err_get_mess::<()>()
-        }
+    pub fn unregister_tracker_from_receiver(receiver_id: &String, tracker_id: &String) -> Result<bool, String> {
+        metrics::time_query("unregister_tracker_from_receiver", || {
+            let mut conn = get_conn();
+            let mut transaction = match conn.start_transaction(false, None, None) {
+                Ok(val) => val,
+                Err(e) => return e.print_err_get_mess()
+            };
+            let mut unregister = || -> Result<bool, mysql::error::Error> {
+                transaction.prep_exec("delete from tracker_presence where tracker = ? and receiver = ?", (tracker_id, receiver_id))?;
+                Ok(transaction.prep_exec(UNREGISTER_TRACKER_WITHOUT_PRESENCE, (tracker_id, receiver_id, tracker_id))?.affected_rows() > 0)
+            };
+            let unregistered = match unregister() {
+                Ok(val) => val,
+                Err(e) => return e.print_err_get_mess()
+            };
+            match transaction.commit() {
+                Ok(_) => Ok(unregistered),
+                Err(e) => e.print_err_get_mess()
+            }
+        })
     }
 
+    static UNREGISTER_TRACKER_WITHOUT_PRESENCE: &'static str = "update rfid_tracker set location = null
+        where id = ? and location = (select location from rfid_receiver where id = ?)
+        and not exists (select 1 from tracker_presence join rfid_receiver on rfid_receiver.id = tracker_presence.receiver
+            where tracker_presence.tracker = ? and rfid_receiver.location = rfid_tracker.location)";
+
     /**
      * Sets the location of a tracker by id
      * 
@@ -63,15 +166,130 @@ pub mod db {
      * `tracker_id` - a String representing a tracker id
      */
     pub fn register_tracker_to_receiver(receiver_id: &String, tracker_id: &String) -> Result<(), String> {
-        let db_receiver = match get_receiver_by_id(receiver_id) {
-            Ok(Some(val)) => val,
-            Ok(None) => return Ok(()),
-            Err(e) => return e.print_err_get_mess()
-        };
-        match DB.lock().unwrap().get_conn().prep_exec("update rfid_tracker set location = ? where id = ?", (db_receiver.location, tracker_id)) {
-            Ok(_) => Ok(()),
-            Err(e) =>  e.print_err_get_mess()
-        }
+        metrics::time_query("register_tracker_to_receiver", || {
+            let db_receiver = match get_receiver_by_id(receiver_id) {
+                Ok(Some(val)) => val,
+                Ok(None) => return Ok(()),
+                Err(e) => return e.print_err_get_mess()
+            };
+            let mut conn = get_conn();
+            let mut transaction = match conn.start_transaction(false, None, None) {
+                Ok(val) => val,
+                Err(e) => return e.print_err_get_mess()
+            };
+            let mut register = || -> Result<(), mysql::error::Error> {
+                transaction.prep_exec(
+                    "update rfid_tracker set location = ?, registered_epoch = unix_timestamp() where id = ?", (db_receiver.location, tracker_id))?;
+                transaction.prep_exec(
+                    "delete from tracker_presence where tracker = ? and receiver in (select id from rfid_receiver where location <> ?)",
+                    (tracker_id, db_receiver.location))?;
+                transaction.prep_exec(
+                    "insert into tracker_presence (tracker, receiver, time_epoch) select id, ?, unix_timestamp() from rfid_tracker where id = ?
+                    on duplicate key update time_epoch = values(time_epoch)", (receiver_id, tracker_id))?;
+                Ok(())
+            };
+            if let Err(e) = register() {
+                return e.print_err_get_mess();
+            }
+            match transaction.commit() {
+                Ok(_) => Ok(()),
+                Err(e) =>  e.print_err_get_mess()
+            }
+        })
+    }
+
+    /**
+     * Unregisters the trackers registered at a location before a time, or before their registration time was recorded.
+     * Returns the number of trackers unregistered.
+     *
+     * # Arguments
+     * `before_epoch` - trackers registered before this time are unregistered
+     */
+    pub fn expire_trackers(before_epoch: u64) -> Result<u64, String> {
+        metrics::time_query("expire_trackers", || {
+            let mut conn = get_conn();
+            let mut transaction = match conn.start_transaction(false, None, None) {
+                Ok(val) => val,
+                Err(e) => return e.print_err_get_mess()
+            };
+            let mut expire = || -> Result<u64, mysql::error::Error> {
+                transaction.prep_exec(
+                    "delete from tracker_presence where tracker in
+                    (select id from rfid_tracker where location is not null and coalesce(registered_epoch, 0) < ?)", (before_epoch,))?;
+                Ok(transaction.prep_exec(
+                    "update rfid_tracker set location = null where location is not null and coalesce(registered_epoch, 0) < ?", (before_epoch,))?.affected_rows())
+            };
+            let expired = match expire() {
+                Ok(val) => val,
+                Err(e) => return e.print_err_get_mess()
+            };
+            match transaction.commit() {
+                Ok(_) => Ok(expired),
+                Err(e) => e.print_err_get_mess()
+            }
+        })
+    }
+
+    /**
+     * Returns whether a location exists
+     *
+     * # Arguments
+     * `location` - an i32 representing a location id
+     */
+    pub fn location_exists(location: i32) -> Result<bool, String> {
+        metrics::time_query("location_exists", || {
+            match get_conn().first_exec::<_, _, i32>("select id from location where id = ?", (location,)) {
+                Ok(val) => Ok(val.is_some()),
+                Err(e) => e.print_err_get_mess()
+            }
+        })
+    }
+
+    /**
+     * Inserts a rfid_tracker row
+     *
+     * # Arguments
+     * `tracker` - the tracker to insert
+     */
+    pub fn insert_tracker(tracker: &Tracker) -> Result<(), String> {
+        metrics::time_query("insert_tracker", || {
+            match get_conn().prep_exec(
+                "insert into rfid_tracker (id, location, registered_epoch) values(?, ?, if(? is null, null, unix_timestamp()))",
+                (&tracker.id, tracker.location, tracker.location)) {
+                Ok(_) => Ok(()),
+                Err(e) => e.print_err_get_mess()
+            }
+        })
+    }
+
+    /**
+     * Inserts a rfid_receiver row
+     *
+     * # Arguments
+     * `receiver` - the receiver to insert
+     */
+    pub fn insert_receiver(receiver: &Receiver) -> Result<(), String> {
+        metrics::time_query("insert_receiver", || {
+            match get_conn().prep_exec("insert into rfid_receiver (id, location) values(?, ?)", (&receiver.id, receiver.location)) {
+                Ok(_) => Ok(()),
+                Err(e) => e.print_err_get_mess()
+            }
+        })
+    }
+
+    /**
+     * Inserts a display row, returns the id of the display
+     *
+     * # Arguments
+     * `location` - the location of the display
+     */
+    pub fn insert_display(location: i32) -> Result<i32, String> {
+        metrics::time_query("insert_display", || {
+            match get_conn().prep_exec("insert into display (location) values(?)", (location,)) {
+                Ok(res) => Ok(res.last_insert_id() as i32),
+                Err(e) => e.print_err_get_mess()
+            }
+        })
     }
 
     /**
@@ -81,12 +299,14 @@ pub mod db {
      * `tracker_id` - an String representing a tracker id
      */
     pub fn get_tracker_by_id(tracker_id: &String) -> Result<Option<Tracker>, String> {
-        match DB.lock().unwrap().get_conn().first_exec(
-            "select id, location from rfid_tracker where id = ?", (tracker_id,)) {
-                Ok(Some((id, location))) => Ok(Some(Tracker{id, location})),
-                Ok(None) => Ok(None),
-                Err(e) => e.print_err_get_mess()
-        }
+        metrics::time_query("get_tracker_by_id", || {
+            match get_conn().first_exec(
+                "select id, location from rfid_tracker where id = ?", (tracker_id,)) {
+                    Ok(Some((id, location))) => Ok(Some(Tracker{id, location})),
+                    Ok(None) => Ok(None),
+                    Err(e) => e.print_err_get_mess()
+            }
+        })
     }
 
     /**
@@ -96,12 +316,14 @@ pub mod db {
      * `receiver_id` - a String representing a receiver id
      */
     pub fn get_receiver_by_id(receiver_id: &String) -> Result<Option<Receiver>, String> {
-        match DB.lock().unwrap().get_conn().first_exec(
-            "select id, location from rfid_receiver where id = ?", (receiver_id,)) {
-                Ok(Some((id, location))) => Ok(Some(Receiver{id, location})),
-                Ok(None) => Ok(None),
-                Err(e) => e.print_err_get_mess()
-        }
+        metrics::time_query("get_receiver_by_id", || {
+            match get_conn().first_exec(
+                "select id, location from rfid_receiver where id = ?", (receiver_id,)) {
+                    Ok(Some((id, location))) => Ok(Some(Receiver{id, location})),
+                    Ok(None) => Ok(None),
+                    Err(e) => e.print_err_get_mess()
+            }
+        })
     }
 
      /**
@@ -111,11 +333,13 @@ pub mod db {
      * `display_id` - an i32 representing display id
      */
     pub fn get_display_location(display_id: i32) -> Option<i32> {
-        match DB.lock().unwrap().get_conn().first_exec(
-        "select location from display where id = ?", (display_id,)) {
-            Ok(val) => val,
-            Err(e) => {println!("{}", e); return None}
-        }
+        metrics::time_query("get_display_location", || {
+            match get_conn().first_exec(
+            "select location from display where id = ?", (display_id,)) {
+                Ok(val) => val,
+                Err(e) => {error!("{}", e); return None}
+            }
+        })
     }
 
     /**
@@ -125,42 +349,51 @@ pub mod db {
      * `display_id` - an i32 representing a display id
      */
     pub fn get_display_by_id(display_id: i32) ->  Result<Option<Display>, String> {
-        match DB.lock().unwrap().get_conn().first_exec(
-            "select id, location from display where id = ?", (display_id,)) {
-                Ok(Some((id, location))) => Ok(Some(Display{id, location})),
-                Err(e) => e.print_err_get_mess(),
-                _ => Ok(None)
-            }
+        metrics::time_query("get_display_by_id", || {
+            match get_conn().first_exec(
+                "select id, location from display where id = ?", (display_id,)) {
+                    Ok(Some((id, location))) => Ok(Some(Display{id, location})),
+                    Err(e) => e.print_err_get_mess(),
+                    _ => Ok(None)
+                }
+        })
     }
 
     /**
-     * Returns the aggregated weight of all the interests for trackers in this location
-     * and turns into a reverse weight sorted tuple of (interest, weight).
+     * Returns the interests of a tracker with their weights, as (interest, weight)
      * 
      * # Arguments
-     * `location` - an i32 representing a physical location  
+     * `tracker_id` - a String representing a tracker id
      */
-    pub fn get_interests_at_location(location: i32) -> Result<Option<Vec<(i32, f32)>>, String> {
+    pub fn get_tracker_interests(tracker_id: &String) -> Result<Vec<(i32, f32)>, String> {
+        metrics::time_query("get_tracker_interests", || {
+            let selected_p: Result<Vec<(i32, f32)>, mysql::error::Error> = get_conn().prep_exec(
+                "select interest, weight from tracker_interest where tracker = ?", (tracker_id,)).map(|result| {
+                   result.map(|x| x.unwrap()).map(|row| mysql::from_row(row)).collect()
+                });
+            match selected_p {
+                Err(e) => e.print_err_get_mess(),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
 
-        let selected_p: Result<Vec<(i32, f32)>, mysql::error::Error> =  DB.lock().unwrap().get_conn().prep_exec(
-            "select interest, sum(weight) as weight from rfid_tracker, tracker_interest where 
-            location = ? and tracker = id
-            group by interest
-            order by weight desc;", (location,)).map(|result| {
-               result.map(|x| x.unwrap()).map(|row| {
-               let (i, w) = mysql::from_row(row);
-               (i, w)
-                }).collect()
-            });
+    /**
+     * Returns every tracker registered at a location, with the weights of its interests
+     */
+    pub fn find_present_trackers() -> Result<Vec<PresentTracker>, String> {
+        metrics::time_query("find_present_trackers", || {
+            let selected_p: Result<Vec<(String, i32, Option<i32>, Option<f32>)>, mysql::error::Error> = get_conn().prep_exec(
+                "select id, location, interest, weight from rfid_tracker left join tracker_interest on tracker = id
+                where location is not null
+                order by id", ()).map(|result| {
+                   result.map(|x| x.unwrap()).map(|row| mysql::from_row(row)).collect()
+                });
             match selected_p {
                 Err(e) => e.print_err_get_mess(),
-                _ => {let res = selected_p.unwrap(); 
-                    match res.len() {
-                        0 => Ok(None),
-                        _ => Ok(Some(res))
-                    }
-                }
+                Ok(rows) => Ok(super::store::group_present_trackers(rows))
             }
+        })
     }
 
     /**
@@ -170,12 +403,14 @@ pub mod db {
      * `video_id` - an i32 representing a video id
      */
     pub fn get_advertisement_video_by_id(video_id: i32) -> Result<Option<AdvertVideo>, String> {
-        match DB.lock().unwrap().get_conn().first_exec("SELECT interest, url, length_sec
-        FROM advertisement_video where id = ?", (video_id,)) {
-            Ok(Some((interest, url, length_sec))) => Ok(Some(AdvertVideo{interest, url, length_sec})),
-            Err(e) => e.print_err_get_mess(),
-            _ => Ok(None)
-        }
+        metrics::time_query("get_advertisement_video_by_id", || {
+            match get_conn().first_exec("SELECT interest, url, length_sec
+            FROM advertisement_video where id = ?", (video_id,)) {
+                Ok(Some((interest, url, length_sec))) => Ok(Some(AdvertVideo{interest, url, length_sec})),
+                Err(e) => e.print_err_get_mess(),
+                _ => Ok(None)
+            }
+        })
     }
 
     /**
@@ -185,76 +420,747 @@ pub mod db {
      * `order_id` - an String representing an order id
      */
     pub fn get_order_by_id(order_id: &String) -> Result<Option<Order>, String> {
-        match DB.lock().unwrap().get_conn().first_exec("SELECT id, credits, user
-        FROM orders where id = ?", (order_id,)) {
-            Ok(Some((id, credits, user))) => Ok(Some(Order{id, credits, user})),
-            Err(e) => e.print_err_get_mess(),
-            _ => Ok(None)
-        }
+        metrics::time_query("get_order_by_id", || {
+            match get_conn().first_exec("SELECT id, credits, user
+            FROM orders where id = ?", (order_id,)) {
+                Ok(Some((id, credits, user))) => Ok(Some(Order{id, credits, user})),
+                Err(e) => e.print_err_get_mess(),
+                _ => Ok(None)
+            }
+        })
     }
 
-     /**
-     * Inserts a played_video row in the database
+    /**
+     * Returns the advertisement_order linking a video to an order if exists
      * 
      * # Arguments
      * `video_id` - an i32 representing a video id
-     * `time_epoch` - time of play in epoch seconds
-     * `order_id` - a String representing an order id
+     * `order_id` - an String representing an order id
      */
-    pub fn insert_played_video(video_id: i32, time_epoch: u64, order_id: &String) -> Result<(), String> {
-        match DB.lock().unwrap().get_conn().prep_exec("INSERT INTO played_video (video, time_epoch, `order`) values(?, ?, ?)", (video_id, time_epoch, order_id)) {
-            Ok(_) => Ok(()),
-            Err(e) => e.print_err_get_mess::<()>()
-        }
-    } 
+    pub fn get_advertisement_order(video_id: i32, order_id: &String) -> Result<Option<AdvertisementOrder>, String> {
+        metrics::time_query("get_advertisement_order", || {
+            match get_conn().first_exec("SELECT video, orders, start_time_epoch, end_time_epoch
+            FROM advertisement_order where video = ? and orders = ?", (video_id, order_id)) {
+                Ok(Some((video, order, start_time_epoch, end_time_epoch))) => Ok(Some(AdvertisementOrder{video, order, start_time_epoch, end_time_epoch})),
+                Err(e) => e.print_err_get_mess(),
+                _ => Ok(None)
+            }
+        })
+    }
 
     /**
-     * Decrements the credit field for orders row matching id
-     * 
+     * Registers a play in a single transaction: redeems its play token, inserts the played_video row, draws its credits
+     * from the order and records the debit, with the rate applied, in the credit ledger.
+     * The ledger of an order is started with an opening entry, and a balance changed outside of the ledger
+     * since its last entry is reconciled with an adjustment entry before the debit.
+     * Returns the balance of the order before the play, or None, registering nothing, if the play token had already been redeemed.
+     *
      * # Arguments
-     * `order_id` - id of the order to decrement
-     * `credits` - i32 amount of credits to withdraw  
+     * `played` - the play to register
+     * `rate_card` - the rate card the play was charged with, id 0 for the default rate card
+     * `play_token` - the signature of the play token the play was reported with
      */
-    pub fn draw_credits_for_order(order_id: &String, credits: i32) -> Result<(), String>{
-        match DB.lock().unwrap().get_conn().prep_exec("UPDATE orders set credits = credits - ? where id = ?", (credits, order_id)) {
-            Ok(_) => Ok(()),
-            Err(e) => e.print_err_get_mess::<()>()
-        }
-    } 
+    pub fn register_play(played: &PlayedVideo, rate_card: &RateCard, play_token: &String) -> Result<Option<i32>, String> {
+        metrics::time_query("register_play", || {
+            let mut conn = get_conn();
+            let mut transaction = match conn.start_transaction(false, None, None) {
+                Ok(val) => val,
+                Err(e) => return e.print_err_get_mess()
+            };
+            let mut register = || -> Result<Option<i32>, mysql::error::Error> {
+                if transaction.prep_exec("INSERT IGNORE INTO redeemed_play_token (signature, time_epoch) values(?, ?)",
+                    (play_token, played.time_epoch))?.affected_rows() == 0 {
+                    return Ok(None);
+                }
+                let balance = reconcile_ledger(&mut transaction, &played.order, played.time_epoch)?;
+                let played_id = transaction.prep_exec(
+                    "INSERT INTO played_video (video, display, time_epoch, `order`, length_sec, complete, credits) values(?, ?, ?, ?, ?, ?, ?)",
+                    (played.video, played.display, played.time_epoch, &played.order, played.length_sec, played.complete, played.credits))?.last_insert_id();
+                transaction.prep_exec("UPDATE orders set credits = credits - ? where id = ?", (played.credits, &played.order))?;
+                transaction.prep_exec(INSERT_LEDGER_ENTRY, (&played.order, played.time_epoch, LedgerEntryType::Debit.as_str(),
+                    -played.credits, balance - played.credits, played_id, Some(rate_card.id).filter(|id| *id != 0), rate_card.credits_per_second, None::<String>))?;
+                Ok(Some(balance))
+            };
+            let balance = match register() {
+                Ok(val) => val,
+                Err(e) => return e.print_err_get_mess()
+            };
+            match transaction.commit() {
+                Ok(_) => Ok(balance),
+                Err(e) => e.print_err_get_mess()
+            }
+        })
+    }
+
+    static INSERT_LEDGER_ENTRY: &'static str = "INSERT INTO credit_ledger
+        (`order`, time_epoch, entry_type, amount, balance_after, played_video, rate_card, credits_per_second, description)
+        values(?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
+    /**
+     * Locks the order row and returns its balance, starting its ledger with an opening entry if it has none,
+     * or recording an adjustment entry if the balance was changed outside of the ledger since its last entry.
+     *
+     * # Arguments
+     * `transaction` - the transaction the order is locked in
+     * `order_id` - an String representing an order id
+     * `time_epoch` - the time of the entries in epoch seconds
+     */
+    fn reconcile_ledger(transaction: &mut mysql::Transaction, order_id: &String, time_epoch: u64) -> Result<i32, mysql::error::Error> {
+        let balance: i32 = transaction.first_exec("SELECT credits FROM orders WHERE id = ? FOR UPDATE", (order_id,))?.unwrap_or(0);
+        let ledger_balance: Option<i32> = transaction.first_exec(
+            "SELECT balance_after FROM credit_ledger WHERE `order` = ? ORDER BY id DESC LIMIT 1", (order_id,))?;
+        match ledger_balance {
+            None => {transaction.prep_exec(INSERT_LEDGER_ENTRY, (order_id, time_epoch, LedgerEntryType::Opening.as_str(),
+                balance, balance, None::<i32>, None::<i32>, None::<f64>, "opening balance"))?;},
+            Some(val) if val != balance => {transaction.prep_exec(INSERT_LEDGER_ENTRY, (order_id, time_epoch, LedgerEntryType::Adjustment.as_str(),
+                balance - val, balance, None::<i32>, None::<i32>, None::<f64>, "balance changed outside of the ledger"))?;},
+            Some(_) => ()
+        };
+        Ok(balance)
+    }
+
+    /**
+     * Returns whether a play has been voided, None if the play does not exist
+     *
+     * # Arguments
+     * `played_video_id` - the id of the played_video row
+     */
+    pub fn get_play_voided(played_video_id: i32) -> Result<Option<bool>, String> {
+        metrics::time_query("get_play_voided", || {
+            match get_conn().first_exec("SELECT voided FROM played_video WHERE id = ?", (played_video_id,)) {
+                Ok(val) => Ok(val),
+                Err(e) => e.print_err_get_mess()
+            }
+        })
+    }
+
+    /**
+     * Voids plays in a single transaction: marks the played_video rows as voided and gives their credits back
+     * to their orders, recording a refund entry per play in the credit ledger.
+     * Plays that are already voided are left untouched.
+     * Returns the plays voided.
+     *
+     * # Arguments
+     * `selection` - the plays to void
+     * `reason` - why the plays are voided, recorded with the plays and the refunds
+     * `time_epoch` - time of voiding in epoch seconds
+     */
+    pub fn void_plays(selection: &PlaySelection, reason: &Option<String>, time_epoch: u64) -> Result<Vec<VoidedPlay>, String> {
+        metrics::time_query("void_plays", || {
+            let mut conn = get_conn();
+            let mut transaction = match conn.start_transaction(false, None, None) {
+                Ok(val) => val,
+                Err(e) => return e.print_err_get_mess()
+            };
+            let mut void = || -> Result<Vec<VoidedPlay>, mysql::error::Error> {
+                let (query, params): (&str, Vec<mysql::Value>) = match selection {
+                    PlaySelection::Single(id) => ("SELECT id, `order`, credits FROM played_video WHERE id = ? AND NOT voided FOR UPDATE", vec![(*id).into()]),
+                    PlaySelection::Display {display, from_epoch, to_epoch} => (
+                        "SELECT id, `order`, credits FROM played_video WHERE display = ? AND time_epoch >= ? AND time_epoch <= ? AND NOT voided ORDER BY id FOR UPDATE",
+                        vec![(*display).into(), (*from_epoch).into(), (*to_epoch).into()])
+                };
+                let plays: Vec<VoidedPlay> = transaction.prep_exec(query, params)?.map(|x| x.unwrap()).map(|row| {
+                    let (id, order, credits) = mysql::from_row(row);
+                    VoidedPlay {id, order, credits}
+                }).collect();
+                let mut orders: Vec<&String> = plays.iter().map(|p| &p.order).collect();
+                orders.sort();
+                orders.dedup();
+                for order_id in orders {
+                    let mut balance = reconcile_ledger(&mut transaction, order_id, time_epoch)?;
+                    for play in plays.iter().filter(|p| &p.order == order_id) {
+                        transaction.prep_exec("UPDATE played_video SET voided = true, voided_epoch = ?, void_reason = ? WHERE id = ?", (time_epoch, reason, play.id))?;
+                        balance += play.credits;
+                        transaction.prep_exec(INSERT_LEDGER_ENTRY, (order_id, time_epoch, LedgerEntryType::Refund.as_str(),
+                            play.credits, balance, play.id, None::<i32>, None::<f64>, reason.clone().unwrap_or_else(|| "voided play".to_owned())))?;
+                    }
+                    transaction.prep_exec("UPDATE orders SET credits = ? WHERE id = ?", (balance, order_id))?;
+                }
+                Ok(plays)
+            };
+            let plays = match void() {
+                Ok(val) => val,
+                Err(e) => return e.print_err_get_mess()
+            };
+            match transaction.commit() {
+                Ok(_) => Ok(plays),
+                Err(e) => e.print_err_get_mess()
+            }
+        })
+    }
+
+    /**
+     * Returns the credit ledger of an order, oldest entry first
+     *
+     * # Arguments
+     * `order_id` - an String representing an order id
+     */
+    pub fn find_ledger_entries(order_id: &String) -> Result<Vec<LedgerEntry>, String> {
+        metrics::time_query("find_ledger_entries", || {
+            let selected_p: Result<Vec<LedgerEntry>, mysql::error::Error> = get_conn().prep_exec(
+                "select id, `order`, time_epoch, entry_type, amount, balance_after, played_video, rate_card, credits_per_second, description
+                from credit_ledger where `order` = ? order by id", (order_id,)).map(|result| {
+                   result.map(|x| x.unwrap()).filter_map(|row| {
+                   let (id, order, time_epoch, entry_type, amount, balance_after, played_video, rate_card, credits_per_second, description):
+                        (i32, String, u64, String, i32, i32, Option<i32>, Option<i32>, Option<f64>, Option<String>) = mysql::from_row(row);
+                   match LedgerEntryType::from_str(&entry_type) {
+                       Some(entry_type) => Some(LedgerEntry {id, order, time_epoch, entry_type, amount, balance_after, played_video, rate_card, credits_per_second, description}),
+                       None => {error!("unknown ledger entry type {} in ledger entry {}", entry_type, id); None}
+                   }
+                    }).collect()
+                });
+            match selected_p {
+                Err(e) => e.print_err_get_mess(),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    /**
+     * Returns the rate cards that apply to a location, both the ones for the location and the ones for all locations
+     *
+     * # Arguments
+     * `location` - an i32 representing a location id
+     */
+    pub fn get_rate_cards_for_location(location: i32) -> Result<Vec<RateCard>, String> {
+        metrics::time_query("get_rate_cards_for_location", || {
+            let selected_p: Result<Vec<RateCard>, mysql::error::Error> = get_conn().prep_exec(
+                "select id, location, start_minute, end_minute, credits_per_second, min_credits, rounding
+                from rate_card where location = ? or location is null", (location,)).map(|result| {
+                   result.map(|x| x.unwrap()).filter_map(|row| {
+                   let (id, location, start_minute, end_minute, credits_per_second, min_credits, rounding):
+                        (i32, Option<i32>, u32, u32, f64, i32, String) = mysql::from_row(row);
+                   match Rounding::from_str(&rounding) {
+                        Some(rounding) => Some(RateCard {id, location, start_minute, end_minute, credits_per_second, min_credits, rounding}),
+                        None => {error!("ignoring rate card {} with unknown rounding {}", id, rounding); None}
+                   }
+                    }).collect()
+                });
+            match selected_p {
+                Err(e) => e.print_err_get_mess(),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
 
     /**
      * Returns all elligible videos for the interests contained in the Vec<i32> interests with interest_id's.
-     * Only returns videos that are payed for, and matches one of the interests given
+     * Only returns videos that are payed for, in a campaign running at the given time, and matches one of the interests given.
+     * Each video carries the budget of its order, for the pacing to be applied by the caller.
      * 
      * # Arguments
      * `interests` - A vector of integers representing interests
+     * `time_epoch` - the time the campaigns must be running at, in epoch seconds
      */
-    pub fn find_eligible_videos_by_interest(interests: Vec<i32>) ->  Result<Option<Vec<AdvertVideoOrder>>, String> {
-        let q_marks = &interests.iter().fold(String::from(""), |a, _b| format!("{}, ?", a))[1..];
-        let prep_q = format!(
-            "SELECT  advertisement_order.video as video_id, interest, url, length_sec, orders FROM advertisement_video, advertisement_order, orders
-            where interest in ({})
-            and advertisement_order.video = advertisement_video.id
-            and advertisement_order.orders = orders.id
-            and orders.credits > 0", q_marks);
-        println!("{}", prep_q);
-
-        let selected_p: Result<Vec<AdvertVideoOrder>, mysql::error::Error> =  DB.lock().unwrap().get_conn().prep_exec(
-            prep_q, interests).map(|result| {
-               result.map(|x| x.unwrap()).map(|row| {
-               let (video_id, interest, url, length_sec, order) = mysql::from_row(row);
-               AdvertVideoOrder{video_id, interest, url, length_sec, order}
-                }).collect()
-            });
-        match selected_p {
-            Err(e) => e.print_err_get_mess(),
-            _ => {let res = selected_p.unwrap(); 
-                match res.len() {
-                    0 => Ok(None),
-                    _ => Ok(Some(res))
+    pub fn find_eligible_videos_by_interest(interests: Vec<i32>, time_epoch: u64) ->  Result<Option<Vec<AdvertVideoOrder>>, String> {
+        metrics::time_query("find_eligible_videos_by_interest", || {
+            let q_marks = &interests.iter().fold(String::from(""), |a, _b| format!("{}, ?", a))[1..];
+            let prep_q = format!(
+                "SELECT  advertisement_order.video as video_id, interest, url, length_sec, orders, orders.credits,
+                (select cast(coalesce(sum(played_video.credits), 0) as signed) from played_video where played_video.`order` = orders.id and not played_video.voided),
+                (select min(campaign.start_time_epoch) from advertisement_order campaign where campaign.orders = orders.id),
+                (select max(campaign.end_time_epoch) from advertisement_order campaign where campaign.orders = orders.id)
+                FROM advertisement_video, advertisement_order, orders
+                where interest in ({})
+                and advertisement_order.video = advertisement_video.id
+                and advertisement_order.orders = orders.id
+                and advertisement_order.start_time_epoch <= ?
+                and advertisement_order.end_time_epoch >= ?
+                and orders.credits > 0", q_marks);
+            trace!("{}", prep_q);
+
+            let mut params: Vec<mysql::Value> = interests.into_iter().map(mysql::Value::from).collect();
+            params.push(time_epoch.into());
+            params.push(time_epoch.into());
+            let selected_p: Result<Vec<AdvertVideoOrder>, mysql::error::Error> =  get_conn().prep_exec(
+                prep_q, params).map(|result| {
+                   result.map(|x| x.unwrap()).map(|row| {
+                   let (video_id, interest, url, length_sec, order, remaining_credits, spent_credits, start_epoch, end_epoch) = mysql::from_row(row);
+                   AdvertVideoOrder{video_id, interest, url, length_sec, order, budget: OrderBudget {remaining_credits, spent_credits, start_epoch, end_epoch}}
+                    }).collect()
+                });
+            match selected_p {
+                Err(e) => e.print_err_get_mess(),
+                _ => {let res = selected_p.unwrap(); 
+                    match res.len() {
+                        0 => Ok(None),
+                        _ => Ok(Some(res))
+                    }
                 }
             }
-        }
+        })
+    }
+
+    /**
+     * Returns the active webhooks of the agency that placed an order
+     *
+     * # Arguments
+     * `order_id` - a String representing an order id
+     */
+    pub fn find_webhooks_for_order(order_id: &String) -> Result<Vec<Webhook>, String> {
+        metrics::time_query("find_webhooks_for_order", || {
+            let selected_p: Result<Vec<Webhook>, mysql::error::Error> = get_conn().prep_exec(
+                "select agency_webhook.id, agency_webhook.agency, url, secret, events, low_credit_threshold
+                from orders, users, agency_webhook
+                where orders.id = ? and orders.user = users.email and users.agency = agency_webhook.agency and agency_webhook.active",
+                (order_id,)).map(|result| {
+                   result.map(|x| x.unwrap()).map(|row| {
+                   let (id, agency, url, secret, events, low_credit_threshold): (i32, String, String, Option<String>, Option<String>, i32) = mysql::from_row(row);
+                   let events = events.map(|events| events.split(',').filter_map(|e| WebhookEvent::from_str(e.trim())).collect());
+                   Webhook {id, agency, url, secret, events, low_credit_threshold}
+                    }).collect()
+                });
+            match selected_p {
+                Err(e) => e.print_err_get_mess(),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    /**
+     * Queues an event for delivery to a webhook
+     *
+     * # Arguments
+     * `webhook_id` - the webhook to deliver to
+     * `event` - the event to deliver
+     * `order_id` - the order the event is about
+     * `payload` - the json body to deliver
+     * `time_epoch` - the time the event happened, the first delivery attempt is due then
+     */
+    pub fn insert_webhook_delivery(webhook_id: i32, event: WebhookEvent, order_id: &String, payload: &String, time_epoch: u64) -> Result<(), String> {
+        metrics::time_query("insert_webhook_delivery", || {
+            match get_conn().prep_exec(
+                "INSERT INTO webhook_delivery (webhook, event, `order`, payload, created_epoch, next_attempt_epoch) values(?, ?, ?, ?, ?, ?)",
+                (webhook_id, event.as_str(), order_id, payload, time_epoch, time_epoch)) {
+                Ok(_) => Ok(()),
+                Err(e) => e.print_err_get_mess::<()>()
+            }
+        })
+    }
+
+    /**
+     * Returns pending webhook deliveries that are due, oldest first
+     *
+     * # Arguments
+     * `time_epoch` - deliveries due at or before this time are returned
+     * `limit` - the maximum number of deliveries returned
+     */
+    pub fn find_due_webhook_deliveries(time_epoch: u64, limit: u32) -> Result<Vec<WebhookDelivery>, String> {
+        metrics::time_query("find_due_webhook_deliveries", || {
+            let selected_p: Result<Vec<WebhookDelivery>, mysql::error::Error> = get_conn().prep_exec(
+                "select webhook_delivery.id, url, secret, event, payload, attempts
+                from webhook_delivery, agency_webhook
+                where webhook_delivery.webhook = agency_webhook.id and status = 'pending' and next_attempt_epoch <= ?
+                order by next_attempt_epoch, webhook_delivery.id limit ?",
+                (time_epoch, limit)).map(|result| {
+                   result.map(|x| x.unwrap()).filter_map(|row| {
+                   let (id, url, secret, event, payload, attempts): (i32, String, Option<String>, String, String, u32) = mysql::from_row(row);
+                   WebhookEvent::from_str(&event).map(|event| WebhookDelivery {id, url, secret, event, payload, attempts})
+                    }).collect()
+                });
+            match selected_p {
+                Err(e) => e.print_err_get_mess(),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    /**
+     * Records the outcome of a delivery attempt
+     *
+     * # Arguments
+     * `delivery_id` - the delivery attempted
+     * `status` - pending if the delivery should be retried
+     * `attempts` - the number of attempts made, including this one
+     * `next_attempt_epoch` - when to retry a pending delivery
+     * `response_code` - the http status code of the response, if any
+     * `error` - what went wrong, if anything
+     * `time_epoch` - the time of the attempt
+     */
+    pub fn update_webhook_delivery(delivery_id: i32, status: DeliveryStatus, attempts: u32, next_attempt_epoch: u64,
+                                   response_code: Option<u16>, error: Option<String>, time_epoch: u64) -> Result<(), String> {
+        metrics::time_query("update_webhook_delivery", || {
+            match get_conn().prep_exec(
+                "UPDATE webhook_delivery set status = ?, attempts = ?, next_attempt_epoch = ?, response_code = ?, last_error = ?, last_attempt_epoch = ?
+                where id = ?",
+                (status.as_str(), attempts, next_attempt_epoch, response_code, error, time_epoch, delivery_id)) {
+                Ok(_) => Ok(()),
+                Err(e) => e.print_err_get_mess::<()>()
+            }
+        })
+    }
+
+    /**
+     * Returns the campaigns that started or ended in the time range, and have not had the event recorded yet
+     *
+     * # Arguments
+     * `after_epoch` - only campaigns starting or ending after this time
+     * `until_epoch` - only campaigns starting or ending at or before this time
+     */
+    pub fn find_unrecorded_campaign_events(after_epoch: u64, until_epoch: u64) -> Result<Vec<(AdvertisementOrder, WebhookEvent)>, String> {
+        metrics::time_query("find_unrecorded_campaign_events", || {
+            let selected_p: Result<Vec<(AdvertisementOrder, WebhookEvent)>, mysql::error::Error> = get_conn().prep_exec(
+                "select video, orders, start_time_epoch, end_time_epoch, 'campaign_started' from advertisement_order
+                where start_time_epoch > ? and start_time_epoch <= ?
+                and not exists (select 1 from campaign_event where campaign_event.video = advertisement_order.video
+                    and campaign_event.orders = advertisement_order.orders and event = 'campaign_started')
+                union all
+                select video, orders, start_time_epoch, end_time_epoch, 'campaign_ended' from advertisement_order
+                where end_time_epoch > ? and end_time_epoch <= ?
+                and not exists (select 1 from campaign_event where campaign_event.video = advertisement_order.video
+                    and campaign_event.orders = advertisement_order.orders and event = 'campaign_ended')",
+                (after_epoch, until_epoch, after_epoch, until_epoch)).map(|result| {
+                   result.map(|x| x.unwrap()).map(|row| {
+                   let (video, order, start_time_epoch, end_time_epoch, event): (i32, String, u64, u64, String) = mysql::from_row(row);
+                   (AdvertisementOrder {video, order, start_time_epoch, end_time_epoch}, WebhookEvent::from_str(&event).unwrap())
+                    }).collect()
+                });
+            match selected_p {
+                Err(e) => e.print_err_get_mess(),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    /**
+     * Records that a campaign event has been handled.
+     * Returns false if it had already been recorded.
+     *
+     * # Arguments
+     * `campaign` - the campaign the event happened to
+     * `event` - campaign_started or campaign_ended
+     * `time_epoch` - the time the event was handled
+     */
+    pub fn record_campaign_event(campaign: &AdvertisementOrder, event: WebhookEvent, time_epoch: u64) -> Result<bool, String> {
+        metrics::time_query("record_campaign_event", || {
+            match get_conn().prep_exec(
+                "INSERT IGNORE INTO campaign_event (video, orders, event, time_epoch) values(?, ?, ?, ?)",
+                (campaign.video, &campaign.order, event.as_str(), time_epoch)) {
+                Ok(res) => Ok(res.affected_rows() == 1),
+                Err(e) => e.print_err_get_mess()
+            }
+        })
+    }
+
+    /**
+     * Inserts a device_log row in the database for each entry, in a single transaction
+     *
+     * # Arguments
+     * `entries` - the LogEntries to persist
+     */
+    pub fn insert_log_entries(entries: &Vec<LogEntry>) -> Result<(), String> {
+        metrics::time_query("insert_log_entries", || {
+            let mut conn = get_conn();
+            let mut transaction = match conn.start_transaction(false, None, None) {
+                Ok(val) => val,
+                Err(e) => return e.print_err_get_mess()
+            };
+            for entry in entries.iter() {
+                match transaction.prep_exec(
+                    "INSERT INTO device_log (device, severity, firmware, time_epoch, message, fields) values(?, ?, ?, ?, ?, ?)",
+                    (&entry.device, entry.severity.as_str(), &entry.firmware, entry.time_epoch, &entry.message, &entry.fields)) {
+                    Ok(_) => (),
+                    Err(e) => return e.print_err_get_mess()
+                }
+            }
+            match transaction.commit() {
+                Ok(_) => Ok(()),
+                Err(e) => e.print_err_get_mess::<()>()
+            }
+        })
+    }
+
+    /**
+     * Returns the persisted log entries matching the filter, newest first.
+     * Filter fields that are None are not applied.
+     *
+     * # Arguments
+     * `filter` - LogFilter with the device, severity and time range to match
+     * `limit` - the maximum number of entries returned
+     */
+    pub fn find_log_entries(filter: &LogFilter, limit: u32) -> Result<Vec<LogEntry>, String> {
+        metrics::time_query("find_log_entries", || {
+            let mut conditions: Vec<String> = vec!["1 = 1".to_owned()];
+            let mut params: Vec<mysql::Value> = vec![];
+            if let Some(device) = &filter.device {
+                conditions.push("device = ?".to_owned());
+                params.push(device.into());
+            }
+            if let Some(severity) = filter.severity {
+                conditions.push("severity = ?".to_owned());
+                params.push(severity.as_str().into());
+            }
+            if let Some(min_severity) = filter.min_severity {
+                let severities: Vec<Severity> = Severity::all().into_iter().filter(|s| *s >= min_severity).collect();
+                conditions.push(format!("severity in ({})", &severities.iter().fold(String::from(""), |a, _b| format!("{}, ?", a))[1..]));
+                for severity in severities {
+                    params.push(severity.as_str().into());
+                }
+            }
+            if let Some(from) = filter.from_epoch {
+                conditions.push("time_epoch >= ?".to_owned());
+                params.push(from.into());
+            }
+            if let Some(to) = filter.to_epoch {
+                conditions.push("time_epoch <= ?".to_owned());
+                params.push(to.into());
+            }
+            params.push(limit.into());
+            let prep_q = format!(
+                "SELECT device, severity, firmware, time_epoch, message, fields FROM device_log
+                where {}
+                order by time_epoch desc, id desc
+                limit ?", conditions.join(" and "));
+
+            let selected_p: Result<Vec<LogEntry>, mysql::error::Error> = get_conn().prep_exec(
+                prep_q, params).map(|result| {
+                   result.map(|x| x.unwrap()).map(|row| {
+                   let (device, severity, firmware, time_epoch, message, fields): (Option<String>, String, Option<String>, u64, String, Option<String>) = mysql::from_row(row);
+                   LogEntry{device, severity: Severity::from_str(&severity).unwrap_or(Severity::Info), firmware, time_epoch, message, fields}
+                    }).collect()
+                });
+            match selected_p {
+                Err(e) => e.print_err_get_mess(),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    /**
+     * Inserts or replaces the latest heartbeat of a device
+     *
+     * # Arguments
+     * `heartbeat` - the Heartbeat to store
+     */
+    pub fn upsert_heartbeat(heartbeat: &Heartbeat) -> Result<(), String> {
+        metrics::time_query("upsert_heartbeat", || {
+            match get_conn().prep_exec(
+                "INSERT INTO device_heartbeat (device_type, device, time_epoch, uptime_sec, firmware, free_memory, last_error) values(?, ?, ?, ?, ?, ?, ?)
+                ON DUPLICATE KEY UPDATE time_epoch = VALUES(time_epoch), uptime_sec = VALUES(uptime_sec), firmware = VALUES(firmware),
+                free_memory = VALUES(free_memory), last_error = VALUES(last_error)",
+                (heartbeat.device_type.as_str(), &heartbeat.device, heartbeat.time_epoch, heartbeat.status.uptime_sec,
+                &heartbeat.status.firmware, heartbeat.status.free_memory, &heartbeat.status.last_error)) {
+                Ok(_) => Ok(()),
+                Err(e) => e.print_err_get_mess::<()>()
+            }
+        })
+    }
+
+    /**
+     * Updates the heartbeat time of a device, keeping the last reported status
+     *
+     * # Arguments
+     * `device_type` - the type of the device
+     * `device_id` - a String representing a receiver or display id
+     * `time_epoch` - time of the heartbeat in epoch seconds
+     */
+    pub fn touch_heartbeat(device_type: DeviceType, device_id: &String, time_epoch: u64) -> Result<(), String> {
+        metrics::time_query("touch_heartbeat", || {
+            match get_conn().prep_exec(
+                "INSERT INTO device_heartbeat (device_type, device, time_epoch) values(?, ?, ?)
+                ON DUPLICATE KEY UPDATE time_epoch = VALUES(time_epoch)",
+                (device_type.as_str(), device_id, time_epoch)) {
+                Ok(_) => Ok(()),
+                Err(e) => e.print_err_get_mess::<()>()
+            }
+        })
+    }
+
+    /**
+     * Returns all receivers and displays whose last heartbeat is older than the given time,
+     * including the ones that never sent a heartbeat.
+     *
+     * # Arguments
+     * `before_epoch` - devices with a heartbeat at or after this time are not returned
+     */
+    pub fn find_stale_devices(before_epoch: u64) -> Result<Vec<StaleDevice>, String> {
+        metrics::time_query("find_stale_devices", || {
+            let selected_p: Result<Vec<StaleDevice>, mysql::error::Error> = get_conn().prep_exec(
+                "select 'receiver', rfid_receiver.id, time_epoch, uptime_sec, firmware, free_memory, last_error
+                from rfid_receiver left join device_heartbeat on device_type = 'receiver' and device = rfid_receiver.id
+                where time_epoch is null or time_epoch < ?
+                union all
+                select 'display', cast(display.id as char), time_epoch, uptime_sec, firmware, free_memory, last_error
+                from display left join device_heartbeat on device_type = 'display' and device = cast(display.id as char)
+                where time_epoch is null or time_epoch < ?", (before_epoch, before_epoch)).map(|result| {
+                   result.map(|x| x.unwrap()).map(|row| {
+                   let (device_type, device, time_epoch, uptime_sec, firmware, free_memory, last_error):
+                        (String, String, Option<u64>, Option<u64>, Option<String>, Option<u64>, Option<String>) = mysql::from_row(row);
+                   let device_type = DeviceType::from_str(&device_type).unwrap();
+                   let last_heartbeat = time_epoch.map(|time_epoch| Heartbeat {
+                        device_type,
+                        device: device.clone(),
+                        time_epoch,
+                        status: DeviceStatus {uptime_sec, firmware, free_memory, last_error}
+                   });
+                   StaleDevice {device_type, device, last_heartbeat}
+                    }).collect()
+                });
+            match selected_p {
+                Err(e) => e.print_err_get_mess(),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    /**
+     * Returns the number of trackers registered at each location that has any
+     */
+    pub fn count_trackers_per_location() -> Result<Vec<(i32, i64)>, String> {
+        metrics::time_query("count_trackers_per_location", || {
+            let selected_p: Result<Vec<(i32, i64)>, mysql::error::Error> = get_conn().prep_exec(
+                "select location, count(*) from rfid_tracker where location is not null group by location", ()).map(|result| {
+                   result.map(|x| x.unwrap()).map(|row| mysql::from_row(row)).collect()
+                });
+            match selected_p {
+                Err(e) => e.print_err_get_mess(),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    /**
+     * Returns the displays at a location, ordered by id
+     *
+     * # Arguments
+     * `location` - an i32 representing a location id
+     */
+    pub fn find_displays_at_location(location: i32) -> Result<Vec<Display>, String> {
+        metrics::time_query("find_displays_at_location", || {
+            let selected_p: Result<Vec<(i32, i32)>, mysql::error::Error> = get_conn().prep_exec(
+                "select id, location from display where location = ? order by id", (location,)).map(|result| {
+                   result.map(|x| x.unwrap()).map(|row| mysql::from_row(row)).collect()
+                });
+            match selected_p {
+                Err(e) => e.print_err_get_mess(),
+                Ok(res) => Ok(res.into_iter().map(|(id, location)| Display {id, location}).collect())
+            }
+        })
+    }
+
+    /**
+     * Returns the receivers at a location, ordered by id
+     *
+     * # Arguments
+     * `location` - an i32 representing a location id
+     */
+    pub fn find_receivers_at_location(location: i32) -> Result<Vec<Receiver>, String> {
+        metrics::time_query("find_receivers_at_location", || {
+            let selected_p: Result<Vec<(String, i32)>, mysql::error::Error> = get_conn().prep_exec(
+                "select id, location from rfid_receiver where location = ? order by id", (location,)).map(|result| {
+                   result.map(|x| x.unwrap()).map(|row| mysql::from_row(row)).collect()
+                });
+            match selected_p {
+                Err(e) => e.print_err_get_mess(),
+                Ok(res) => Ok(res.into_iter().map(|(id, location)| Receiver {id, location}).collect())
+            }
+        })
+    }
+
+    /**
+     * Records that there are `trackers` trackers at a location from `time_epoch` on
+     */
+    pub fn insert_occupancy(location: i32, trackers: i64, time_epoch: u64) -> Result<(), String> {
+        metrics::time_query("insert_occupancy", || {
+            match get_conn().prep_exec(
+                "INSERT INTO occupancy_history (location, time_epoch, trackers) values(?, ?, ?)",
+                (location, time_epoch, trackers)) {
+                Ok(_) => Ok(()),
+                Err(e) => e.print_err_get_mess::<()>()
+            }
+        })
+    }
+
+    /**
+     * Returns the recorded occupancy of a location as (time_epoch, trackers), oldest first:
+     * the last record at or before `from_epoch`, giving the trackers at `from_epoch`, and the records after it and before `to_epoch`
+     */
+    pub fn find_occupancy_history(location: i32, from_epoch: u64, to_epoch: u64) -> Result<Vec<(u64, i64)>, String> {
+        metrics::time_query("find_occupancy_history", || {
+            let selected_p: Result<Vec<(u64, i64)>, mysql::error::Error> = get_conn().prep_exec(
+                "select time_epoch, trackers from occupancy_history
+                where location = ? and (time_epoch > ? and time_epoch < ? or id = (
+                    select id from occupancy_history where location = ? and time_epoch <= ? order by time_epoch desc, id desc limit 1))
+                order by time_epoch, id", (location, from_epoch, to_epoch, location, from_epoch)).map(|result| {
+                   result.map(|x| x.unwrap()).map(|row| mysql::from_row(row)).collect()
+                });
+            match selected_p {
+                Err(e) => e.print_err_get_mess(),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    /**
+     * Verifies that the database answers a trivial query within the timeout, and that the pool can be created.
+     * The query runs on a connection of its own, connecting and reading each bounded by the timeout,
+     * as the timeouts of the pool connections are sized for the queries they serve
+     *
+     * # Arguments
+     * `timeout` - the maximum time to wait for the database
+     */
+    pub fn ping(timeout: Duration) -> Result<(), String> {
+        metrics::time_query("ping", || {
+            let mut opts = mysql::OptsBuilder::from_opts(mysql::Opts::from_url(&config::get().database.url).map_err(|e| format!("{}", e))?);
+            opts.tcp_connect_timeout(Some(timeout))
+                .read_timeout(Some(timeout))
+                .write_timeout(Some(timeout));
+            let mut conn = mysql::Conn::new(opts).map_err(|e| format!("{}", e))?;
+            conn.query("SELECT 1").map_err(|e| format!("{}", e))?;
+            pool().map(|_| ())
+        })
+    }
+
+    /**
+     * Returns every order with the number of plays it paid for and the credits they cost, voided plays left out
+     */
+    pub fn list_orders() -> Result<Vec<OrderSummary>, String> {
+        metrics::time_query("list_orders", || {
+            let selected_p: Result<Vec<OrderSummary>, mysql::error::Error> = get_conn().prep_exec(
+                "select orders.id, orders.credits, orders.user, count(played_video.id), cast(coalesce(sum(played_video.credits), 0) as signed)
+                from orders left join played_video on played_video.`order` = orders.id and not played_video.voided
+                group by orders.id, orders.credits, orders.user order by orders.id", ()).map(|result| {
+                   result.map(|x| x.unwrap()).map(|row| {
+                   let (id, credits, user, plays, spent_credits) = mysql::from_row(row);
+                   OrderSummary {order: Order {id, credits, user}, plays, spent_credits}
+                    }).collect()
+                });
+            match selected_p {
+                Err(e) => e.print_err_get_mess(),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    /**
+     * Runs sql statements in a single transaction, none of them are applied if one fails
+     *
+     * # Arguments
+     * `statements` - the statements to run, in order
+     */
+    pub fn execute_statements(statements: &Vec<String>) -> Result<(), String> {
+        metrics::time_query("execute_statements", || {
+            let mut conn = get_conn();
+            let mut transaction = conn.start_transaction(false, None, None).map_err(|e| format!("{}", e))?;
+            for statement in statements.iter() {
+                transaction.query(statement).map_err(|e| format!("{}: {}", statement, e))?;
+            }
+            transaction.commit().map_err(|e| format!("{}", e))
+        })
+    }
+
+    /**
+     * Applies the schema migrations not yet applied to the database.
+     * Returns the versions applied, none if the schema was up to date.
+     *
+     * # Arguments
+     * `time_epoch` - the time recorded for the applied migrations
+     */
+    pub fn migrate(time_epoch: u64) -> Result<Vec<u32>, String> {
+        metrics::time_query("migrate", || {
+            let mut conn = get_conn();
+            super::migrations::migrate(&mut conn, time_epoch)
+        })
     }
 
     trait PrintErr {
@@ -263,7 +1169,7 @@ pub mod db {
 
     impl PrintErr for mysql::error::Error {   
         fn print_err_get_mess<T>(&self) -> Result<T, String> {
-            eprintln!("ERROR: {}", &self);
+            error!("{}", &self);
             panic!("{}", &self);
             //Err(format!("{}", &self))
         }
@@ -271,7 +1177,7 @@ pub mod db {
 
     impl PrintErr for String {
         fn print_err_get_mess<T>(&self) -> Result<T, String> {
-            eprintln!("ERROR: {}", &self);
+            error!("{}", &self);
             panic!("{}", &self);
             //Err(format!("{}", &self))
         }
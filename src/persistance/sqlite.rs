@@ -0,0 +1,1011 @@
+/**
+ * SQLite store, for local development and single-site deployments without a MySQL server.
+ *
+ * Runs the same queries as the MySQL store in persistance::db, adapted to SQLite. The database is a single file,
+ * or lives in memory with the path `:memory:`, and is used over a single connection.
+ */
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{error, info, trace};
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+use crate::metrics;
+use crate::model::*;
+use super::migrations;
+use super::store::{group_present_trackers, Store};
+
+pub struct SqliteStore {
+    conn: Mutex<Connection>
+}
+
+impl SqliteStore {
+    /**
+     * Opens the database file at the path, creating it if it does not exist
+     *
+     * # Arguments
+     * `path` - the path of the database file, or `:memory:` for a database in memory
+     */
+    pub fn open(path: &str) -> Result<SqliteStore, String> {
+        let conn = match path {
+            ":memory:" => Connection::open_in_memory(),
+            path => Connection::open(path)
+        }.map_err(|e| format!("could not open sqlite database {}: {}", path, e))?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;").map_err(|e| format!("{}", e))?;
+        Ok(SqliteStore {conn: Mutex::new(conn)})
+    }
+
+    fn conn(&self) -> MutexGuard<Connection> {
+        self.conn.lock().unwrap()
+    }
+}
+
+static INSERT_LEDGER_ENTRY: &'static str = "INSERT INTO credit_ledger
+    (`order`, time_epoch, entry_type, amount, balance_after, played_video, rate_card, credits_per_second, description)
+    values(?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
+static UNREGISTER_TRACKER_WITHOUT_PRESENCE: &'static str = "update rfid_tracker set location = null
+    where id = ? and location = (select location from rfid_receiver where id = ?)
+    and not exists (select 1 from tracker_presence join rfid_receiver on rfid_receiver.id = tracker_presence.receiver
+        where tracker_presence.tracker = ? and rfid_receiver.location = rfid_tracker.location)";
+
+/**
+ * Returns the balance of an order, starting its ledger with an opening entry if it has none,
+ * or recording an adjustment entry if the balance was changed outside of the ledger since its last entry.
+ * Called in the transaction of the credit movement.
+ *
+ * # Arguments
+ * `conn` - the connection of the transaction
+ * `order_id` - an String representing an order id
+ * `time_epoch` - the time of the entries in epoch seconds
+ */
+fn reconcile_ledger(conn: &Connection, order_id: &String, time_epoch: u64) -> rusqlite::Result<i32> {
+    let balance: i32 = conn.query_row("SELECT credits FROM orders WHERE id = ?", params![order_id], |row| row.get(0)).optional()?.unwrap_or(0);
+    let ledger_balance: Option<i32> = conn.query_row(
+        "SELECT balance_after FROM credit_ledger WHERE `order` = ? ORDER BY id DESC LIMIT 1", params![order_id], |row| row.get(0)).optional()?;
+    match ledger_balance {
+        None => {conn.execute(INSERT_LEDGER_ENTRY, params![order_id, time_epoch as i64, LedgerEntryType::Opening.as_str(),
+            balance, balance, None::<i32>, None::<i32>, None::<f64>, "opening balance"])?;},
+        Some(val) if val != balance => {conn.execute(INSERT_LEDGER_ENTRY, params![order_id, time_epoch as i64, LedgerEntryType::Adjustment.as_str(),
+            balance - val, balance, None::<i32>, None::<i32>, None::<f64>, "balance changed outside of the ledger"])?;},
+        Some(_) => ()
+    };
+    Ok(balance)
+}
+
+/**
+ * Question marks for the parameters of an in clause
+ */
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+fn print_err_get_mess<T>(e: rusqlite::Error) -> Result<T, String> {
+    error!("{}", e);
+    Err(format!("{}", e))
+}
+
+fn now_epoch() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(e) => panic!("ERROR GETTING SYSTEM TIME!, ERROR\n{}", e),
+    }
+}
+
+impl Store for SqliteStore {
+    fn unregister_tracker_from_receiver(&self, receiver_id: &String, tracker_id: &String) -> Result<bool, String> {
+        metrics::time_query("unregister_tracker_from_receiver", || {
+            let mut conn = self.conn();
+            let mut unregister = || -> rusqlite::Result<bool> {
+                let transaction = conn.transaction()?;
+                transaction.execute("delete from tracker_presence where tracker = ? and receiver = ?", params![tracker_id, receiver_id])?;
+                let count = transaction.execute(UNREGISTER_TRACKER_WITHOUT_PRESENCE, params![tracker_id, receiver_id, tracker_id])?;
+                transaction.commit()?;
+                Ok(count > 0)
+            };
+            match unregister() {
+                Ok(unregistered) => Ok(unregistered),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn register_tracker_to_receiver(&self, receiver_id: &String, tracker_id: &String) -> Result<(), String> {
+        metrics::time_query("register_tracker_to_receiver", || {
+            let db_receiver = match self.get_receiver_by_id(receiver_id)? {
+                Some(val) => val,
+                None => return Ok(())
+            };
+            let now = now_epoch() as i64;
+            let mut conn = self.conn();
+            let mut register = || -> rusqlite::Result<()> {
+                let transaction = conn.transaction()?;
+                transaction.execute("update rfid_tracker set location = ?, registered_epoch = ? where id = ?",
+                    params![db_receiver.location, now, tracker_id])?;
+                transaction.execute("delete from tracker_presence where tracker = ? and receiver in (select id from rfid_receiver where location <> ?)",
+                    params![tracker_id, db_receiver.location])?;
+                transaction.execute("insert or replace into tracker_presence (tracker, receiver, time_epoch) select id, ?, ? from rfid_tracker where id = ?",
+                    params![receiver_id, now, tracker_id])?;
+                transaction.commit()
+            };
+            match register() {
+                Ok(_) => Ok(()),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn expire_trackers(&self, before_epoch: u64) -> Result<u64, String> {
+        metrics::time_query("expire_trackers", || {
+            let mut conn = self.conn();
+            let mut expire = || -> rusqlite::Result<usize> {
+                let transaction = conn.transaction()?;
+                transaction.execute("delete from tracker_presence where tracker in
+                    (select id from rfid_tracker where location is not null and coalesce(registered_epoch, 0) < ?)", params![before_epoch as i64])?;
+                let count = transaction.execute(
+                    "update rfid_tracker set location = null where location is not null and coalesce(registered_epoch, 0) < ?", params![before_epoch as i64])?;
+                transaction.commit()?;
+                Ok(count)
+            };
+            match expire() {
+                Ok(count) => Ok(count as u64),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn location_exists(&self, location: i32) -> Result<bool, String> {
+        metrics::time_query("location_exists", || {
+            match self.conn().query_row("select id from location where id = ?", params![location], |row| row.get::<_, i32>(0)).optional() {
+                Ok(val) => Ok(val.is_some()),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn insert_tracker(&self, tracker: &Tracker) -> Result<(), String> {
+        metrics::time_query("insert_tracker", || {
+            match self.conn().execute("insert into rfid_tracker (id, location, registered_epoch) values(?, ?, ?)",
+                params![tracker.id, tracker.location, tracker.location.map(|_| now_epoch() as i64)]) {
+                Ok(_) => Ok(()),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn insert_receiver(&self, receiver: &Receiver) -> Result<(), String> {
+        metrics::time_query("insert_receiver", || {
+            match self.conn().execute("insert into rfid_receiver (id, location) values(?, ?)", params![receiver.id, receiver.location]) {
+                Ok(_) => Ok(()),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn insert_display(&self, location: i32) -> Result<i32, String> {
+        metrics::time_query("insert_display", || {
+            let conn = self.conn();
+            match conn.execute("insert into display (location) values(?)", params![location]) {
+                Ok(_) => Ok(conn.last_insert_rowid() as i32),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn get_tracker_by_id(&self, tracker_id: &String) -> Result<Option<Tracker>, String> {
+        metrics::time_query("get_tracker_by_id", || {
+            match self.conn().query_row("select id, location from rfid_tracker where id = ?", params![tracker_id],
+                |row| Ok(Tracker {id: row.get(0)?, location: row.get(1)?})).optional() {
+                Ok(val) => Ok(val),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn get_receiver_by_id(&self, receiver_id: &String) -> Result<Option<Receiver>, String> {
+        metrics::time_query("get_receiver_by_id", || {
+            match self.conn().query_row("select id, location from rfid_receiver where id = ?", params![receiver_id],
+                |row| Ok(Receiver {id: row.get(0)?, location: row.get(1)?})).optional() {
+                Ok(val) => Ok(val),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn get_display_location(&self, display_id: i32) -> Option<i32> {
+        self.get_display_by_id(display_id).ok().and_then(|d| d).map(|d| d.location)
+    }
+
+    fn get_display_by_id(&self, display_id: i32) -> Result<Option<Display>, String> {
+        metrics::time_query("get_display_by_id", || {
+            match self.conn().query_row("select id, location from display where id = ?", params![display_id],
+                |row| Ok(Display {id: row.get(0)?, location: row.get(1)?})).optional() {
+                Ok(val) => Ok(val),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn get_tracker_interests(&self, tracker_id: &String) -> Result<Vec<(i32, f32)>, String> {
+        metrics::time_query("get_tracker_interests", || {
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<(i32, f32)>> = conn.prepare(
+                "select interest, weight from tracker_interest where tracker = ?").and_then(|mut stmt| {
+                    stmt.query_map(params![tracker_id], |row| Ok((row.get(0)?, row.get::<_, f64>(1)? as f32)))?.collect()
+                });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    fn find_present_trackers(&self) -> Result<Vec<PresentTracker>, String> {
+        metrics::time_query("find_present_trackers", || {
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<(String, i32, Option<i32>, Option<f32>)>> = conn.prepare(
+                "select id, location, interest, weight from rfid_tracker left join tracker_interest on tracker = id
+                where location is not null
+                order by id").and_then(|mut stmt| {
+                    stmt.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get::<_, Option<f64>>(3)?.map(|w| w as f32))))?.collect()
+                });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(rows) => Ok(group_present_trackers(rows))
+            }
+        })
+    }
+
+    fn get_advertisement_video_by_id(&self, video_id: i32) -> Result<Option<AdvertVideo>, String> {
+        metrics::time_query("get_advertisement_video_by_id", || {
+            match self.conn().query_row("select interest, url, length_sec from advertisement_video where id = ?", params![video_id],
+                |row| Ok(AdvertVideo {interest: row.get(0)?, url: row.get(1)?, length_sec: row.get(2)?})).optional() {
+                Ok(val) => Ok(val),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn get_order_by_id(&self, order_id: &String) -> Result<Option<Order>, String> {
+        metrics::time_query("get_order_by_id", || {
+            match self.conn().query_row("select id, credits, user from orders where id = ?", params![order_id],
+                |row| Ok(Order {id: row.get(0)?, credits: row.get(1)?, user: row.get(2)?})).optional() {
+                Ok(val) => Ok(val),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn get_advertisement_order(&self, video_id: i32, order_id: &String) -> Result<Option<AdvertisementOrder>, String> {
+        metrics::time_query("get_advertisement_order", || {
+            match self.conn().query_row(
+                "select video, orders, start_time_epoch, end_time_epoch from advertisement_order where video = ? and orders = ?",
+                params![video_id, order_id], |row| Ok(AdvertisementOrder {video: row.get(0)?, order: row.get(1)?,
+                    start_time_epoch: row.get::<_, i64>(2)? as u64, end_time_epoch: row.get::<_, i64>(3)? as u64})).optional() {
+                Ok(val) => Ok(val),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn register_play(&self, played: &PlayedVideo, rate_card: &RateCard, play_token: &String) -> Result<Option<i32>, String> {
+        metrics::time_query("register_play", || {
+            let mut conn = self.conn();
+            let mut register = || -> rusqlite::Result<Option<i32>> {
+                let transaction = conn.transaction()?;
+                if transaction.execute("INSERT OR IGNORE INTO redeemed_play_token (signature, time_epoch) values(?, ?)",
+                    params![play_token, played.time_epoch as i64])? == 0 {
+                    return Ok(None);
+                }
+                let balance = reconcile_ledger(&transaction, &played.order, played.time_epoch)?;
+                transaction.execute(
+                    "INSERT INTO played_video (video, display, time_epoch, `order`, length_sec, complete, credits) values(?, ?, ?, ?, ?, ?, ?)",
+                    params![played.video, played.display, played.time_epoch as i64, played.order, played.length_sec, played.complete, played.credits])?;
+                let played_id = transaction.last_insert_rowid();
+                transaction.execute("UPDATE orders set credits = credits - ? where id = ?", params![played.credits, played.order])?;
+                transaction.execute(INSERT_LEDGER_ENTRY, params![played.order, played.time_epoch as i64, LedgerEntryType::Debit.as_str(),
+                    -played.credits, balance - played.credits, played_id, Some(rate_card.id).filter(|id| *id != 0), rate_card.credits_per_second,
+                    None::<String>])?;
+                transaction.commit()?;
+                Ok(Some(balance))
+            };
+            match register() {
+                Ok(balance) => Ok(balance),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn get_play_voided(&self, played_video_id: i32) -> Result<Option<bool>, String> {
+        metrics::time_query("get_play_voided", || {
+            match self.conn().query_row("SELECT voided FROM played_video WHERE id = ?", params![played_video_id], |row| row.get(0)).optional() {
+                Ok(val) => Ok(val),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn void_plays(&self, selection: &PlaySelection, reason: &Option<String>, time_epoch: u64) -> Result<Vec<VoidedPlay>, String> {
+        metrics::time_query("void_plays", || {
+            let mut conn = self.conn();
+            let mut void = || -> rusqlite::Result<Vec<VoidedPlay>> {
+                let transaction = conn.transaction()?;
+                let (query, params): (&str, Vec<Value>) = match selection {
+                    PlaySelection::Single(id) => ("SELECT id, `order`, credits FROM played_video WHERE id = ? AND NOT voided", vec![(*id).into()]),
+                    PlaySelection::Display {display, from_epoch, to_epoch} => (
+                        "SELECT id, `order`, credits FROM played_video WHERE display = ? AND time_epoch >= ? AND time_epoch <= ? AND NOT voided ORDER BY id",
+                        vec![(*display).into(), (*from_epoch as i64).into(), (*to_epoch as i64).into()])
+                };
+                let plays: Vec<VoidedPlay> = transaction.prepare(query)?
+                    .query_map(&params, |row| Ok(VoidedPlay {id: row.get(0)?, order: row.get(1)?, credits: row.get(2)?}))?
+                    .collect::<rusqlite::Result<Vec<VoidedPlay>>>()?;
+                let mut orders: Vec<&String> = plays.iter().map(|p| &p.order).collect();
+                orders.sort();
+                orders.dedup();
+                for order_id in orders {
+                    let mut balance = reconcile_ledger(&transaction, order_id, time_epoch)?;
+                    for play in plays.iter().filter(|p| &p.order == order_id) {
+                        transaction.execute("UPDATE played_video SET voided = 1, voided_epoch = ?, void_reason = ? WHERE id = ?",
+                            params![time_epoch as i64, reason, play.id])?;
+                        balance += play.credits;
+                        transaction.execute(INSERT_LEDGER_ENTRY, params![order_id, time_epoch as i64, LedgerEntryType::Refund.as_str(),
+                            play.credits, balance, play.id, None::<i32>, None::<f64>, reason.clone().unwrap_or_else(|| "voided play".to_owned())])?;
+                    }
+                    transaction.execute("UPDATE orders SET credits = ? WHERE id = ?", params![balance, order_id])?;
+                }
+                transaction.commit()?;
+                Ok(plays)
+            };
+            match void() {
+                Ok(plays) => Ok(plays),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn find_ledger_entries(&self, order_id: &String) -> Result<Vec<LedgerEntry>, String> {
+        metrics::time_query("find_ledger_entries", || {
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<(LedgerEntry, String)>> = conn.prepare(
+                "select id, `order`, time_epoch, entry_type, amount, balance_after, played_video, rate_card, credits_per_second, description
+                from credit_ledger where `order` = ? order by id").and_then(|mut stmt| {
+                    stmt.query_map(params![order_id], |row| Ok((LedgerEntry {
+                        id: row.get(0)?,
+                        order: row.get(1)?,
+                        time_epoch: row.get::<_, i64>(2)? as u64,
+                        entry_type: LedgerEntryType::Opening,
+                        amount: row.get(4)?,
+                        balance_after: row.get(5)?,
+                        played_video: row.get(6)?,
+                        rate_card: row.get(7)?,
+                        credits_per_second: row.get(8)?,
+                        description: row.get(9)?
+                    }, row.get(3)?)))?.collect()
+                });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(res) => Ok(res.into_iter().filter_map(|(entry, entry_type)| match LedgerEntryType::from_str(&entry_type) {
+                    Some(entry_type) => Some(LedgerEntry {entry_type, ..entry}),
+                    None => {error!("unknown ledger entry type {} in ledger entry {}", entry_type, entry.id); None}
+                }).collect())
+            }
+        })
+    }
+
+    fn get_rate_cards_for_location(&self, location: i32) -> Result<Vec<RateCard>, String> {
+        metrics::time_query("get_rate_cards_for_location", || {
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<(RateCard, String)>> = conn.prepare(
+                "select id, location, start_minute, end_minute, credits_per_second, min_credits, rounding
+                from rate_card where location = ? or location is null").and_then(|mut stmt| {
+                    stmt.query_map(params![location], |row| Ok((RateCard {
+                        id: row.get(0)?,
+                        location: row.get(1)?,
+                        start_minute: row.get::<_, i64>(2)? as u32,
+                        end_minute: row.get::<_, i64>(3)? as u32,
+                        credits_per_second: row.get(4)?,
+                        min_credits: row.get(5)?,
+                        rounding: Rounding::Down
+                    }, row.get(6)?)))?.collect()
+                });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(res) => Ok(res.into_iter().filter_map(|(rate_card, rounding)| match Rounding::from_str(&rounding) {
+                    Some(rounding) => Some(RateCard {rounding, ..rate_card}),
+                    None => {error!("ignoring rate card {} with unknown rounding {}", rate_card.id, rounding); None}
+                }).collect())
+            }
+        })
+    }
+
+    fn find_eligible_videos_by_interest(&self, interests: Vec<i32>, time_epoch: u64) -> Result<Option<Vec<AdvertVideoOrder>>, String> {
+        metrics::time_query("find_eligible_videos_by_interest", || {
+            let prep_q = format!(
+                "SELECT advertisement_order.video as video_id, interest, url, length_sec, advertisement_order.orders, orders.credits,
+                (select coalesce(sum(played_video.credits), 0) from played_video where played_video.`order` = orders.id and not played_video.voided),
+                (select min(campaign.start_time_epoch) from advertisement_order campaign where campaign.orders = orders.id),
+                (select max(campaign.end_time_epoch) from advertisement_order campaign where campaign.orders = orders.id)
+                FROM advertisement_video, advertisement_order, orders
+                where interest in ({})
+                and advertisement_order.video = advertisement_video.id
+                and advertisement_order.orders = orders.id
+                and advertisement_order.start_time_epoch <= ?
+                and advertisement_order.end_time_epoch >= ?
+                and orders.credits > 0", placeholders(interests.len()));
+            trace!("{}", prep_q);
+
+            let mut params: Vec<Value> = interests.into_iter().map(Value::from).collect();
+            params.push((time_epoch as i64).into());
+            params.push((time_epoch as i64).into());
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<AdvertVideoOrder>> = conn.prepare(&prep_q).and_then(|mut stmt| {
+                stmt.query_map(&params, |row| Ok(AdvertVideoOrder {
+                    video_id: row.get(0)?,
+                    interest: row.get(1)?,
+                    url: row.get(2)?,
+                    length_sec: row.get(3)?,
+                    order: row.get(4)?,
+                    budget: OrderBudget {
+                        remaining_credits: row.get(5)?,
+                        spent_credits: row.get(6)?,
+                        start_epoch: row.get::<_, i64>(7)? as u64,
+                        end_epoch: row.get::<_, i64>(8)? as u64
+                    }
+                }))?.collect()
+            });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(res) => {
+                    match res.len() {
+                        0 => Ok(None),
+                        _ => Ok(Some(res))
+                    }
+                }
+            }
+        })
+    }
+
+    fn find_webhooks_for_order(&self, order_id: &String) -> Result<Vec<Webhook>, String> {
+        metrics::time_query("find_webhooks_for_order", || {
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<Webhook>> = conn.prepare(
+                "select agency_webhook.id, agency_webhook.agency, url, secret, events, low_credit_threshold
+                from orders, users, agency_webhook
+                where orders.id = ? and orders.user = users.email and users.agency = agency_webhook.agency and agency_webhook.active").and_then(|mut stmt| {
+                    stmt.query_map(params![order_id], |row| {
+                        let events: Option<String> = row.get(4)?;
+                        Ok(Webhook {
+                            id: row.get(0)?,
+                            agency: row.get(1)?,
+                            url: row.get(2)?,
+                            secret: row.get(3)?,
+                            events: events.map(|events| events.split(',').filter_map(|e| WebhookEvent::from_str(e.trim())).collect()),
+                            low_credit_threshold: row.get(5)?
+                        })
+                    })?.collect()
+                });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    fn insert_webhook_delivery(&self, webhook_id: i32, event: WebhookEvent, order_id: &String, payload: &String, time_epoch: u64) -> Result<(), String> {
+        metrics::time_query("insert_webhook_delivery", || {
+            match self.conn().execute(
+                "INSERT INTO webhook_delivery (webhook, event, `order`, payload, created_epoch, next_attempt_epoch) values(?, ?, ?, ?, ?, ?)",
+                params![webhook_id, event.as_str(), order_id, payload, time_epoch as i64, time_epoch as i64]) {
+                Ok(_) => Ok(()),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn find_due_webhook_deliveries(&self, time_epoch: u64, limit: u32) -> Result<Vec<WebhookDelivery>, String> {
+        metrics::time_query("find_due_webhook_deliveries", || {
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<(i32, String, Option<String>, String, String, i64)>> = conn.prepare(
+                "select webhook_delivery.id, url, secret, event, payload, attempts
+                from webhook_delivery, agency_webhook
+                where webhook_delivery.webhook = agency_webhook.id and status = 'pending' and next_attempt_epoch <= ?
+                order by next_attempt_epoch, webhook_delivery.id limit ?").and_then(|mut stmt| {
+                    stmt.query_map(params![time_epoch as i64, limit as i64],
+                        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?)))?.collect()
+                });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(res) => Ok(res.into_iter().filter_map(|(id, url, secret, event, payload, attempts)| {
+                    WebhookEvent::from_str(&event).map(|event| WebhookDelivery {id, url, secret, event, payload, attempts: attempts as u32})
+                }).collect())
+            }
+        })
+    }
+
+    fn update_webhook_delivery(&self, delivery_id: i32, status: DeliveryStatus, attempts: u32, next_attempt_epoch: u64,
+                               response_code: Option<u16>, error: Option<String>, time_epoch: u64) -> Result<(), String> {
+        metrics::time_query("update_webhook_delivery", || {
+            match self.conn().execute(
+                "UPDATE webhook_delivery set status = ?, attempts = ?, next_attempt_epoch = ?, response_code = ?, last_error = ?, last_attempt_epoch = ?
+                where id = ?",
+                params![status.as_str(), attempts as i64, next_attempt_epoch as i64, response_code.map(i64::from), error, time_epoch as i64, delivery_id]) {
+                Ok(_) => Ok(()),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn find_unrecorded_campaign_events(&self, after_epoch: u64, until_epoch: u64) -> Result<Vec<(AdvertisementOrder, WebhookEvent)>, String> {
+        metrics::time_query("find_unrecorded_campaign_events", || {
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<(AdvertisementOrder, String)>> = conn.prepare(
+                "select video, orders, start_time_epoch, end_time_epoch, 'campaign_started' from advertisement_order
+                where start_time_epoch > ? and start_time_epoch <= ?
+                and not exists (select 1 from campaign_event where campaign_event.video = advertisement_order.video
+                    and campaign_event.orders = advertisement_order.orders and event = 'campaign_started')
+                union all
+                select video, orders, start_time_epoch, end_time_epoch, 'campaign_ended' from advertisement_order
+                where end_time_epoch > ? and end_time_epoch <= ?
+                and not exists (select 1 from campaign_event where campaign_event.video = advertisement_order.video
+                    and campaign_event.orders = advertisement_order.orders and event = 'campaign_ended')").and_then(|mut stmt| {
+                    stmt.query_map(params![after_epoch as i64, until_epoch as i64, after_epoch as i64, until_epoch as i64], |row| Ok((AdvertisementOrder {
+                        video: row.get(0)?,
+                        order: row.get(1)?,
+                        start_time_epoch: row.get::<_, i64>(2)? as u64,
+                        end_time_epoch: row.get::<_, i64>(3)? as u64
+                    }, row.get(4)?)))?.collect()
+                });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(res) => Ok(res.into_iter().map(|(campaign, event)| (campaign, WebhookEvent::from_str(&event).unwrap())).collect())
+            }
+        })
+    }
+
+    fn record_campaign_event(&self, campaign: &AdvertisementOrder, event: WebhookEvent, time_epoch: u64) -> Result<bool, String> {
+        metrics::time_query("record_campaign_event", || {
+            match self.conn().execute("INSERT OR IGNORE INTO campaign_event (video, orders, event, time_epoch) values(?, ?, ?, ?)",
+                params![campaign.video, campaign.order, event.as_str(), time_epoch as i64]) {
+                Ok(count) => Ok(count == 1),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn insert_log_entries(&self, entries: &Vec<LogEntry>) -> Result<(), String> {
+        metrics::time_query("insert_log_entries", || {
+            let mut conn = self.conn();
+            let mut insert = || -> rusqlite::Result<()> {
+                let transaction = conn.transaction()?;
+                for entry in entries.iter() {
+                    transaction.execute("INSERT INTO device_log (device, severity, firmware, time_epoch, message, fields) values(?, ?, ?, ?, ?, ?)",
+                        params![entry.device, entry.severity.as_str(), entry.firmware, entry.time_epoch as i64, entry.message, entry.fields])?;
+                }
+                transaction.commit()
+            };
+            match insert() {
+                Ok(()) => Ok(()),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn find_log_entries(&self, filter: &LogFilter, limit: u32) -> Result<Vec<LogEntry>, String> {
+        metrics::time_query("find_log_entries", || {
+            let mut conditions: Vec<String> = vec!["1 = 1".to_owned()];
+            let mut params: Vec<Value> = vec![];
+            if let Some(device) = &filter.device {
+                conditions.push("device = ?".to_owned());
+                params.push(device.clone().into());
+            }
+            if let Some(severity) = filter.severity {
+                conditions.push("severity = ?".to_owned());
+                params.push(severity.as_str().to_owned().into());
+            }
+            if let Some(min_severity) = filter.min_severity {
+                let severities: Vec<Severity> = Severity::all().into_iter().filter(|s| *s >= min_severity).collect();
+                conditions.push(format!("severity in ({})", placeholders(severities.len())));
+                for severity in severities {
+                    params.push(severity.as_str().to_owned().into());
+                }
+            }
+            if let Some(from) = filter.from_epoch {
+                conditions.push("time_epoch >= ?".to_owned());
+                params.push((from as i64).into());
+            }
+            if let Some(to) = filter.to_epoch {
+                conditions.push("time_epoch <= ?".to_owned());
+                params.push((to as i64).into());
+            }
+            params.push((limit as i64).into());
+            let prep_q = format!(
+                "SELECT device, severity, firmware, time_epoch, message, fields FROM device_log
+                where {}
+                order by time_epoch desc, id desc
+                limit ?", conditions.join(" and "));
+
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<LogEntry>> = conn.prepare(&prep_q).and_then(|mut stmt| {
+                stmt.query_map(&params, |row| {
+                    let severity: String = row.get(1)?;
+                    Ok(LogEntry {
+                        device: row.get(0)?,
+                        severity: Severity::from_str(&severity).unwrap_or(Severity::Info),
+                        firmware: row.get(2)?,
+                        time_epoch: row.get::<_, i64>(3)? as u64,
+                        message: row.get(4)?,
+                        fields: row.get(5)?
+                    })
+                })?.collect()
+            });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    fn upsert_heartbeat(&self, heartbeat: &Heartbeat) -> Result<(), String> {
+        metrics::time_query("upsert_heartbeat", || {
+            match self.conn().execute(
+                "INSERT INTO device_heartbeat (device_type, device, time_epoch, uptime_sec, firmware, free_memory, last_error) values(?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT(device_type, device) DO UPDATE SET time_epoch = excluded.time_epoch, uptime_sec = excluded.uptime_sec,
+                firmware = excluded.firmware, free_memory = excluded.free_memory, last_error = excluded.last_error",
+                params![heartbeat.device_type.as_str(), heartbeat.device, heartbeat.time_epoch as i64, heartbeat.status.uptime_sec.map(|u| u as i64),
+                    heartbeat.status.firmware, heartbeat.status.free_memory.map(|m| m as i64), heartbeat.status.last_error]) {
+                Ok(_) => Ok(()),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn touch_heartbeat(&self, device_type: DeviceType, device_id: &String, time_epoch: u64) -> Result<(), String> {
+        metrics::time_query("touch_heartbeat", || {
+            match self.conn().execute(
+                "INSERT INTO device_heartbeat (device_type, device, time_epoch) values(?, ?, ?)
+                ON CONFLICT(device_type, device) DO UPDATE SET time_epoch = excluded.time_epoch",
+                params![device_type.as_str(), device_id, time_epoch as i64]) {
+                Ok(_) => Ok(()),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn find_stale_devices(&self, before_epoch: u64) -> Result<Vec<StaleDevice>, String> {
+        metrics::time_query("find_stale_devices", || {
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<StaleDevice>> = conn.prepare(
+                "select 'receiver', rfid_receiver.id, time_epoch, uptime_sec, firmware, free_memory, last_error
+                from rfid_receiver left join device_heartbeat on device_type = 'receiver' and device = rfid_receiver.id
+                where time_epoch is null or time_epoch < ?
+                union all
+                select 'display', cast(display.id as text), time_epoch, uptime_sec, firmware, free_memory, last_error
+                from display left join device_heartbeat on device_type = 'display' and device = cast(display.id as text)
+                where time_epoch is null or time_epoch < ?").and_then(|mut stmt| {
+                    stmt.query_map(params![before_epoch as i64, before_epoch as i64], |row| {
+                        let device_type: String = row.get(0)?;
+                        let device_type = DeviceType::from_str(&device_type).unwrap();
+                        let device: String = row.get(1)?;
+                        let time_epoch: Option<i64> = row.get(2)?;
+                        let status = DeviceStatus {
+                            uptime_sec: row.get::<_, Option<i64>>(3)?.map(|u| u as u64),
+                            firmware: row.get(4)?,
+                            free_memory: row.get::<_, Option<i64>>(5)?.map(|m| m as u64),
+                            last_error: row.get(6)?
+                        };
+                        let last_heartbeat = time_epoch.map(|time_epoch| Heartbeat {device_type, device: device.clone(), time_epoch: time_epoch as u64, status});
+                        Ok(StaleDevice {device_type, device, last_heartbeat})
+                    })?.collect()
+                });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    fn count_trackers_per_location(&self) -> Result<Vec<(i32, i64)>, String> {
+        metrics::time_query("count_trackers_per_location", || {
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<(i32, i64)>> = conn.prepare(
+                "select location, count(*) from rfid_tracker where location is not null group by location").and_then(|mut stmt| {
+                    stmt.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?.collect()
+                });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    fn find_displays_at_location(&self, location: i32) -> Result<Vec<Display>, String> {
+        metrics::time_query("find_displays_at_location", || {
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<Display>> = conn.prepare(
+                "select id, location from display where location = ? order by id").and_then(|mut stmt| {
+                    stmt.query_map(params![location], |row| Ok(Display {id: row.get(0)?, location: row.get(1)?}))?.collect()
+                });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    fn find_receivers_at_location(&self, location: i32) -> Result<Vec<Receiver>, String> {
+        metrics::time_query("find_receivers_at_location", || {
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<Receiver>> = conn.prepare(
+                "select id, location from rfid_receiver where location = ? order by id").and_then(|mut stmt| {
+                    stmt.query_map(params![location], |row| Ok(Receiver {id: row.get(0)?, location: row.get(1)?}))?.collect()
+                });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    fn insert_occupancy(&self, location: i32, trackers: i64, time_epoch: u64) -> Result<(), String> {
+        metrics::time_query("insert_occupancy", || {
+            match self.conn().execute(
+                "INSERT INTO occupancy_history (location, time_epoch, trackers) values(?, ?, ?)",
+                params![location, time_epoch as i64, trackers]) {
+                Ok(_) => Ok(()),
+                Err(e) => print_err_get_mess(e)
+            }
+        })
+    }
+
+    fn find_occupancy_history(&self, location: i32, from_epoch: u64, to_epoch: u64) -> Result<Vec<(u64, i64)>, String> {
+        metrics::time_query("find_occupancy_history", || {
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<(u64, i64)>> = conn.prepare(
+                "select time_epoch, trackers from occupancy_history
+                where location = ? and (time_epoch > ? and time_epoch < ? or id = (
+                    select id from occupancy_history where location = ? and time_epoch <= ? order by time_epoch desc, id desc limit 1))
+                order by time_epoch, id").and_then(|mut stmt| {
+                    stmt.query_map(params![location, from_epoch as i64, to_epoch as i64, location, from_epoch as i64],
+                        |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?)))?.collect()
+                });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    /**
+     * The database is local, it answers as soon as the connection is free
+     */
+    fn ping(&self, _timeout: Duration) -> Result<(), String> {
+        metrics::time_query("ping", || {
+            let conn = self.conn();
+            conn.query_row("SELECT 1", NO_PARAMS, |row| row.get::<_, i32>(0)).map(|_| ()).map_err(|e| format!("{}", e))
+        })
+    }
+
+    fn list_orders(&self) -> Result<Vec<OrderSummary>, String> {
+        metrics::time_query("list_orders", || {
+            let conn = self.conn();
+            let selected: rusqlite::Result<Vec<OrderSummary>> = conn.prepare(
+                "select orders.id, orders.credits, orders.user, count(played_video.id), coalesce(sum(played_video.credits), 0)
+                from orders left join played_video on played_video.`order` = orders.id and not played_video.voided
+                group by orders.id, orders.credits, orders.user order by orders.id").and_then(|mut stmt| {
+                    stmt.query_map(NO_PARAMS, |row| Ok(OrderSummary {
+                        order: Order {id: row.get(0)?, credits: row.get(1)?, user: row.get(2)?},
+                        plays: row.get(3)?,
+                        spent_credits: row.get(4)?
+                    }))?.collect()
+                });
+            match selected {
+                Err(e) => print_err_get_mess(e),
+                Ok(res) => Ok(res)
+            }
+        })
+    }
+
+    fn execute_statements(&self, statements: &Vec<String>) -> Result<(), String> {
+        metrics::time_query("execute_statements", || {
+            let mut conn = self.conn();
+            let transaction = conn.transaction().map_err(|e| format!("{}", e))?;
+            for statement in statements.iter() {
+                transaction.execute_batch(statement).map_err(|e| format!("{}: {}", statement, e))?;
+            }
+            transaction.commit().map_err(|e| format!("{}", e))
+        })
+    }
+
+    /**
+     * Applies the SQLite migrations not yet applied, see persistance::migrations.
+     * Each migration is applied in a transaction of its own, a failing migration leaves nothing behind.
+     */
+    fn migrate(&self, time_epoch: u64) -> Result<Vec<u32>, String> {
+        metrics::time_query("migrate", || {
+            let mut conn = self.conn();
+            conn.execute_batch(migrations::CREATE_SCHEMA_VERSION).map_err(|e| format!("{}", e))?;
+            let applied: Vec<(u32, String)> = conn.prepare("SELECT version, checksum FROM schema_version ORDER BY version")
+                .and_then(|mut stmt| stmt.query_map(NO_PARAMS, |row| Ok((row.get::<_, i64>(0)? as u32, row.get(1)?)))?.collect())
+                .map_err(|e| format!("{}", e))?;
+            let pending = migrations::pending_migrations(migrations::SQLITE_MIGRATIONS, &applied)?;
+            let mut versions = vec![];
+            for migration in pending {
+                info!("applying sqlite migration {} {}", migration.version, migration.name);
+                let mut apply = || -> rusqlite::Result<()> {
+                    let transaction = conn.transaction()?;
+                    for statement in migration.statements() {
+                        transaction.execute_batch(&statement)?;
+                    }
+                    transaction.execute("INSERT INTO schema_version (version, name, checksum, applied_epoch) values(?, ?, ?, ?)",
+                        params![migration.version as i64, migration.name, migration.checksum(), time_epoch as i64])?;
+                    transaction.commit()
+                };
+                apply().map_err(|e| format!("migration {} {} failed: {}", migration.version, migration.name, e))?;
+                versions.push(migration.version);
+            }
+            Ok(versions)
+        })
+    }
+}
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store_with_order(credits: i32) -> SqliteStore {
+        let store = SqliteStore::open(":memory:").unwrap();
+        assert_eq!(store.migrate(0).unwrap(), vec![1, 2, 3]);
+        store.execute_statements(&migrations::split_statements(&format!("
+            insert into location (name) values('location1');
+            insert into interest (name) values('sport');
+            insert into interest (name) values('movies');
+            insert into display (location) values(1);
+            insert into rfid_receiver (id, location) values('receiver1', 1);
+            insert into rfid_tracker (id) values('tracker1');
+            insert into rfid_tracker (id) values('tracker2');
+            insert into tracker_interest (tracker, interest, weight) values('tracker1', 1, 10);
+            insert into tracker_interest (tracker, interest, weight) values('tracker1', 2, 5);
+            insert into tracker_interest (tracker, interest, weight) values('tracker2', 2, 20);
+            insert into advertisement_video (url, length_sec, interest) values('video1', 10, 1);
+            insert into agency (orgnr, name) values('1', 'agency1');
+            insert into users (username, email, pass_hash, agency) values('user1', 'email@example.com', 'HASH', '1');
+            insert into orders (id, credits, user) values('1', {}, 'email@example.com');
+            insert into advertisement_order (video, orders, start_time_epoch, end_time_epoch) values(1, '1', 0, 2147483647);", credits))).unwrap();
+        store
+    }
+
+    fn play(credits: i32) -> PlayedVideo {
+        PlayedVideo {video: 1, display: 1, order: "1".to_owned(), time_epoch: 100, length_sec: 10, complete: true, credits}
+    }
+
+    #[test]
+    fn migrations_are_applied_once_unittest() {
+        let store = store_with_order(100);
+        assert_eq!(store.migrate(0).unwrap(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn trackers_are_counted_at_their_location_unittest() {
+        let store = store_with_order(100);
+        assert_eq!(store.count_trackers_per_location().unwrap(), vec![]);
+        store.register_tracker_to_receiver(&"receiver1".to_owned(), &"tracker1".to_owned()).unwrap();
+        store.register_tracker_to_receiver(&"receiver1".to_owned(), &"tracker2".to_owned()).unwrap();
+        assert_eq!(store.count_trackers_per_location().unwrap(), vec![(1, 2)]);
+        assert_eq!(store.expire_trackers(0).unwrap(), 0, "trackers registered now should not be expired");
+        assert!(store.unregister_tracker_from_receiver(&"receiver1".to_owned(), &"tracker2".to_owned()).unwrap());
+        assert_eq!(store.count_trackers_per_location().unwrap(), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn trackers_leave_a_location_when_every_receiver_lost_them_unittest() {
+        let store = store_with_order(100);
+        store.execute_statements(&migrations::split_statements("
+            insert into location (name) values('location2');
+            insert into rfid_receiver (id, location) values('receiver2', 1);
+            insert into rfid_receiver (id, location) values('receiver3', 2);
+            update rfid_tracker set location = 1 where id = 'tracker2';")).unwrap();
+        let location = |tracker: &str| store.get_tracker_by_id(&tracker.to_owned()).unwrap().unwrap().location;
+        store.register_tracker_to_receiver(&"receiver1".to_owned(), &"tracker1".to_owned()).unwrap();
+        store.register_tracker_to_receiver(&"receiver2".to_owned(), &"tracker1".to_owned()).unwrap();
+        assert!(!store.unregister_tracker_from_receiver(&"receiver1".to_owned(), &"tracker1".to_owned()).unwrap());
+        assert_eq!(location("tracker1"), Some(1), "receiver2 still sees the tracker");
+        assert!(store.unregister_tracker_from_receiver(&"receiver2".to_owned(), &"tracker1".to_owned()).unwrap());
+        assert_eq!(location("tracker1"), None);
+
+        store.register_tracker_to_receiver(&"receiver1".to_owned(), &"tracker1".to_owned()).unwrap();
+        store.register_tracker_to_receiver(&"receiver3".to_owned(), &"tracker1".to_owned()).unwrap();
+        assert!(!store.unregister_tracker_from_receiver(&"receiver1".to_owned(), &"tracker1".to_owned()).unwrap(),
+            "the tracker has moved to the location of receiver3");
+        assert!(store.unregister_tracker_from_receiver(&"receiver3".to_owned(), &"tracker1".to_owned()).unwrap());
+
+        assert!(store.unregister_tracker_from_receiver(&"receiver1".to_owned(), &"tracker2".to_owned()).unwrap(),
+            "a tracker registered without presences should be unregistered by any receiver at its location");
+        assert_eq!(location("tracker2"), None);
+    }
+
+    #[test]
+    fn eligible_videos_need_credits_and_a_running_campaign_unittest() {
+        let store = store_with_order(100);
+        let videos: Vec<(i32, String, String)> = store.find_eligible_videos_by_interest(vec![1, 2], 100).unwrap().unwrap().into_iter()
+            .map(|v| (v.video_id, v.url, v.order)).collect();
+        assert_eq!(videos, vec![(1, "video1".to_owned(), "1".to_owned())]);
+        assert!(store.find_eligible_videos_by_interest(vec![2], 100).unwrap().is_none());
+        let empty = store_with_order(0);
+        assert!(empty.find_eligible_videos_by_interest(vec![1], 100).unwrap().is_none());
+    }
+
+    #[test]
+    fn plays_are_debited_and_refunded_in_the_ledger_unittest() {
+        let store = store_with_order(100);
+        let rate_card = RateCard {id: 0, location: None, start_minute: 0, end_minute: 1440, credits_per_second: 1.0, min_credits: 1, rounding: Rounding::Down};
+        assert_eq!(store.register_play(&play(10), &rate_card, &"signature".to_owned()).unwrap(), Some(100));
+        assert_eq!(store.get_order_by_id(&"1".to_owned()).unwrap().unwrap().credits, 90);
+        assert_eq!(store.get_play_voided(1).unwrap(), Some(false));
+        assert_eq!(store.list_orders().unwrap()[0].spent_credits, 10);
+
+        let voided = store.void_plays(&PlaySelection::Display {display: 1, from_epoch: 0, to_epoch: 200}, &None, 200).unwrap();
+        assert_eq!(voided, vec![VoidedPlay {id: 1, order: "1".to_owned(), credits: 10}]);
+        assert!(store.void_plays(&PlaySelection::Single(1), &None, 200).unwrap().is_empty(), "a play should only be voided once");
+        assert_eq!(store.get_order_by_id(&"1".to_owned()).unwrap().unwrap().credits, 100);
+        let entries: Vec<(LedgerEntryType, i32, i32)> = store.find_ledger_entries(&"1".to_owned()).unwrap().iter()
+            .map(|e| (e.entry_type, e.amount, e.balance_after)).collect();
+        assert_eq!(entries, vec![(LedgerEntryType::Opening, 100, 100), (LedgerEntryType::Debit, -10, 90), (LedgerEntryType::Refund, 10, 100)]);
+    }
+
+    #[test]
+    fn play_tokens_and_campaign_events_are_recorded_once_unittest() {
+        let store = store_with_order(100);
+        let rate_card = RateCard {id: 0, location: None, start_minute: 0, end_minute: 1440, credits_per_second: 1.0, min_credits: 1, rounding: Rounding::Down};
+        assert_eq!(store.register_play(&play(10), &rate_card, &"signature".to_owned()).unwrap(), Some(100));
+        assert_eq!(store.register_play(&play(10), &rate_card, &"signature".to_owned()).unwrap(), None,
+            "a play with a redeemed token should not be registered");
+        assert_eq!(store.get_order_by_id(&"1".to_owned()).unwrap().unwrap().credits, 90);
+        let campaign = AdvertisementOrder {video: 1, order: "1".to_owned(), start_time_epoch: 0, end_time_epoch: 2147483647};
+        assert!(store.record_campaign_event(&campaign, WebhookEvent::CampaignStarted, 100).unwrap());
+        assert!(!store.record_campaign_event(&campaign, WebhookEvent::CampaignStarted, 100).unwrap());
+    }
+
+    #[test]
+    fn heartbeats_replace_the_last_one_unittest() {
+        let store = store_with_order(100);
+        let status = DeviceStatus {uptime_sec: Some(10), firmware: Some("1.0".to_owned()), free_memory: None, last_error: None};
+        store.upsert_heartbeat(&Heartbeat {device_type: DeviceType::Receiver, device: "receiver1".to_owned(), time_epoch: 50, status}).unwrap();
+        store.touch_heartbeat(DeviceType::Receiver, &"receiver1".to_owned(), 150).unwrap();
+        let stale = store.find_stale_devices(100).unwrap();
+        assert_eq!(stale.len(), 1, "only the display should be stale");
+        assert_eq!(stale[0].device, "1");
+        assert!(stale[0].last_heartbeat.is_none());
+        let stale = store.find_stale_devices(200).unwrap();
+        let receiver = stale.iter().find(|d| d.device_type == DeviceType::Receiver).unwrap();
+        assert_eq!(receiver.last_heartbeat.as_ref().unwrap().status.firmware, Some("1.0".to_owned()), "touching should keep the last status");
+    }
+
+    #[test]
+    fn log_entries_are_newest_first_unittest() {
+        let store = store_with_order(100);
+        let entry = |time_epoch: u64, severity: Severity, message: &str| LogEntry {device: Some("d".to_owned()), severity, firmware: None,
+            time_epoch, message: message.to_owned(), fields: None};
+        store.insert_log_entries(&vec![entry(1, Severity::Error, "a"), entry(2, Severity::Info, "b"), entry(2, Severity::Error, "c")]).unwrap();
+        let messages: Vec<String> = store.find_log_entries(&LogFilter::default(), 2).unwrap().into_iter().map(|e| e.message).collect();
+        assert_eq!(messages, vec!["c", "b"]);
+        let filter = LogFilter {min_severity: Some(Severity::Warn), ..LogFilter::default()};
+        let messages: Vec<String> = store.find_log_entries(&filter, 10).unwrap().into_iter().map(|e| e.message).collect();
+        assert_eq!(messages, vec!["c", "a"]);
+    }
+
+    #[test]
+    fn occupancy_history_starts_at_the_last_record_before_unittest() {
+        let store = store_with_order(100);
+        store.execute_statements(&vec!["insert into location (name) values('location2')".to_owned()]).unwrap();
+        for (location, time_epoch, trackers) in vec![(1, 50, 2), (1, 130, 4), (2, 100, 7), (1, 150, 0), (1, 150, 3)] {
+            store.insert_occupancy(location, trackers, time_epoch).unwrap();
+        }
+        assert_eq!(store.find_occupancy_history(1, 100, 150).unwrap(), vec![(50, 2), (130, 4)]);
+        assert_eq!(store.find_occupancy_history(1, 0, 200).unwrap(), vec![(50, 2), (130, 4), (150, 0), (150, 3)]);
+        assert_eq!(store.find_occupancy_history(1, 150, 200).unwrap(), vec![(150, 3)], "the last of the records at the same time should count");
+    }
+}
@@ -0,0 +1,258 @@
+/**
+ * The storage the services work against, one implementation per backend.
+ *
+ * The store is created when the app starts and handed to rocket as managed state, endpoints get it with the
+ * Storage request guard and pass it on to the services. Commands and background work are given the same store.
+ */
+use std::sync::Arc;
+use std::time::Duration;
+use crate::model::*;
+use super::db;
+
+/**
+ * A store shared by the endpoints, the commands and the webhook dispatcher
+ */
+pub type SharedStore = Arc<dyn Store>;
+
+/**
+ * Every query of the app. See the MySQL implementation in persistance::db for the documentation of each query,
+ * other implementations must behave the same.
+ */
+pub trait Store: Send + Sync {
+    fn unregister_tracker_from_receiver(&self, receiver_id: &String, tracker_id: &String) -> Result<bool, String>;
+    fn register_tracker_to_receiver(&self, receiver_id: &String, tracker_id: &String) -> Result<(), String>;
+    fn expire_trackers(&self, before_epoch: u64) -> Result<u64, String>;
+    fn location_exists(&self, location: i32) -> Result<bool, String>;
+    fn insert_tracker(&self, tracker: &Tracker) -> Result<(), String>;
+    fn insert_receiver(&self, receiver: &Receiver) -> Result<(), String>;
+    fn insert_display(&self, location: i32) -> Result<i32, String>;
+    fn get_tracker_by_id(&self, tracker_id: &String) -> Result<Option<Tracker>, String>;
+    fn get_receiver_by_id(&self, receiver_id: &String) -> Result<Option<Receiver>, String>;
+    fn get_display_location(&self, display_id: i32) -> Option<i32>;
+    fn get_display_by_id(&self, display_id: i32) -> Result<Option<Display>, String>;
+    fn get_tracker_interests(&self, tracker_id: &String) -> Result<Vec<(i32, f32)>, String>;
+    fn find_present_trackers(&self) -> Result<Vec<PresentTracker>, String>;
+    fn get_advertisement_video_by_id(&self, video_id: i32) -> Result<Option<AdvertVideo>, String>;
+    fn get_order_by_id(&self, order_id: &String) -> Result<Option<Order>, String>;
+    fn get_advertisement_order(&self, video_id: i32, order_id: &String) -> Result<Option<AdvertisementOrder>, String>;
+    fn register_play(&self, played: &PlayedVideo, rate_card: &RateCard, play_token: &String) -> Result<Option<i32>, String>;
+    fn get_play_voided(&self, played_video_id: i32) -> Result<Option<bool>, String>;
+    fn void_plays(&self, selection: &PlaySelection, reason: &Option<String>, time_epoch: u64) -> Result<Vec<VoidedPlay>, String>;
+    fn find_ledger_entries(&self, order_id: &String) -> Result<Vec<LedgerEntry>, String>;
+    fn get_rate_cards_for_location(&self, location: i32) -> Result<Vec<RateCard>, String>;
+    fn find_eligible_videos_by_interest(&self, interests: Vec<i32>, time_epoch: u64) -> Result<Option<Vec<AdvertVideoOrder>>, String>;
+    fn find_webhooks_for_order(&self, order_id: &String) -> Result<Vec<Webhook>, String>;
+    fn insert_webhook_delivery(&self, webhook_id: i32, event: WebhookEvent, order_id: &String, payload: &String, time_epoch: u64) -> Result<(), String>;
+    fn find_due_webhook_deliveries(&self, time_epoch: u64, limit: u32) -> Result<Vec<WebhookDelivery>, String>;
+    fn update_webhook_delivery(&self, delivery_id: i32, status: DeliveryStatus, attempts: u32, next_attempt_epoch: u64,
+                               response_code: Option<u16>, error: Option<String>, time_epoch: u64) -> Result<(), String>;
+    fn find_unrecorded_campaign_events(&self, after_epoch: u64, until_epoch: u64) -> Result<Vec<(AdvertisementOrder, WebhookEvent)>, String>;
+    fn record_campaign_event(&self, campaign: &AdvertisementOrder, event: WebhookEvent, time_epoch: u64) -> Result<bool, String>;
+    fn insert_log_entries(&self, entries: &Vec<LogEntry>) -> Result<(), String>;
+    fn find_log_entries(&self, filter: &LogFilter, limit: u32) -> Result<Vec<LogEntry>, String>;
+    fn upsert_heartbeat(&self, heartbeat: &Heartbeat) -> Result<(), String>;
+    fn touch_heartbeat(&self, device_type: DeviceType, device_id: &String, time_epoch: u64) -> Result<(), String>;
+    fn find_stale_devices(&self, before_epoch: u64) -> Result<Vec<StaleDevice>, String>;
+    fn count_trackers_per_location(&self) -> Result<Vec<(i32, i64)>, String>;
+    fn find_displays_at_location(&self, location: i32) -> Result<Vec<Display>, String>;
+    fn find_receivers_at_location(&self, location: i32) -> Result<Vec<Receiver>, String>;
+    fn insert_occupancy(&self, location: i32, trackers: i64, time_epoch: u64) -> Result<(), String>;
+    fn find_occupancy_history(&self, location: i32, from_epoch: u64, to_epoch: u64) -> Result<Vec<(u64, i64)>, String>;
+    fn ping(&self, timeout: Duration) -> Result<(), String>;
+    fn list_orders(&self) -> Result<Vec<OrderSummary>, String>;
+    fn execute_statements(&self, statements: &Vec<String>) -> Result<(), String>;
+    fn migrate(&self, time_epoch: u64) -> Result<Vec<u32>, String>;
+}
+
+/**
+ * Groups (tracker, location, interest, weight) rows ordered by tracker into the present trackers, for find_present_trackers.
+ * Trackers without interests come as a single row without interest and weight.
+ */
+pub fn group_present_trackers(rows: Vec<(String, i32, Option<i32>, Option<f32>)>) -> Vec<PresentTracker> {
+    let mut trackers: Vec<PresentTracker> = vec![];
+    for (id, location, interest, weight) in rows {
+        if trackers.last().map(|t| t.id != id).unwrap_or(true) {
+            trackers.push(PresentTracker {id, location, interests: vec![]});
+        }
+        if let (Some(interest), Some(weight)) = (interest, weight) {
+            trackers.last_mut().unwrap().interests.push((interest, weight));
+        }
+    }
+    trackers
+}
+
+/**
+ * The MySQL store, backed by the connection pool of persistance::db
+ */
+pub struct MysqlStore;
+
+impl Store for MysqlStore {
+    fn unregister_tracker_from_receiver(&self, receiver_id: &String, tracker_id: &String) -> Result<bool, String> {
+        db::unregister_tracker_from_receiver(receiver_id, tracker_id)
+    }
+
+    fn register_tracker_to_receiver(&self, receiver_id: &String, tracker_id: &String) -> Result<(), String> {
+        db::register_tracker_to_receiver(receiver_id, tracker_id)
+    }
+
+    fn expire_trackers(&self, before_epoch: u64) -> Result<u64, String> {
+        db::expire_trackers(before_epoch)
+    }
+
+    fn location_exists(&self, location: i32) -> Result<bool, String> {
+        db::location_exists(location)
+    }
+
+    fn insert_tracker(&self, tracker: &Tracker) -> Result<(), String> {
+        db::insert_tracker(tracker)
+    }
+
+    fn insert_receiver(&self, receiver: &Receiver) -> Result<(), String> {
+        db::insert_receiver(receiver)
+    }
+
+    fn insert_display(&self, location: i32) -> Result<i32, String> {
+        db::insert_display(location)
+    }
+
+    fn get_tracker_by_id(&self, tracker_id: &String) -> Result<Option<Tracker>, String> {
+        db::get_tracker_by_id(tracker_id)
+    }
+
+    fn get_receiver_by_id(&self, receiver_id: &String) -> Result<Option<Receiver>, String> {
+        db::get_receiver_by_id(receiver_id)
+    }
+
+    fn get_display_location(&self, display_id: i32) -> Option<i32> {
+        db::get_display_location(display_id)
+    }
+
+    fn get_display_by_id(&self, display_id: i32) -> Result<Option<Display>, String> {
+        db::get_display_by_id(display_id)
+    }
+
+    fn get_tracker_interests(&self, tracker_id: &String) -> Result<Vec<(i32, f32)>, String> {
+        db::get_tracker_interests(tracker_id)
+    }
+
+    fn find_present_trackers(&self) -> Result<Vec<PresentTracker>, String> {
+        db::find_present_trackers()
+    }
+
+    fn get_advertisement_video_by_id(&self, video_id: i32) -> Result<Option<AdvertVideo>, String> {
+        db::get_advertisement_video_by_id(video_id)
+    }
+
+    fn get_order_by_id(&self, order_id: &String) -> Result<Option<Order>, String> {
+        db::get_order_by_id(order_id)
+    }
+
+    fn get_advertisement_order(&self, video_id: i32, order_id: &String) -> Result<Option<AdvertisementOrder>, String> {
+        db::get_advertisement_order(video_id, order_id)
+    }
+
+    fn register_play(&self, played: &PlayedVideo, rate_card: &RateCard, play_token: &String) -> Result<Option<i32>, String> {
+        db::register_play(played, rate_card, play_token)
+    }
+
+    fn get_play_voided(&self, played_video_id: i32) -> Result<Option<bool>, String> {
+        db::get_play_voided(played_video_id)
+    }
+
+    fn void_plays(&self, selection: &PlaySelection, reason: &Option<String>, time_epoch: u64) -> Result<Vec<VoidedPlay>, String> {
+        db::void_plays(selection, reason, time_epoch)
+    }
+
+    fn find_ledger_entries(&self, order_id: &String) -> Result<Vec<LedgerEntry>, String> {
+        db::find_ledger_entries(order_id)
+    }
+
+    fn get_rate_cards_for_location(&self, location: i32) -> Result<Vec<RateCard>, String> {
+        db::get_rate_cards_for_location(location)
+    }
+
+    fn find_eligible_videos_by_interest(&self, interests: Vec<i32>, time_epoch: u64) -> Result<Option<Vec<AdvertVideoOrder>>, String> {
+        db::find_eligible_videos_by_interest(interests, time_epoch)
+    }
+
+    fn find_webhooks_for_order(&self, order_id: &String) -> Result<Vec<Webhook>, String> {
+        db::find_webhooks_for_order(order_id)
+    }
+
+    fn insert_webhook_delivery(&self, webhook_id: i32, event: WebhookEvent, order_id: &String, payload: &String, time_epoch: u64) -> Result<(), String> {
+        db::insert_webhook_delivery(webhook_id, event, order_id, payload, time_epoch)
+    }
+
+    fn find_due_webhook_deliveries(&self, time_epoch: u64, limit: u32) -> Result<Vec<WebhookDelivery>, String> {
+        db::find_due_webhook_deliveries(time_epoch, limit)
+    }
+
+    fn update_webhook_delivery(&self, delivery_id: i32, status: DeliveryStatus, attempts: u32, next_attempt_epoch: u64,
+                               response_code: Option<u16>, error: Option<String>, time_epoch: u64) -> Result<(), String> {
+        db::update_webhook_delivery(delivery_id, status, attempts, next_attempt_epoch, response_code, error, time_epoch)
+    }
+
+    fn find_unrecorded_campaign_events(&self, after_epoch: u64, until_epoch: u64) -> Result<Vec<(AdvertisementOrder, WebhookEvent)>, String> {
+        db::find_unrecorded_campaign_events(after_epoch, until_epoch)
+    }
+
+    fn record_campaign_event(&self, campaign: &AdvertisementOrder, event: WebhookEvent, time_epoch: u64) -> Result<bool, String> {
+        db::record_campaign_event(campaign, event, time_epoch)
+    }
+
+    fn insert_log_entries(&self, entries: &Vec<LogEntry>) -> Result<(), String> {
+        db::insert_log_entries(entries)
+    }
+
+    fn find_log_entries(&self, filter: &LogFilter, limit: u32) -> Result<Vec<LogEntry>, String> {
+        db::find_log_entries(filter, limit)
+    }
+
+    fn upsert_heartbeat(&self, heartbeat: &Heartbeat) -> Result<(), String> {
+        db::upsert_heartbeat(heartbeat)
+    }
+
+    fn touch_heartbeat(&self, device_type: DeviceType, device_id: &String, time_epoch: u64) -> Result<(), String> {
+        db::touch_heartbeat(device_type, device_id, time_epoch)
+    }
+
+    fn find_stale_devices(&self, before_epoch: u64) -> Result<Vec<StaleDevice>, String> {
+        db::find_stale_devices(before_epoch)
+    }
+
+    fn count_trackers_per_location(&self) -> Result<Vec<(i32, i64)>, String> {
+        db::count_trackers_per_location()
+    }
+
+    fn find_displays_at_location(&self, location: i32) -> Result<Vec<Display>, String> {
+        db::find_displays_at_location(location)
+    }
+
+    fn find_receivers_at_location(&self, location: i32) -> Result<Vec<Receiver>, String> {
+        db::find_receivers_at_location(location)
+    }
+
+    fn insert_occupancy(&self, location: i32, trackers: i64, time_epoch: u64) -> Result<(), String> {
+        db::insert_occupancy(location, trackers, time_epoch)
+    }
+
+    fn find_occupancy_history(&self, location: i32, from_epoch: u64, to_epoch: u64) -> Result<Vec<(u64, i64)>, String> {
+        db::find_occupancy_history(location, from_epoch, to_epoch)
+    }
+
+    fn ping(&self, timeout: Duration) -> Result<(), String> {
+        db::ping(timeout)
+    }
+
+    fn list_orders(&self) -> Result<Vec<OrderSummary>, String> {
+        db::list_orders()
+    }
+
+    fn execute_statements(&self, statements: &Vec<String>) -> Result<(), String> {
+        db::execute_statements(statements)
+    }
+
+    fn migrate(&self, time_epoch: u64) -> Result<Vec<u32>, String> {
+        db::migrate(time_epoch)
+    }
+}
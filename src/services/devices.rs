@@ -1,25 +1,30 @@
 /**
  * Devices business logic
  */
-use futures::join;
-use crate::persistance::db;
+use log::error;
+use crate::persistance::store::Store;
 use crate::model::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::services::occupancy::{self, Occupancy};
 use crate::services::DeviceServiceError;
 use crate::services::DeviceServiceError::{
     NoSuchTracker,
     NoSuchReceiver,
+    NoSuchDisplay,
+    NoSuchLocation,
+    DeviceAlreadyExists,
     Other
 };
 
 /**
- * Registers new tracker location to database
+ * Registers new tracker location to database, and places the tracker at the location in the occupancy
  */
-pub async fn ftr_register_tracker_location(receiver_id: &String, tracker_id: &String) -> Result<(), DeviceServiceError> {
-    match join!(validate_receiver_id(receiver_id), validate_tracker_id(tracker_id)) {
+pub fn ftr_register_tracker_location(store: &dyn Store, occupancy: &Occupancy, receiver_id: &String, tracker_id: &String) -> Result<(), DeviceServiceError> {
+    match (validate_receiver_id(store, receiver_id), validate_tracker_id(store, tracker_id)) {
         (Ok(_), Ok(_))  => 
-            match db::register_tracker_to_receiver(receiver_id, tracker_id) {
-            Ok(_) => Ok(()),
-            Err(e) => {println!("{:?}", e); panic!(e)}
+            match store.register_tracker_to_receiver(receiver_id, tracker_id) {
+            Ok(_) => {enter_occupancy(store, occupancy, receiver_id, tracker_id); Ok(())},
+            Err(e) => {error!("{:?}", e); panic!(e)}
         },
         (Err(_), _) =>  return Err(NoSuchReceiver),
         (_, Err(_)) =>  return Err(NoSuchTracker)
@@ -27,48 +32,188 @@ pub async fn ftr_register_tracker_location(receiver_id: &String, tracker_id: &St
 }
 
 /**
- * Unregisters a tracker from a location, only if it currently registered to this location. 
+ * Places a registered tracker at the location of the receiver in the occupancy, recording the change in the occupancy history.
+ * A tracker whose interests can not be read is placed without interests, the next resync of the occupancy corrects it.
  */
-pub async fn ftr_unregister_tracker_location(receiver_id: &String, tracker_id: &String) -> Result<(), DeviceServiceError> {
-    match join!(validate_receiver_id(receiver_id), validate_tracker_id(tracker_id)) {
-        (Ok(_), Ok(_))  => {
-            let tracker_loc = match db::get_tracker_by_id(tracker_id).unwrap().unwrap().location {
-                None => return Ok(()),
-                Some(val) => val
-            };
-            match db::get_receiver_by_id(receiver_id) {
-                Ok(Some(Receiver {id: _, location})) if (location == tracker_loc) => 
-                match db::unregister_tracker(tracker_id) {
-                    Err(e) => {eprintln!("{}", e); Err(Other)},
-                    Ok(v) => Ok(v)
-                },
-                _ => Ok(())
-            }
+fn enter_occupancy(store: &dyn Store, occupancy: &Occupancy, receiver_id: &String, tracker_id: &String) {
+    let location = match store.get_receiver_by_id(receiver_id) {
+        Ok(Some(receiver)) => receiver.location,
+        Ok(None) => return,
+        Err(e) => {error!("{}", e); return}
+    };
+    let interests = match store.get_tracker_interests(tracker_id) {
+        Ok(val) => val,
+        Err(e) => {error!("could not get the interests of tracker {}: {}", tracker_id, e); vec![]}
+    };
+    occupancy::record_changes(store, &occupancy.enter(tracker_id, location, interests));
+}
+
+/**
+ * Records that a receiver lost a tracker. The tracker is unregistered from its location, and leaves the occupancy,
+ * only if it is registered to the location of the receiver and no other receiver at the location still sees it.
+ */
+pub fn ftr_unregister_tracker_location(store: &dyn Store, occupancy: &Occupancy, receiver_id: &String, tracker_id: &String) -> Result<(), DeviceServiceError> {
+    match (validate_receiver_id(store, receiver_id), validate_tracker_id(store, tracker_id)) {
+        (Ok(_), Ok(_))  => match store.unregister_tracker_from_receiver(receiver_id, tracker_id) {
+            Ok(true) => {occupancy::record_changes(store, &occupancy.leave(tracker_id)); Ok(())},
+            Ok(false) => Ok(()),
+            Err(e) => {error!("{}", e); Err(Other)}
         },
         (Err(_), _) => Err(NoSuchReceiver),
         (_, Err(_)) => Err(NoSuchTracker)
     }
 }
 
+/**
+ * Stores a heartbeat with the reported status for a receiver or display, granted it exists.
+ */
+pub fn register_heartbeat(store: &dyn Store, device_type: DeviceType, device_id: &String, status: DeviceStatus) -> Result<(), DeviceServiceError> {
+    match device_type {
+        DeviceType::Receiver => match store.get_receiver_by_id(device_id) {
+            Ok(Some(_)) => (),
+            Ok(None) => return Err(NoSuchReceiver),
+            Err(e) => {error!("{}", e); return Err(Other)}
+        },
+        DeviceType::Display => match device_id.parse::<i32>().map(|id| store.get_display_by_id(id)) {
+            Ok(Ok(Some(_))) => (),
+            Ok(Ok(None)) | Err(_) => return Err(NoSuchDisplay),
+            Ok(Err(e)) => {error!("{}", e); return Err(Other)}
+        }
+    };
+    let heartbeat = Heartbeat {device_type, device: device_id.clone(), time_epoch: now_epoch(), status};
+    match store.upsert_heartbeat(&heartbeat) {
+        Ok(_) => Ok(()),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+/**
+ * Registers an implicit heartbeat for a display known to exist, keeping its last reported status.
+ */
+pub fn register_display_activity(store: &dyn Store, display_id: i32) -> Result<(), DeviceServiceError> {
+    match store.touch_heartbeat(DeviceType::Display, &display_id.to_string(), now_epoch()) {
+        Ok(_) => Ok(()),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+/**
+ * Returns all receivers and displays that have not sent a heartbeat in the last `threshold_sec` seconds.
+ */
+pub fn find_stale_devices(store: &dyn Store, threshold_sec: u64) -> Result<Vec<StaleDevice>, DeviceServiceError> {
+    match store.find_stale_devices(now_epoch().saturating_sub(threshold_sec)) {
+        Ok(devices) => Ok(devices),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+/**
+ * Returns the number of registered trackers per location, as (location, count)
+ */
+pub fn count_active_trackers(store: &dyn Store) -> Result<Vec<(i32, i64)>, DeviceServiceError> {
+    match store.count_trackers_per_location() {
+        Ok(counts) => Ok(counts),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+/**
+ * Unregisters the trackers registered at a location more than `max_age_sec` seconds ago,
+ * or before their registration time was recorded. Returns the number of trackers unregistered.
+ * The locations the trackers leave are recorded in the occupancy history, the occupancy picks the change up on its next resync.
+ */
+pub fn expire_trackers(store: &dyn Store, max_age_sec: u64) -> Result<u64, DeviceServiceError> {
+    match occupancy::record_store_change(store, || store.expire_trackers(now_epoch().saturating_sub(max_age_sec))) {
+        Ok(count) => Ok(count),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+/**
+ * Creates a tracker, registered at the location if given. A random id is generated if none is given.
+ */
+pub fn create_tracker(store: &dyn Store, id: Option<String>, location: Option<i32>) -> Result<Tracker, DeviceServiceError> {
+    if let Some(location) = location {
+        validate_location(store, location)?;
+    }
+    let tracker = Tracker {id: id.unwrap_or_else(random_device_id), location};
+    match store.get_tracker_by_id(&tracker.id) {
+        Ok(None) => (),
+        Ok(Some(_)) => return Err(DeviceAlreadyExists),
+        Err(e) => {error!("{}", e); return Err(Other)}
+    };
+    match store.insert_tracker(&tracker) {
+        Ok(_) => Ok(tracker),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+/**
+ * Creates a receiver at a location. A random id is generated if none is given.
+ */
+pub fn create_receiver(store: &dyn Store, id: Option<String>, location: i32) -> Result<Receiver, DeviceServiceError> {
+    validate_location(store, location)?;
+    let receiver = Receiver {id: id.unwrap_or_else(random_device_id), location};
+    match store.get_receiver_by_id(&receiver.id) {
+        Ok(None) => (),
+        Ok(Some(_)) => return Err(DeviceAlreadyExists),
+        Err(e) => {error!("{}", e); return Err(Other)}
+    };
+    match store.insert_receiver(&receiver) {
+        Ok(_) => Ok(receiver),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+/**
+ * Creates a display at a location, displays are numbered by the database.
+ */
+pub fn create_display(store: &dyn Store, location: i32) -> Result<Display, DeviceServiceError> {
+    validate_location(store, location)?;
+    match store.insert_display(location) {
+        Ok(id) => Ok(Display {id, location}),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+fn validate_location(store: &dyn Store, location: i32) -> Result<(), DeviceServiceError> {
+    match store.location_exists(location) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(NoSuchLocation),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+fn random_device_id() -> String {
+    rand::random::<[u8; 8]>().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn now_epoch() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(e) => panic!("ERROR GETTING SYSTEM TIME!, ERROR\n{}", e),
+    }
+}
+
 /**
  * Validates a receiver by id. Ok(()) if exists, Err() if not  
  */
-pub async fn validate_receiver_id(station_id: &String) -> Result<(), &'static str>{
-    match db::get_receiver_by_id(station_id) {
+pub fn validate_receiver_id(store: &dyn Store, station_id: &String) -> Result<(), &'static str>{
+    match store.get_receiver_by_id(station_id) {
         Ok(Some(_)) => Ok(()),
         Ok(None) => Err("No such tracker exists"),
-        Err(e) => {println!("{}",e); Err("Unknown Error when accessing database")}
+        Err(e) => {error!("{}",e); Err("Unknown Error when accessing database")}
     }
 }
 
 /**
  * Validates a tracker by id. Ok(()) if exists, Err() if not  
  */
-pub async fn validate_tracker_id(tracker_id: &String) -> Result<(), &'static str>{
-    match db::get_tracker_by_id(tracker_id) {
+pub fn validate_tracker_id(store: &dyn Store, tracker_id: &String) -> Result<(), &'static str>{
+    match store.get_tracker_by_id(tracker_id) {
         Ok(Some(_)) => Ok(()),
         Ok(None) => Err("No such tracker exists"),
-        Err(e) => {println!("{}",e); Err("Unknown Error when accessing database")}
+        Err(e) => {error!("{}",e); Err("Unknown Error when accessing database")}
     }
 }
 
@@ -81,15 +226,16 @@ pub async fn validate_tracker_id(tracker_id: &String) -> Result<(), &'static str
  **************/
 #[cfg(test)]
 mod tests {
-    use futures::executor::block_on;
     use mocktopus::mocking::*;
     use super::*;
+    use crate::persistance::db;
+    use crate::persistance::store::MysqlStore;
 
     #[test]
     fn validate_nonexistant_tracker_unittest() {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
         db::get_tracker_by_id.mock_safe(|_| MockResult::Return(Ok(None)));
-        assert!(match block_on(validate_tracker_id(&"id".to_owned())) {
+        assert!(match validate_tracker_id(&MysqlStore, &"id".to_owned()) {
             Err(_) => true,
             _ => false
         }, "Wrong value returned when validating nonextistant tracker"); 
@@ -99,7 +245,7 @@ mod tests {
     fn validate_tracker_unittest() {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
         db::get_tracker_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Tracker{id: String::from("tracker_id"), location: None}))));
-        assert!(match block_on(validate_tracker_id(&"id".to_owned())) {
+        assert!(match validate_tracker_id(&MysqlStore, &"id".to_owned()) {
             Ok(()) => true,
             _ => false
         }, "Wrong value returned when validating tracker"); 
@@ -109,7 +255,7 @@ mod tests {
     fn validate_nonexistant_receiver_unittest() {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
         db::get_receiver_by_id.mock_safe(|_| MockResult::Return(Ok(None)));
-        assert!(match block_on(validate_receiver_id(&"id".to_owned())) {
+        assert!(match validate_receiver_id(&MysqlStore, &"id".to_owned()) {
             Err(_) => true,
             _ => false
         }, "Wrong value returned when validating nonextistant tracker"); 
@@ -119,7 +265,7 @@ mod tests {
     fn validate_tracker_receiver_unittest() {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
         db::get_receiver_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Receiver {id: String::from("receiver_1"), location: 1}))));
-        assert!(match block_on(validate_receiver_id(&"id".to_owned())) {
+        assert!(match validate_receiver_id(&MysqlStore, &"id".to_owned()) {
             Ok(()) => true,
             _ => false
         }, "Wrong value returned when validating tracker"); 
@@ -130,7 +276,7 @@ mod tests {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
         db::get_receiver_by_id.mock_safe(|_| MockResult::Return(Err(String::from("ERROR"))));
         db::get_tracker_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Tracker {id: String::from("tracker_id"), location: None}))));
-        assert!(match block_on(ftr_register_tracker_location(&String::from("tr"),&String::from("rec"))) {
+        assert!(match ftr_register_tracker_location(&MysqlStore, &Occupancy::default(), &String::from("tr"),&String::from("rec")) {
             Err(NoSuchReceiver) => true,
             _ => false
         })
@@ -141,7 +287,7 @@ mod tests {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
         db::get_receiver_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Receiver{id: String::from("receiver_id"), location: 1}))));
         db::get_tracker_by_id.mock_safe(|_| MockResult::Return(Err(String::from("no such"))));
-        assert!(match block_on(ftr_register_tracker_location(&String::from("tr"),&String::from("rec"))) {
+        assert!(match ftr_register_tracker_location(&MysqlStore, &Occupancy::default(), &String::from("tr"),&String::from("rec")) {
             Err(NoSuchTracker) => true,
             _ => false
         })
@@ -153,10 +299,17 @@ mod tests {
         db::get_receiver_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Receiver{id: String::from("receiver_id"), location: 1}))));
         db::get_tracker_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Tracker {id: String::from("tracker_id"), location: None}))));
         db::register_tracker_to_receiver.mock_safe(|_,_| MockResult::Return(Ok(())));
-        assert!(match block_on(ftr_register_tracker_location(&String::from("tr"),&String::from("rec"))) {
+        db::get_tracker_interests.mock_safe(|_| MockResult::Return(Ok(vec![(1, 2.0)])));
+        db::insert_occupancy.mock_safe(|location, trackers, _| {
+            assert_eq!((location, trackers), (1, 1), "the tracker entering should be recorded in the occupancy history");
+            MockResult::Return(Ok(()))
+        });
+        let occupancy = Occupancy::default();
+        assert!(match ftr_register_tracker_location(&MysqlStore, &occupancy, &String::from("tr"),&String::from("rec")) {
             Ok(()) => true,
             _ => false
-        })
+        });
+        assert_eq!(occupancy.interests_at(1), Some(vec![(1, 2.0)]), "the registered tracker should be present at the receiver's location")
     }
     
     #[test]
@@ -164,11 +317,15 @@ mod tests {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
         db::get_receiver_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Receiver{id: String::from("receiver_id"), location: 1}))));
         db::get_tracker_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Tracker {id: String::from("tracker_id"), location: Some(2)}))));
-        db::register_tracker_to_receiver.mock_safe(|_,_| MockResult::Return(Ok(())));
-        assert!(match block_on(ftr_unregister_tracker_location(&String::from("tr"),&String::from("rec"))) {
+        db::unregister_tracker_from_receiver.mock_safe(|_,_| MockResult::Return(Ok(false)));
+        db::insert_occupancy.mock_safe(|_, _, _| panic!("a tracker that was not unregistered should not leave the occupancy"));
+        let occupancy = Occupancy::default();
+        occupancy.enter(&String::from("rec"), 2, vec![(1, 2.0)]);
+        assert!(match ftr_unregister_tracker_location(&MysqlStore, &occupancy, &String::from("tr"),&String::from("rec")) {
             Ok(()) => true,
             _ => false
-        })
+        });
+        assert_eq!(occupancy.interests_at(2), Some(vec![(1, 2.0)]), "the tracker should still be present at its location")
     }
     
     #[test]
@@ -176,12 +333,21 @@ mod tests {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
         db::get_receiver_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Receiver{id: String::from("receiver_id"), location: 1}))));
         db::get_tracker_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Tracker {id: String::from("tracker_id"), location: Some(1)}))));
-        db::unregister_tracker.mock_safe(|_| MockResult::Return(Ok(())));
-        db::register_tracker_to_receiver.mock_safe(|_,_| MockResult::Return(Ok(())));
-        assert!(match block_on(ftr_unregister_tracker_location(&String::from("tr"),&String::from("rec"))) {
+        db::unregister_tracker_from_receiver.mock_safe(|receiver, tracker| {
+            assert_eq!((receiver.as_str(), tracker.as_str()), ("tr", "rec"));
+            MockResult::Return(Ok(true))
+        });
+        db::insert_occupancy.mock_safe(|location, trackers, _| {
+            assert_eq!((location, trackers), (1, 0), "the tracker leaving should be recorded in the occupancy history");
+            MockResult::Return(Ok(()))
+        });
+        let occupancy = Occupancy::default();
+        occupancy.enter(&String::from("rec"), 1, vec![(1, 2.0)]);
+        assert!(match ftr_unregister_tracker_location(&MysqlStore, &occupancy, &String::from("tr"),&String::from("rec")) {
             Ok(()) => true,
             _ => false
-        })
+        });
+        assert!(occupancy.interests_at(1).is_none(), "the unregistered tracker should have left the location")
     }
     
     #[test]
@@ -189,9 +355,8 @@ mod tests {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
         db::get_receiver_by_id.mock_safe(|_| MockResult::Return(Ok(None)));
         db::get_tracker_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Tracker {id: String::from("tracker_id"), location: Some(1)}))));
-        db::unregister_tracker.mock_safe(|_| MockResult::Return(Ok(())));
-        db::register_tracker_to_receiver.mock_safe(|_,_| MockResult::Return(Ok(())));
-        assert!(match block_on(ftr_unregister_tracker_location(&String::from("tr"),&String::from("rec"))) {
+        db::unregister_tracker_from_receiver.mock_safe(|_,_| panic!("a nonexistent device should not be unregistered"));
+        assert!(match ftr_unregister_tracker_location(&MysqlStore, &Occupancy::default(), &String::from("tr"),&String::from("rec")) {
             Err(_) => true,
             _ => false
         })
@@ -202,11 +367,92 @@ mod tests {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
         db::get_tracker_by_id.mock_safe(|_| MockResult::Return(Ok(None)));
         db::get_receiver_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Receiver{id: String::from("receiver_id"), location: 1}))));
-        db::unregister_tracker.mock_safe(|_| MockResult::Return(Ok(())));
-        db::register_tracker_to_receiver.mock_safe(|_,_| MockResult::Return(Ok(())));
-        assert!(match block_on(ftr_unregister_tracker_location(&String::from("tr"),&String::from("rec"))) {
+        db::unregister_tracker_from_receiver.mock_safe(|_,_| panic!("a nonexistent device should not be unregistered"));
+        assert!(match ftr_unregister_tracker_location(&MysqlStore, &Occupancy::default(), &String::from("tr"),&String::from("rec")) {
             Err(_) => true,
             _ => false
         })
     }
+    
+    #[test]
+    fn register_heartbeat_for_nonexistent_display_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::get_display_by_id.mock_safe(|_| MockResult::Return(Ok(None)));
+        assert!(match register_heartbeat(&MysqlStore, DeviceType::Display, &String::from("1"), DeviceStatus::default()) {
+            Err(NoSuchDisplay) => true,
+            _ => false
+        });
+        assert!(match register_heartbeat(&MysqlStore, DeviceType::Display, &String::from("not_a_display_id"), DeviceStatus::default()) {
+            Err(NoSuchDisplay) => true,
+            _ => false
+        })
+    }
+
+    #[test]
+    fn register_heartbeat_for_receiver_success_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::get_receiver_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Receiver{id: String::from("receiver_id"), location: 1}))));
+        db::upsert_heartbeat.mock_safe(|heartbeat| {
+            assert_eq!(heartbeat.device, "receiver_id");
+            assert_eq!(heartbeat.status.firmware, Some(String::from("2.0.1")));
+            MockResult::Return(Ok(()))
+        });
+        let status = DeviceStatus {firmware: Some(String::from("2.0.1")), ..Default::default()};
+        assert!(match register_heartbeat(&MysqlStore, DeviceType::Receiver, &String::from("receiver_id"), status) {
+            Ok(()) => true,
+            _ => false
+        })
+    }
+
+    #[test]
+    fn create_receiver_at_nonexistent_location_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::location_exists.mock_safe(|_| MockResult::Return(Ok(false)));
+        assert!(match create_receiver(&MysqlStore, Some(String::from("receiver_id")), 5) {
+            Err(NoSuchLocation) => true,
+            _ => false
+        })
+    }
+
+    #[test]
+    fn create_existing_tracker_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::get_tracker_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Tracker {id: String::from("tracker_id"), location: None}))));
+        db::insert_tracker.mock_safe(|_| panic!("an existing tracker should not be inserted"));
+        assert!(match create_tracker(&MysqlStore, Some(String::from("tracker_id")), None) {
+            Err(DeviceAlreadyExists) => true,
+            _ => false
+        })
+    }
+
+    #[test]
+    fn create_tracker_with_generated_id_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::location_exists.mock_safe(|_| MockResult::Return(Ok(true)));
+        db::get_tracker_by_id.mock_safe(|_| MockResult::Return(Ok(None)));
+        db::insert_tracker.mock_safe(|tracker| {
+            assert_eq!(tracker.location, Some(2));
+            MockResult::Return(Ok(()))
+        });
+        let tracker = create_tracker(&MysqlStore, None, Some(2)).unwrap();
+        assert_eq!(tracker.id.len(), 16);
+        assert_ne!(tracker.id, create_tracker(&MysqlStore, None, Some(2)).unwrap().id, "generated ids should differ");
+    }
+
+    #[test]
+    fn expire_trackers_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::expire_trackers.mock_safe(|before_epoch| {
+            assert!(before_epoch <= now_epoch() - 60 && before_epoch > now_epoch() - 120);
+            MockResult::Return(Ok(3))
+        });
+        let expired = PresentTracker {id: "tracker1".to_owned(), location: 1, interests: vec![]};
+        let present = std::sync::Mutex::new(vec![vec![expired], vec![]].into_iter());
+        db::find_present_trackers.mock_safe(move || MockResult::Return(Ok(present.lock().unwrap().next().unwrap())));
+        db::insert_occupancy.mock_safe(|location, trackers, _| {
+            assert_eq!((location, trackers), (1, 0), "the location the tracker left should be recorded");
+            MockResult::Return(Ok(()))
+        });
+        assert_eq!(expire_trackers(&MysqlStore, 60).unwrap(), 3);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,188 @@
+/**
+ * Credit ledger of orders
+ */
+#[cfg(test)]
+use mocktopus::macros::*;
+
+use log::{error, info};
+use crate::persistance::store::Store;
+use crate::metrics;
+use crate::model::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::services::LedgerServiceError;
+use crate::services::LedgerServiceError::{NoSuchOrder, NoSuchPlay, NoSuchDisplay, PlayAlreadyVoided, Other};
+
+/**
+ * Returns the statement of an order: its balance and every credit movement recorded in its ledger.
+ */
+#[cfg_attr(test, mockable)]
+pub fn get_order_statement(store: &dyn Store, order_id: &String) -> Result<OrderStatement, LedgerServiceError> {
+    let order = match store.get_order_by_id(order_id) {
+        Ok(Some(val)) => val,
+        Ok(None) => return Err(NoSuchOrder),
+        Err(e) => {error!("{}", e); return Err(Other)}
+    };
+    match store.find_ledger_entries(order_id) {
+        Ok(entries) => Ok(OrderStatement {order, entries}),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+/**
+ * Returns every order with the plays it paid for and the credits they cost.
+ */
+#[cfg_attr(test, mockable)]
+pub fn list_orders(store: &dyn Store) -> Result<Vec<OrderSummary>, LedgerServiceError> {
+    match store.list_orders() {
+        Ok(orders) => Ok(orders),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+/**
+ * Voids a play, giving its credits back to its order.
+ * Returns the voided play.
+ */
+#[cfg_attr(test, mockable)]
+pub fn void_play(store: &dyn Store, played_video_id: i32, reason: &Option<String>) -> Result<VoidedPlay, LedgerServiceError> {
+    match store.get_play_voided(played_video_id) {
+        Ok(None) => return Err(NoSuchPlay),
+        Ok(Some(true)) => return Err(PlayAlreadyVoided),
+        Ok(Some(false)) => (),
+        Err(e) => {error!("{}", e); return Err(Other)}
+    };
+    //Voided by another request since it was checked
+    void_plays(store, &PlaySelection::Single(played_video_id), reason)?.pop().ok_or(PlayAlreadyVoided)
+}
+
+/**
+ * Voids every play of a display between two times (inclusive) that is not voided yet,
+ * giving their credits back to their orders.
+ * Returns the voided plays.
+ */
+#[cfg_attr(test, mockable)]
+pub fn void_display_plays(store: &dyn Store, display_id: i32, from_epoch: u64, to_epoch: u64, reason: &Option<String>) -> Result<Vec<VoidedPlay>, LedgerServiceError> {
+    match store.get_display_by_id(display_id) {
+        Ok(None) => return Err(NoSuchDisplay),
+        Ok(Some(_)) => (),
+        Err(e) => {error!("{}", e); return Err(Other)}
+    };
+    void_plays(store, &PlaySelection::Display {display: display_id, from_epoch, to_epoch}, reason)
+}
+
+fn void_plays(store: &dyn Store, selection: &PlaySelection, reason: &Option<String>) -> Result<Vec<VoidedPlay>, LedgerServiceError> {
+    let time_epoch = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(e) => panic!("ERROR GETTING SYSTEM TIME!, ERROR\n{}", e),
+    };
+    match store.void_plays(selection, reason, time_epoch) {
+        Ok(plays) => {
+            for play in plays.iter() {
+                metrics::CREDITS_REFUNDED.with_label_values(&[play.order.as_str()]).inc_by(play.credits as i64);
+            }
+            info!("voided {} plays selected by {:?}, reason: {:?}", plays.len(), selection, reason);
+            Ok(plays)
+        },
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use mocktopus::mocking::*;
+    use super::*;
+    use crate::persistance::db;
+    use crate::persistance::store::MysqlStore;
+
+    fn entry(id: i32, entry_type: LedgerEntryType, amount: i32, balance_after: i32) -> LedgerEntry {
+        LedgerEntry {
+            id,
+            order: "order_1".to_owned(),
+            time_epoch: 1,
+            entry_type,
+            amount,
+            balance_after,
+            played_video: None,
+            rate_card: None,
+            credits_per_second: None,
+            description: None
+        }
+    }
+
+    #[test]
+    fn void_play_only_voids_existing_unvoided_plays_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::void_plays.mock_safe(|_, _, _| panic!("a play that can not be voided should not be voided"));
+        db::get_play_voided.mock_safe(|_| MockResult::Return(Ok(None)));
+        assert!(match void_play(&MysqlStore, 1, &None) {
+            Err(NoSuchPlay) => true,
+            _ => false
+        }, "incorrect error on voiding nonexistent play");
+        db::get_play_voided.mock_safe(|_| MockResult::Return(Ok(Some(true))));
+        assert!(match void_play(&MysqlStore, 1, &None) {
+            Err(PlayAlreadyVoided) => true,
+            _ => false
+        }, "incorrect error on voiding voided play");
+
+        db::get_play_voided.mock_safe(|_| MockResult::Return(Ok(Some(false))));
+        db::void_plays.mock_safe(|selection, reason, time_epoch| {
+            assert_eq!(selection, &PlaySelection::Single(1));
+            assert_eq!(reason, &Some("display malfunction".to_owned()));
+            assert!(time_epoch > 0);
+            MockResult::Return(Ok(vec![VoidedPlay {id: 1, order: "order_1".to_owned(), credits: 12}]))
+        });
+        assert_eq!(void_play(&MysqlStore, 1, &Some("display malfunction".to_owned())).unwrap(), VoidedPlay {id: 1, order: "order_1".to_owned(), credits: 12});
+    }
+
+    #[test]
+    fn void_display_plays_of_nonexistent_display_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::get_display_by_id.mock_safe(|_| MockResult::Return(Ok(None)));
+        db::void_plays.mock_safe(|_, _, _| panic!("plays of a nonexistent display should not be voided"));
+        assert!(match void_display_plays(&MysqlStore, 1, 0, 10, &None) {
+            Err(NoSuchDisplay) => true,
+            _ => false
+        }, "incorrect error on voiding plays of nonexistent display");
+    }
+
+    #[test]
+    fn get_order_statement_of_nonexistent_order_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::get_order_by_id.mock_safe(|_| MockResult::Return(Ok(None)));
+        db::find_ledger_entries.mock_safe(|_| panic!("the ledger of a nonexistent order should not be queried"));
+        assert!(match get_order_statement(&MysqlStore, &"order_1".to_owned()) {
+            Err(NoSuchOrder) => true,
+            _ => false
+        }, "incorrect error on statement of nonexistent order");
+    }
+
+    #[test]
+    fn get_order_statement_reconciles_balance_with_ledger_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::get_order_by_id.mock_safe(|_| MockResult::Return(
+            Ok(Some(Order { id: "order_1".to_owned(), credits: 88, user: "user_1".to_owned() }))
+        ));
+        db::find_ledger_entries.mock_safe(|order_id| {
+            assert_eq!(order_id, &"order_1".to_owned());
+            MockResult::Return(Ok(vec![entry(1, LedgerEntryType::Opening, 100, 100), entry(2, LedgerEntryType::Debit, -12, 88)]))
+        });
+        let statement = get_order_statement(&MysqlStore, &"order_1".to_owned()).unwrap();
+        assert_eq!(statement.ledger_balance(), 88);
+        assert!(statement.is_reconciled(), "an order balance matching its ledger should be reconciled");
+
+        db::get_order_by_id.mock_safe(|_| MockResult::Return(
+            Ok(Some(Order { id: "order_1".to_owned(), credits: 188, user: "user_1".to_owned() }))
+        ));
+        let statement = get_order_statement(&MysqlStore, &"order_1".to_owned()).unwrap();
+        assert!(!statement.is_reconciled(), "a balance changed outside of the ledger should not be reconciled");
+
+        db::find_ledger_entries.mock_safe(|_| MockResult::Return(Ok(vec![])));
+        let statement = get_order_statement(&MysqlStore, &"order_1".to_owned()).unwrap();
+        assert_eq!(statement.ledger_balance(), 188, "an order without a ledger should keep its balance");
+    }
+}
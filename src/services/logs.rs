@@ -0,0 +1,103 @@
+/**
+ * Device log business logic
+ */
+#[cfg(test)]
+use mocktopus::macros::*;
+
+use log::error;
+use crate::persistance::store::Store;
+use crate::model::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::services::LogServiceError;
+use crate::services::LogServiceError::Other;
+
+/**
+ * The maximum number of log entries returned by a single query
+ */
+pub static MAX_LOG_ENTRIES: u32 = 1000;
+
+/**
+ * Persists a batch of log messages reported by devices, timestamped with the current server time.
+ * Returns the number of persisted messages.
+ */
+#[cfg_attr(test, mockable)]
+pub fn store_log_messages(store: &dyn Store, mut entries: Vec<LogEntry>) -> Result<usize, LogServiceError> {
+    let time_epoch = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(e) => panic!("ERROR GETTING SYSTEM TIME!, ERROR\n{}", e),
+    };
+    for entry in entries.iter_mut() {
+        entry.time_epoch = time_epoch;
+    }
+    match store.insert_log_entries(&entries) {
+        Ok(_) => Ok(entries.len()),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+/**
+ * Returns the persisted log entries matching the filter, newest first.
+ */
+#[cfg_attr(test, mockable)]
+pub fn find_log_messages(store: &dyn Store, filter: &LogFilter) -> Result<Vec<LogEntry>, LogServiceError> {
+    match store.find_log_entries(filter, MAX_LOG_ENTRIES) {
+        Ok(entries) => Ok(entries),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use mocktopus::mocking::*;
+    use super::*;
+    use crate::persistance::db;
+    use crate::persistance::store::MysqlStore;
+
+    #[test]
+    fn store_log_messages_persists_timestamped_entries_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::insert_log_entries.mock_safe(|entries| {
+            assert_eq!(entries.len(), 2);
+            assert_eq!(entries[0].device, Some("receiver_1".to_owned()));
+            assert_eq!(entries[0].severity, Severity::Fatal);
+            assert_eq!(entries[1].firmware, Some("1.2.0".to_owned()));
+            assert!(entries.iter().all(|e| e.time_epoch > 0), "log entries should be timestamped");
+            MockResult::Return(Ok(()))
+        });
+        let entries = vec![
+            LogEntry {device: Some("receiver_1".to_owned()), severity: Severity::Fatal, firmware: None, time_epoch: 0, message: "out of memory".to_owned(), fields: None},
+            LogEntry {device: Some("receiver_1".to_owned()), severity: Severity::Debug, firmware: Some("1.2.0".to_owned()), time_epoch: 0, message: "rebooting".to_owned(), fields: None}
+        ];
+        assert!(match store_log_messages(&MysqlStore, entries) {
+            Ok(2) => true,
+            _ => false
+        }, "Wrong value returned when storing log messages");
+    }
+
+    #[test]
+    fn find_log_messages_passes_filter_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::find_log_entries.mock_safe(|filter, limit| {
+            assert_eq!(filter.device, Some("display_1".to_owned()));
+            assert_eq!(limit, MAX_LOG_ENTRIES);
+            MockResult::Return(Ok(vec![LogEntry {
+                device: Some("display_1".to_owned()),
+                severity: Severity::Info,
+                firmware: None,
+                time_epoch: 1,
+                message: "booted".to_owned(),
+                fields: None
+            }]))
+        });
+        let filter = LogFilter {device: Some("display_1".to_owned()), ..Default::default()};
+        assert!(match find_log_messages(&MysqlStore, &filter) {
+            Ok(entries) => entries.len() == 1,
+            _ => false
+        }, "Wrong value returned when finding log messages");
+    }
+}
@@ -6,11 +6,45 @@ pub mod devices;
  * Videos business logic
  */
 pub mod videos;
+/**
+ * Device log business logic
+ */
+pub mod logs;
+/**
+ * Pricing of played videos
+ */
+pub mod pricing;
+/**
+ * Proof-of-play tokens
+ */
+pub mod play_tokens;
+/**
+ * Outbound webhook notifications
+ */
+pub mod webhooks;
+/**
+ * Credit ledger of orders
+ */
+pub mod ledger;
+/**
+ * Live occupancy of the locations
+ */
+pub mod occupancy;
+/**
+ * Budget pacing of orders over their campaigns
+ */
+pub mod pacing;
 
 #[derive(Debug)]
 pub enum DeviceServiceError {
     NoSuchTracker,
     NoSuchReceiver,
+    NoSuchDisplay,
+    NoSuchLocation,
+    /**
+     * A device with the id to create already exists
+     */
+    DeviceAlreadyExists,
     Other
 }
 
@@ -21,5 +55,50 @@ pub enum VideoServiceError {
     NoSuchDisplay,
     NoSuchOrder,
     NoSuchDisplayLocation,
+    /**
+     * The reported play length is not possible for the video, carries the video length in seconds
+     */
+    InvalidPlayLength(i32),
+    /**
+     * The order does not pay for the video
+     */
+    OrderNotForVideo,
+    /**
+     * The campaign linking the order and the video is not running
+     */
+    CampaignNotActive,
+    /**
+     * The play token is missing, forged or was issued for another play
+     */
+    InvalidPlayToken,
+    /**
+     * The play token was issued too long ago
+     */
+    ExpiredPlayToken,
+    /**
+     * The play token has already been used to register a play
+     */
+    RedeemedPlayToken,
+    Other,
+}
+
+#[derive(Debug)]
+pub enum LogServiceError {
+    Other,
+}
+#[derive(Debug)]
+pub enum LedgerServiceError {
+    NoSuchOrder,
+    NoSuchPlay,
+    NoSuchDisplay,
+    /**
+     * The play has already been voided, its credits have already been given back
+     */
+    PlayAlreadyVoided,
     Other,
-}
\ No newline at end of file
+}
+#[derive(Debug)]
+pub enum OccupancyServiceError {
+    NoSuchLocation,
+    Other,
+}
@@ -0,0 +1,440 @@
+/**
+ * Live occupancy of the locations: the trackers present at each location and their aggregated interests.
+ *
+ * Kept up to date by the registrations of trackers, rebuilt from the store on startup and resynced
+ * periodically, so that changes made outside of the server, like expired trackers or edited interests, are picked up.
+ * The changes of the number of trackers at a location are recorded in the occupancy history by the writes making them,
+ * the rebuilds only correct the occupancy.
+ *
+ * The occupancy lives in the process: the server must run as a single instance, a second instance would select videos
+ * from an occupancy that misses the registrations made through the first until its next resync.
+ */
+#[cfg(test)]
+use mocktopus::macros::*;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{error, info};
+use crate::model::{Audience, OccupancyBucket, PresentTracker};
+use crate::persistance::store::{SharedStore, Store};
+use crate::services::OccupancyServiceError;
+use crate::services::OccupancyServiceError::{NoSuchLocation, Other};
+
+pub type SharedOccupancy = Arc<Occupancy>;
+
+/**
+ * The most buckets an occupancy history is split into
+ */
+pub static MAX_HISTORY_BUCKETS: u64 = 1000;
+
+/**
+ * The trackers present at a location with the weights of their interests,
+ * and the interests of all of them summed per interest, highest weight first
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LocationOccupancy {
+    pub trackers: BTreeMap<String, Vec<(i32, f32)>>,
+    pub interests: Vec<(i32, f32)>
+}
+
+#[derive(Default)]
+pub struct Occupancy {
+    state: RwLock<OccupancyState>
+}
+
+#[derive(Default)]
+struct OccupancyState {
+    trackers: HashMap<String, i32>,
+    locations: HashMap<i32, LocationOccupancy>,
+    //Changes made while a rebuild loads the store, replayed on top of what was loaded
+    recording: Option<Vec<Change>>
+}
+
+enum Change {
+    Enter(String, i32, Vec<(i32, f32)>),
+    Leave(String)
+}
+
+impl Occupancy {
+    /**
+     * Places a tracker at a location, moving it from the location it was at if any.
+     * Returns the locations whose number of trackers changed, as (location, trackers)
+     */
+    pub fn enter(&self, tracker_id: &String, location: i32, interests: Vec<(i32, f32)>) -> Vec<(i32, i64)> {
+        let mut state = self.write();
+        if let Some(recording) = state.recording.as_mut() {
+            recording.push(Change::Enter(tracker_id.clone(), location, interests.clone()));
+        }
+        state.enter(tracker_id, location, interests)
+    }
+
+    /**
+     * Removes a tracker from the location it is at, if any.
+     * Returns the locations whose number of trackers changed, as (location, trackers)
+     */
+    pub fn leave(&self, tracker_id: &String) -> Vec<(i32, i64)> {
+        let mut state = self.write();
+        if let Some(recording) = state.recording.as_mut() {
+            recording.push(Change::Leave(tracker_id.clone()));
+        }
+        state.leave(tracker_id).into_iter().collect()
+    }
+
+    /**
+     * The aggregated interests at a location, highest weight first. None if no tracker is present.
+     */
+    pub fn interests_at(&self, location: i32) -> Option<Vec<(i32, f32)>> {
+        self.read().locations.get(&location).map(|l| l.interests.clone())
+    }
+
+    /**
+     * The trackers and the aggregated interests at a location. None if no tracker is present.
+     */
+    pub fn at_location(&self, location: i32) -> Option<LocationOccupancy> {
+        self.read().locations.get(&location).cloned()
+    }
+
+    /**
+     * Replaces the occupancy with the trackers present according to the store.
+     * Trackers entering or leaving while the store is read are applied on top of it.
+     * Returns the locations whose number of trackers changed, as (location, trackers)
+     */
+    pub fn rebuild(&self, store: &dyn Store) -> Result<Vec<(i32, i64)>, String> {
+        self.write().recording = Some(vec![]);
+        let present = match store.find_present_trackers() {
+            Ok(val) => val,
+            Err(e) => {self.write().recording = None; return Err(e)}
+        };
+        let mut state = self.write();
+        let changes = state.recording.take().unwrap_or_default();
+        let counts_before = state.counts();
+        *state = OccupancyState::from_present(present);
+        for change in changes {
+            match change {
+                Change::Enter(tracker, location, interests) => {state.enter(&tracker, location, interests);},
+                Change::Leave(tracker) => {state.leave(&tracker);}
+            }
+        }
+        Ok(changed_counts(&counts_before, &state.counts()))
+    }
+
+    fn read(&self) -> RwLockReadGuard<OccupancyState> {
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<OccupancyState> {
+        self.state.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl OccupancyState {
+    fn from_present(present: Vec<PresentTracker>) -> OccupancyState {
+        let mut state = OccupancyState::default();
+        for tracker in present {
+            state.trackers.insert(tracker.id.clone(), tracker.location);
+            state.locations.entry(tracker.location).or_default().trackers.insert(tracker.id, tracker.interests);
+        }
+        for location in state.locations.values_mut() {
+            location.aggregate();
+        }
+        state
+    }
+
+    fn enter(&mut self, tracker_id: &String, location: i32, interests: Vec<(i32, f32)>) -> Vec<(i32, i64)> {
+        let mut changes = vec![];
+        if self.trackers.get(tracker_id) != Some(&location) {
+            changes.extend(self.leave(tracker_id));
+        }
+        self.trackers.insert(tracker_id.clone(), location);
+        let occupancy = self.locations.entry(location).or_default();
+        if occupancy.trackers.insert(tracker_id.clone(), interests).is_none() {
+            changes.push((location, occupancy.trackers.len() as i64));
+        }
+        occupancy.aggregate();
+        changes
+    }
+
+    fn leave(&mut self, tracker_id: &String) -> Option<(i32, i64)> {
+        let location = self.trackers.remove(tracker_id)?;
+        let remaining = match self.locations.get_mut(&location) {
+            Some(occupancy) => {
+                occupancy.trackers.remove(tracker_id);
+                occupancy.aggregate();
+                occupancy.trackers.len()
+            },
+            None => 0
+        };
+        if remaining == 0 {
+            self.locations.remove(&location);
+        }
+        Some((location, remaining as i64))
+    }
+
+    fn counts(&self) -> HashMap<i32, i64> {
+        self.locations.iter().map(|(location, occupancy)| (*location, occupancy.trackers.len() as i64)).collect()
+    }
+}
+
+impl LocationOccupancy {
+    fn aggregate(&mut self) {
+        let mut interests: Vec<(i32, f32)> = vec![];
+        for (interest, weight) in self.trackers.values().flatten() {
+            match interests.iter_mut().find(|x| x.0 == *interest) {
+                Some(x) => x.1 += weight,
+                None => interests.push((*interest, *weight))
+            }
+        }
+        interests.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then(a.0.cmp(&b.0)));
+        self.interests = interests;
+    }
+}
+
+/**
+ * The locations whose number of trackers differs between the counts, with the number after, as (location, trackers)
+ */
+fn changed_counts(before: &HashMap<i32, i64>, after: &HashMap<i32, i64>) -> Vec<(i32, i64)> {
+    let locations: BTreeSet<&i32> = before.keys().chain(after.keys()).collect();
+    locations.into_iter()
+        .map(|location| (*location, after.get(location).cloned().unwrap_or(0)))
+        .filter(|(location, trackers)| before.get(location).cloned().unwrap_or(0) != *trackers)
+        .collect()
+}
+
+/**
+ * Records the changed numbers of trackers at locations, as (location, trackers), in the occupancy history.
+ * A change that can not be recorded is logged and left out.
+ */
+pub fn record_changes(store: &dyn Store, changes: &Vec<(i32, i64)>) {
+    let now = now_epoch();
+    for (location, trackers) in changes.iter() {
+        if let Err(e) = store.insert_occupancy(*location, *trackers, now) {
+            error!("could not record the occupancy of location {}: {}", location, e);
+        }
+    }
+}
+
+/**
+ * Makes a change to the trackers in the store that does not go through the occupancy, like the expiry of trackers,
+ * and records the numbers of trackers it changed at locations, as counted in the store, in the occupancy history.
+ */
+pub fn record_store_change<T>(store: &dyn Store, change: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    let before = OccupancyState::from_present(store.find_present_trackers()?).counts();
+    let res = change()?;
+    match store.find_present_trackers() {
+        Ok(present) => record_changes(store, &changed_counts(&before, &OccupancyState::from_present(present).counts())),
+        Err(e) => error!("could not record the occupancy after the change: {}", e)
+    }
+    Ok(res)
+}
+
+/**
+ * Returns the trackers, their interests, the displays and the receivers at a location
+ *
+ * # Return values:
+ * [OccupancyServiceError::NoSuchLocation](../enum.OccupancyServiceError.html) if the location does not exist
+ */
+#[cfg_attr(test, mockable)]
+pub fn get_audience(store: &dyn Store, occupancy: &Occupancy, location: i32) -> Result<Audience, OccupancyServiceError> {
+    validate_location(store, location)?;
+    let (displays, receivers) = match (store.find_displays_at_location(location), store.find_receivers_at_location(location)) {
+        (Ok(displays), Ok(receivers)) => (displays, receivers),
+        (Err(e), _) | (_, Err(e)) => {error!("{}", e); return Err(Other)}
+    };
+    let present = occupancy.at_location(location).unwrap_or_default();
+    Ok(Audience {
+        location,
+        trackers: present.trackers.len(),
+        interests: present.interests,
+        displays: displays.iter().map(|d| d.id).collect(),
+        receivers: receivers.into_iter().map(|r| r.id).collect()
+    })
+}
+
+/**
+ * Returns the trackers at a location from `from_epoch` until `to_epoch`, or now if that is earlier,
+ * in buckets of `bucket_sec` seconds, the last bucket cut short at the end.
+ *
+ * # Return values:
+ * [OccupancyServiceError::NoSuchLocation](../enum.OccupancyServiceError.html) if the location does not exist
+ */
+#[cfg_attr(test, mockable)]
+pub fn get_audience_history(store: &dyn Store, location: i32, from_epoch: u64, to_epoch: u64, bucket_sec: u64) -> Result<Vec<OccupancyBucket>, OccupancyServiceError> {
+    validate_location(store, location)?;
+    let to_epoch = to_epoch.min(now_epoch());
+    if from_epoch >= to_epoch {
+        return Ok(vec![]);
+    }
+    match store.find_occupancy_history(location, from_epoch, to_epoch) {
+        Ok(history) => Ok(bucket_history(&history, from_epoch, to_epoch, bucket_sec)),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+/**
+ * Splits the history of (time_epoch, trackers) records, oldest first, into buckets.
+ * There are no trackers before the first record.
+ */
+fn bucket_history(history: &Vec<(u64, i64)>, from_epoch: u64, to_epoch: u64, bucket_sec: u64) -> Vec<OccupancyBucket> {
+    let mut records = history.iter().peekable();
+    let mut trackers = 0;
+    let mut buckets = vec![];
+    let mut start = from_epoch;
+    while start < to_epoch {
+        let end = to_epoch.min(start.saturating_add(bucket_sec));
+        while let Some(&&(_, val)) = records.peek().filter(|r| r.0 <= start) {
+            trackers = val;
+            records.next();
+        }
+        let mut peak = trackers;
+        let mut tracker_seconds = 0;
+        let mut since = start;
+        while let Some(&&(time, val)) = records.peek().filter(|r| r.0 < end) {
+            tracker_seconds += trackers * (time - since) as i64;
+            since = time;
+            trackers = val;
+            peak = peak.max(trackers);
+            records.next();
+        }
+        tracker_seconds += trackers * (end - since) as i64;
+        buckets.push(OccupancyBucket {start_epoch: start, average: tracker_seconds as f64 / (end - start) as f64, peak});
+        start = end;
+    }
+    buckets
+}
+
+fn validate_location(store: &dyn Store, location: i32) -> Result<(), OccupancyServiceError> {
+    match store.location_exists(location) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(NoSuchLocation),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+fn now_epoch() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(e) => panic!("ERROR GETTING SYSTEM TIME!, ERROR\n{}", e),
+    }
+}
+
+/**
+ * Starts a thread rebuilding the occupancy from the store every `interval_sec` seconds
+ */
+pub fn start_resync(occupancy: SharedOccupancy, store: SharedStore, interval_sec: u64) {
+    thread::Builder::new().name("occupancy".to_owned()).spawn(move || {
+        loop {
+            thread::sleep(Duration::from_secs(interval_sec));
+            //A database error panics, the resync keeps running and retries on the next round
+            let res = panic::catch_unwind(AssertUnwindSafe(|| occupancy.rebuild(&*store)));
+            match res {
+                Ok(Ok(changes)) if !changes.is_empty() => info!("the resync changed the number of trackers at {} locations", changes.len()),
+                Ok(Ok(_)) => (),
+                Ok(Err(e)) => error!("could not resync the occupancy: {}", e),
+                Err(_) => error!("could not resync the occupancy")
+            }
+        }
+    }).expect("could not start the occupancy resync");
+    info!("resyncing the occupancy every {} seconds", interval_sec);
+}
+
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Receiver, Tracker};
+    use crate::persistance::memory::MemoryStore;
+
+    fn tracker(id: &str) -> String {
+        id.to_owned()
+    }
+
+    #[test]
+    fn interests_are_summed_per_location_unittest() {
+        let occupancy = Occupancy::default();
+        assert!(occupancy.interests_at(1).is_none());
+        occupancy.enter(&tracker("tracker1"), 1, vec![(1, 1.0), (2, 3.0)]);
+        occupancy.enter(&tracker("tracker2"), 1, vec![(1, 4.0)]);
+        occupancy.enter(&tracker("tracker3"), 2, vec![(3, 1.0)]);
+        assert_eq!(occupancy.interests_at(1), Some(vec![(1, 5.0), (2, 3.0)]));
+        assert_eq!(occupancy.interests_at(2), Some(vec![(3, 1.0)]));
+        occupancy.leave(&tracker("tracker2"));
+        assert_eq!(occupancy.interests_at(1), Some(vec![(2, 3.0), (1, 1.0)]));
+        occupancy.leave(&tracker("tracker3"));
+        assert!(occupancy.interests_at(2).is_none(), "a location without trackers should have no interests");
+    }
+
+    #[test]
+    fn tracker_entering_moves_from_its_location_unittest() {
+        let occupancy = Occupancy::default();
+        assert_eq!(occupancy.enter(&tracker("tracker1"), 1, vec![(1, 1.0)]), vec![(1, 1)]);
+        assert_eq!(occupancy.enter(&tracker("tracker1"), 1, vec![(1, 2.0)]), vec![], "entering again should not change the number of trackers");
+        assert_eq!(occupancy.enter(&tracker("tracker1"), 2, vec![(1, 1.0)]), vec![(1, 0), (2, 1)]);
+        assert!(occupancy.at_location(1).is_none());
+        assert_eq!(occupancy.at_location(2).unwrap().trackers.keys().collect::<Vec<&String>>(), vec!["tracker1"]);
+        assert_eq!(occupancy.leave(&tracker("tracker1")), vec![(2, 0)]);
+        assert_eq!(occupancy.leave(&tracker("tracker1")), vec![]);
+        assert!(occupancy.at_location(2).is_none());
+    }
+
+    #[test]
+    fn rebuild_loads_the_present_trackers_unittest() {
+        let store = MemoryStore::new();
+        let location = store.add_location("location1");
+        let interest = store.add_interest("sport");
+        store.insert_receiver(&Receiver {id: "receiver1".to_owned(), location}).unwrap();
+        store.insert_tracker(&Tracker {id: "tracker1".to_owned(), location: None}).unwrap();
+        store.insert_tracker(&Tracker {id: "tracker2".to_owned(), location: None}).unwrap();
+        store.set_tracker_interest("tracker1", interest, 2.0);
+        store.register_tracker_to_receiver(&"receiver1".to_owned(), &"tracker1".to_owned()).unwrap();
+        store.register_tracker_to_receiver(&"receiver1".to_owned(), &"tracker2".to_owned()).unwrap();
+
+        let occupancy = Occupancy::default();
+        occupancy.enter(&tracker("gone"), location, vec![(interest, 10.0)]);
+        assert_eq!(occupancy.rebuild(&store).unwrap(), vec![(location, 2)], "the changed number of trackers should be returned");
+        let at_location = occupancy.at_location(location).unwrap();
+        assert_eq!(at_location.trackers.keys().collect::<Vec<&String>>(), vec!["tracker1", "tracker2"]);
+        assert_eq!(at_location.interests, vec![(interest, 2.0)], "a tracker without interests should still be present");
+    }
+
+    #[test]
+    fn history_is_averaged_per_bucket_unittest() {
+        //2 trackers since before the start, 4 from 130, none from 150
+        let history = vec![(50, 2), (130, 4), (150, 0)];
+        assert_eq!(bucket_history(&history, 100, 200, 50), vec![
+            OccupancyBucket {start_epoch: 100, average: 2.8, peak: 4},
+            OccupancyBucket {start_epoch: 150, average: 0.0, peak: 0}
+        ]);
+        assert_eq!(bucket_history(&history, 0, 60, 40), vec![
+            OccupancyBucket {start_epoch: 0, average: 0.0, peak: 0},
+            OccupancyBucket {start_epoch: 40, average: 1.0, peak: 2}
+        ], "there should be no trackers before the first record, and the last bucket should be cut short");
+    }
+
+    #[test]
+    fn audience_lists_the_devices_and_trackers_of_the_location_unittest() {
+        let store = MemoryStore::new();
+        let location = store.add_location("location1");
+        let other = store.add_location("location2");
+        let display = store.insert_display(location).unwrap();
+        store.insert_display(other).unwrap();
+        store.insert_receiver(&Receiver {id: "receiver1".to_owned(), location}).unwrap();
+        let occupancy = Occupancy::default();
+        occupancy.enter(&tracker("tracker1"), location, vec![(1, 2.0)]);
+
+        let audience = get_audience(&store, &occupancy, location).unwrap();
+        assert_eq!(audience, Audience {location, trackers: 1, interests: vec![(1, 2.0)], displays: vec![display], receivers: vec!["receiver1".to_owned()]});
+        assert_eq!(get_audience(&store, &occupancy, other).unwrap().trackers, 0);
+        assert!(match get_audience(&store, &occupancy, 100) {
+            Err(NoSuchLocation) => true,
+            _ => false
+        });
+    }
+}
@@ -0,0 +1,79 @@
+use crate::config::PacingCurve;
+use crate::model::OrderBudget;
+
+/**
+ * The share of the campaign that has passed at `now`, from 0 before the start to 1 after the end
+ */
+fn elapsed_share(budget: &OrderBudget, now: u64) -> f64 {
+    if now >= budget.end_epoch || budget.end_epoch <= budget.start_epoch {
+        return 1.0;
+    }
+    if now <= budget.start_epoch {
+        return 0.0;
+    }
+    (now - budget.start_epoch) as f64 / (budget.end_epoch - budget.start_epoch) as f64
+}
+
+/**
+ * The number of credits the order should have spent at `now` following the curve
+ */
+pub fn target_spend(budget: &OrderBudget, now: u64, curve: PacingCurve) -> f64 {
+    let elapsed = elapsed_share(budget, now);
+    let share = match curve {
+        PacingCurve::Off => 1.0,
+        PacingCurve::Even => elapsed,
+        PacingCurve::FrontLoaded => elapsed.sqrt(),
+        PacingCurve::BackLoaded => elapsed * elapsed
+    };
+    budget.total_credits() as f64 * share
+}
+
+/**
+ * True if the order has spent more than its pacing target at `now`.
+ * An order may run `tolerance` (share of the total budget, at least one credit) ahead of the target,
+ * so that it can be shown at the start of its campaign.
+ */
+pub fn is_ahead_of_target(budget: &OrderBudget, now: u64, curve: PacingCurve, tolerance: f64) -> bool {
+    let allowance = f64::max(budget.total_credits() as f64 * tolerance, 1.0);
+    budget.spent_credits as f64 >= target_spend(budget, now, curve) + allowance
+}
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(spent_credits: i32) -> OrderBudget {
+        OrderBudget {remaining_credits: 1000 - spent_credits, spent_credits, start_epoch: 1000, end_epoch: 2000}
+    }
+
+    #[test]
+    fn target_spend_follows_curve_unittest() {
+        assert_eq!(target_spend(&budget(0), 500, PacingCurve::Even), 0.0);
+        assert_eq!(target_spend(&budget(0), 1250, PacingCurve::Even), 250.0);
+        assert_eq!(target_spend(&budget(0), 1250, PacingCurve::FrontLoaded), 500.0);
+        assert_eq!(target_spend(&budget(0), 1500, PacingCurve::BackLoaded), 250.0);
+        assert_eq!(target_spend(&budget(0), 1250, PacingCurve::Off), 1000.0);
+        assert_eq!(target_spend(&budget(0), 3000, PacingCurve::Even), 1000.0);
+    }
+
+    #[test]
+    fn orders_ahead_of_target_are_paced_unittest() {
+        assert!(!is_ahead_of_target(&budget(0), 1000, PacingCurve::Even, 0.05), "an unspent order should run at the start");
+        assert!(!is_ahead_of_target(&budget(49), 1000, PacingCurve::Even, 0.05));
+        assert!(is_ahead_of_target(&budget(50), 1000, PacingCurve::Even, 0.05));
+        assert!(!is_ahead_of_target(&budget(540), 1500, PacingCurve::Even, 0.05));
+        assert!(is_ahead_of_target(&budget(550), 1500, PacingCurve::Even, 0.05));
+        assert!(!is_ahead_of_target(&budget(999), 1000, PacingCurve::Off, 0.05));
+    }
+
+    #[test]
+    fn small_budgets_may_always_spend_one_credit_unittest() {
+        let small = OrderBudget {remaining_credits: 10, spent_credits: 0, start_epoch: 1000, end_epoch: 2000};
+        assert!(!is_ahead_of_target(&small, 1000, PacingCurve::Even, 0.0));
+    }
+}
@@ -0,0 +1,142 @@
+/**
+ * Proof-of-play tokens
+ */
+#[cfg(test)]
+use mocktopus::macros::*;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+use crate::config;
+use crate::model::PlayToken;
+use crate::services::VideoServiceError;
+use crate::services::VideoServiceError::{ExpiredPlayToken, InvalidPlayToken};
+
+type HmacSha256 = Hmac<Sha256>;
+
+static TOKEN_VERSION: &'static str = "v1";
+
+/**
+ * Issues a token for a video handed out to a display, to be sent back when the play is registered.
+ *
+ * The token is `<payload>.<signature>`, both base64url encoded, where the payload binds
+ * display, video, order and issue time together with a random nonce, and the signature is a HMAC-SHA256 of the payload.
+ */
+#[cfg_attr(test, mockable)]
+pub fn issue_play_token(display_id: i32, video_id: i32, order_id: &String) -> String {
+    let issued_at = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(e) => panic!("ERROR GETTING SYSTEM TIME!, ERROR\n{}", e),
+    };
+    issue_play_token_at(&config::get().play_token_secret, display_id, video_id, order_id, issued_at)
+}
+
+fn issue_play_token_at(secret: &String, display_id: i32, video_id: i32, order_id: &String, issued_at: u64) -> String {
+    //The order goes last, it is the only part that may contain the separator
+    let payload = format!("{}:{}:{}:{}:{:016x}:{}", TOKEN_VERSION, display_id, video_id, issued_at, rand::thread_rng().gen::<u64>(), order_id);
+    format!("{}.{}", base64::encode_config(&payload, base64::URL_SAFE_NO_PAD), base64::encode_config(&sign(secret, &payload), base64::URL_SAFE_NO_PAD))
+}
+
+/**
+ * Verifies a play token sent back for a play of a video on a display for an order.
+ *
+ * Returns the token claims if the signature is valid, the token was issued for this display, video and order,
+ * and it was issued at most `valid_for_sec` plus the configured play_token_ttl_sec seconds before `now`.
+ *
+ * # Arguments
+ * `valid_for_sec` - the length of the video, the token stays valid while the video plays
+ */
+pub fn verify_play_token(token: &String, display_id: i32, video_id: i32, order_id: &String, valid_for_sec: u64, now: u64) -> Result<PlayToken, VideoServiceError> {
+    let claims = decode_play_token(&config::get().play_token_secret, token)?;
+    if claims.display != display_id || claims.video != video_id || &claims.order != order_id {
+        return Err(InvalidPlayToken);
+    }
+    if now > claims.issued_at + valid_for_sec + config::get().play_token_ttl_sec {
+        return Err(ExpiredPlayToken);
+    }
+    Ok(claims)
+}
+
+fn decode_play_token(secret: &String, token: &String) -> Result<PlayToken, VideoServiceError> {
+    let mut parts = token.splitn(2, '.');
+    let (payload, signature) = match (parts.next(), parts.next()) {
+        (Some(payload), Some(signature)) => (payload, signature),
+        _ => return Err(InvalidPlayToken)
+    };
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()
+        .and_then(|p| String::from_utf8(p).ok())
+        .ok_or(InvalidPlayToken)?;
+    let signature_bytes = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).map_err(|_| InvalidPlayToken)?;
+    let mut mac = HmacSha256::new_varkey(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.input(payload.as_bytes());
+    mac.verify(&signature_bytes).map_err(|_| InvalidPlayToken)?;
+
+    let fields: Vec<&str> = payload.splitn(6, ':').collect();
+    match &fields[..] {
+        [version, display, video, issued_at, _nonce, order] if *version == TOKEN_VERSION => {
+            match (display.parse::<i32>(), video.parse::<i32>(), issued_at.parse::<u64>()) {
+                (Ok(display), Ok(video), Ok(issued_at)) => Ok(PlayToken {display, video, order: order.to_string(), issued_at, signature: signature.to_owned()}),
+                _ => Err(InvalidPlayToken)
+            }
+        },
+        _ => Err(InvalidPlayToken)
+    }
+}
+
+fn sign(secret: &String, payload: &String) -> Vec<u8> {
+    let mut mac = HmacSha256::new_varkey(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.input(payload.as_bytes());
+    mac.result().code().to_vec()
+}
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn issued_token_verifies_unittest() {
+        let order = "order:1".to_owned();
+        let token = issue_play_token(1, 2, &order);
+        let claims = decode_play_token(&config::get().play_token_secret, &token).unwrap();
+        assert_eq!((claims.display, claims.video, claims.order), (1, 2, order.clone()));
+        assert!(verify_play_token(&token, 1, 2, &order, 30, claims.issued_at + 30).is_ok());
+        assert_ne!(token, issue_play_token(1, 2, &order), "two tokens for the same video should differ");
+    }
+
+    #[test]
+    fn token_for_other_play_is_rejected_unittest() {
+        let order = "order_1".to_owned();
+        let token = issue_play_token(1, 2, &order);
+        assert!(match verify_play_token(&token, 3, 2, &order, 30, 0) { Err(InvalidPlayToken) => true, _ => false });
+        assert!(match verify_play_token(&token, 1, 3, &order, 30, 0) { Err(InvalidPlayToken) => true, _ => false });
+        assert!(match verify_play_token(&token, 1, 2, &"order_2".to_owned(), 30, 0) { Err(InvalidPlayToken) => true, _ => false });
+    }
+
+    #[test]
+    fn expired_token_is_rejected_unittest() {
+        let order = "order_1".to_owned();
+        let token = issue_play_token_at(&config::get().play_token_secret, 1, 2, &order, 1000);
+        let ttl = config::get().play_token_ttl_sec;
+        assert!(verify_play_token(&token, 1, 2, &order, 30, 1000 + 30 + ttl).is_ok());
+        assert!(match verify_play_token(&token, 1, 2, &order, 30, 1000 + 31 + ttl) { Err(ExpiredPlayToken) => true, _ => false });
+    }
+
+    #[test]
+    fn forged_tokens_are_rejected_unittest() {
+        let order = "order_1".to_owned();
+        let secret = config::get().play_token_secret.clone();
+        let other_secret = issue_play_token_at(&"another secret".to_owned(), 1, 2, &order, 1000);
+        let token = issue_play_token_at(&secret, 1, 2, &order, 1000);
+        let payload = base64::encode_config("v1:1:2:999999999:0000000000000000:order_1", base64::URL_SAFE_NO_PAD);
+        let tampered = format!("{}.{}", payload, token.splitn(2, '.').nth(1).unwrap());
+        for forged in vec![other_secret, tampered, "".to_owned(), "abc".to_owned(), "a.b".to_owned()] {
+            assert!(decode_play_token(&secret, &forged).is_err(), "{} should be rejected", forged);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+/**
+ * Pricing of played videos
+ */
+#[cfg(test)]
+use mocktopus::macros::*;
+
+use log::error;
+use crate::persistance::store::Store;
+use crate::config;
+use crate::model::{RateCard, Rounding};
+use crate::services::VideoServiceError;
+use crate::services::VideoServiceError::Other;
+
+static MINUTES_PER_DAY: u32 = 24 * 60;
+
+/**
+ * The rate card used where no rate card in the database applies,
+ * one credit per billing_seconds_per_credit seconds from the configuration, rounded down, at least one credit.
+ */
+pub fn default_rate_card() -> RateCard {
+    RateCard {
+        id: 0,
+        location: None,
+        start_minute: 0,
+        end_minute: MINUTES_PER_DAY,
+        credits_per_second: 1.0 / config::get().billing_seconds_per_credit as f64,
+        min_credits: 1,
+        rounding: Rounding::Down
+    }
+}
+
+/**
+ * Returns the credits to charge for playing a video at a location at a certain time, and the rate card applied.
+ * The time of day is taken in the rate_card_utc_offset_min timezone from the configuration.
+ *
+ * # Arguments
+ * `location` - the location of the display that played the video
+ * `time_epoch` - time of play in epoch seconds
+ * `length_sec` - the number of seconds played
+ */
+#[cfg_attr(test, mockable)]
+pub fn charge_for_play(store: &dyn Store, location: i32, time_epoch: u64, length_sec: i32) -> Result<(i32, RateCard), VideoServiceError> {
+    let cards = match store.get_rate_cards_for_location(location) {
+        Ok(val) => val,
+        Err(e) => {error!("{}", e); return Err(Other)}
+    };
+    let minute = minute_of_day(time_epoch, config::get().rate_card_utc_offset_min);
+    let card = select_rate_card(&cards, minute).cloned().unwrap_or_else(default_rate_card);
+    Ok((compute_charge(&card, length_sec), card))
+}
+
+/**
+ * Applies a rate card to a play of length_sec seconds
+ */
+pub fn compute_charge(card: &RateCard, length_sec: i32) -> i32 {
+    //Rounded to micro credits first so that e.g. 30 seconds at 0.1 credits per second is not rounded up to 4
+    let raw = (card.credits_per_second * std::cmp::max(length_sec, 0) as f64 * 1e6).round() / 1e6;
+    let rounded = match card.rounding {
+        Rounding::Up => raw.ceil(),
+        Rounding::Down => raw.floor(),
+        Rounding::Nearest => raw.round()
+    } as i32;
+    std::cmp::max(rounded, card.min_credits)
+}
+
+/**
+ * The most specific rate card applying at the minute of the day: cards for the location before cards for all locations,
+ * then the card with the shortest time window, then the newest card.
+ */
+fn select_rate_card(cards: &Vec<RateCard>, minute: u32) -> Option<&RateCard> {
+    cards.iter()
+        .filter(|card| applies_at(card, minute))
+        .min_by_key(|card| (card.location.is_none(), window_length(card), -card.id))
+}
+
+fn applies_at(card: &RateCard, minute: u32) -> bool {
+    match card.start_minute <= card.end_minute {
+        true => minute >= card.start_minute && minute < card.end_minute,
+        false => minute >= card.start_minute || minute < card.end_minute
+    }
+}
+
+fn window_length(card: &RateCard) -> u32 {
+    match card.start_minute <= card.end_minute {
+        true => card.end_minute - card.start_minute,
+        false => card.end_minute + MINUTES_PER_DAY - card.start_minute
+    }
+}
+
+fn minute_of_day(time_epoch: u64, utc_offset_min: i32) -> u32 {
+    ((time_epoch / 60) as i64 + utc_offset_min as i64).rem_euclid(MINUTES_PER_DAY as i64) as u32
+}
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use mocktopus::mocking::*;
+    use super::*;
+    use crate::persistance::db;
+    use crate::persistance::store::MysqlStore;
+
+    fn card(id: i32, location: Option<i32>, start_minute: u32, end_minute: u32, credits_per_second: f64) -> RateCard {
+        RateCard {id, location, start_minute, end_minute, credits_per_second, min_credits: 1, rounding: Rounding::Down}
+    }
+
+    #[test]
+    fn default_rate_card_bills_per_configured_seconds_unittest() {
+        assert_eq!(compute_charge(&default_rate_card(), 100), 12);
+        assert_eq!(compute_charge(&default_rate_card(), 3), 1);
+    }
+
+    #[test]
+    fn compute_charge_rounding_and_minimum_unittest() {
+        let mut c = card(1, None, 0, MINUTES_PER_DAY, 0.1);
+        assert_eq!(compute_charge(&c, 30), 3);
+        assert_eq!(compute_charge(&c, 35), 3);
+        c.rounding = Rounding::Up;
+        assert_eq!(compute_charge(&c, 30), 3);
+        assert_eq!(compute_charge(&c, 31), 4);
+        c.rounding = Rounding::Nearest;
+        assert_eq!(compute_charge(&c, 35), 4);
+        c.min_credits = 5;
+        assert_eq!(compute_charge(&c, 35), 5);
+    }
+
+    #[test]
+    fn most_specific_rate_card_is_selected_unittest() {
+        let cards = vec![
+            card(1, None, 0, MINUTES_PER_DAY, 1.0),
+            card(2, None, 8 * 60, 10 * 60, 2.0),
+            card(3, Some(1), 0, MINUTES_PER_DAY, 3.0),
+            card(4, Some(1), 22 * 60, 2 * 60, 4.0)
+        ];
+        assert_eq!(select_rate_card(&cards, 9 * 60).map(|c| c.id), Some(3));
+        assert_eq!(select_rate_card(&cards, 23 * 60).map(|c| c.id), Some(4));
+        assert_eq!(select_rate_card(&cards, 60).map(|c| c.id), Some(4));
+        assert_eq!(select_rate_card(&cards[..2].to_vec(), 9 * 60).map(|c| c.id), Some(2));
+        assert_eq!(select_rate_card(&cards[1..2].to_vec(), 11 * 60), None);
+    }
+
+    #[test]
+    fn charge_for_play_falls_back_to_default_rate_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::get_rate_cards_for_location.mock_safe(|location| {
+            assert_eq!(location, 1);
+            MockResult::Return(Ok(vec![card(1, Some(1), 8 * 60, 10 * 60, 1.0)]))
+        });
+        assert_eq!(charge_for_play(&MysqlStore, 1, 9 * 3600, 100).map(|(credits, card)| (credits, card.id)).unwrap(), (100, 1));
+        assert_eq!(charge_for_play(&MysqlStore, 1, 12 * 3600, 100).map(|(credits, card)| (credits, card.id)).unwrap(), (12, 0));
+    }
+}
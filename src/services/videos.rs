@@ -5,48 +5,97 @@
 use mocktopus::macros::*;
 
 use rand::prelude::*;
-use crate::persistance::db;
+use log::{debug, error, trace};
+use crate::persistance::store::Store;
+use crate::metrics;
+use crate::config::{self, SelectionStrategy};
 use crate::model::*;
 use std::time::{SystemTime, UNIX_EPOCH};
+use crate::services::occupancy::Occupancy;
 use crate::services::VideoServiceError;
+use crate::services::{pacing, pricing, play_tokens, webhooks};
 use crate::services::VideoServiceError::{    
     NoSuchVideo,
     NoSuchDisplay,
     NoSuchOrder,
     NoSuchDisplayLocation,
+    InvalidPlayLength,
+    OrderNotForVideo,
+    CampaignNotActive,
+    RedeemedPlayToken,
     Other
 };
 
 /**
  *  Registers a view in played_videos see public endpoint doc
+ *  Returns the registered play, with the validated length and the number of credits charged
  */
 #[cfg_attr(test, mockable)]
-pub fn register_video_view(display_id: i32, video_id: i32, order_id: &String, length_sec: i32) ->  Result<(), VideoServiceError> {
+pub fn register_video_view(store: &dyn Store, display_id: i32, video_id: i32, order_id: &String, length_sec: i32, token: &String) ->  Result<PlayedVideo, VideoServiceError> {
 
-    match (db::get_advertisement_video_by_id(video_id), db::get_display_by_id(display_id), db::get_order_by_id(order_id)) {
+    let (video, display) = match (store.get_advertisement_video_by_id(video_id), store.get_display_by_id(display_id), store.get_order_by_id(order_id)) {
         (Ok(None), _, _) => return Err(NoSuchVideo),
         (Ok(Some(_)), Ok(None), _) => return Err(NoSuchDisplay),
         (Ok(Some(_)), Ok(Some(_)), Ok(None)) => return Err(NoSuchOrder),
-        (Ok(Some(video)), Ok(Some(_)), Ok(Some(_))) => video,
+        (Ok(Some(video)), Ok(Some(display)), Ok(Some(_))) => (video, display),
         _ => return Err(Other)
     };
-    
-    let credits_amt = std::cmp::max(length_sec/8, 1);
-    match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(n) =>    match (db::insert_played_video(video_id, n.as_secs(), order_id), db::draw_credits_for_order(order_id, credits_amt)) {
-                        (Ok(_), Ok(_)) => Ok(()),
-                        _ => panic!("ERROR UPDATING TABLES")
-                    },
+    let time_epoch = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
         Err(e) => panic!("ERROR GETTING SYSTEM TIME!, ERROR\n{}", e),
+    };
+    let claims = play_tokens::verify_play_token(token, display_id, video_id, order_id, std::cmp::max(video.length_sec, 0) as u64, time_epoch)?;
+    verify_play_is_eligible(store, video_id, order_id, time_epoch)?;
+    let (length_sec, complete) = validate_play_length(length_sec, video.length_sec, config::get().play_length_tolerance_sec)?;
+    let (credits, rate_card) = pricing::charge_for_play(store, display.location, time_epoch, length_sec)?;
+    let played = PlayedVideo {video: video_id, display: display_id, order: order_id.clone(), time_epoch, length_sec, complete, credits};
+    //The token is redeemed in the transaction registering the play, a play that fails to register leaves it unredeemed
+    match store.register_play(&played, &rate_card, &claims.signature) {
+        Ok(Some(balance)) => {
+            metrics::CREDITS_DRAWN.with_label_values(&[order_id.as_str()]).inc_by(credits as i64);
+            webhooks::notify_credit_change(store, order_id, balance, balance - credits);
+            Ok(played)
+        },
+        Ok(None) => Err(RedeemedPlayToken),
+        Err(e) => {error!("{}", e); Err(Other)}
+    }
+}
+
+/**
+ * Verifies that the order pays for the video in a campaign running at the time of play.
+ * The interests at the display's location are not checked, the play token proves the video was eligible when it was served.
+ */
+fn verify_play_is_eligible(store: &dyn Store, video_id: i32, order_id: &String, time_epoch: u64) -> Result<(), VideoServiceError> {
+    match store.get_advertisement_order(video_id, order_id) {
+        Ok(None) => Err(OrderNotForVideo),
+        Ok(Some(ref campaign)) if time_epoch < campaign.start_time_epoch || time_epoch > campaign.end_time_epoch => Err(CampaignNotActive),
+        Ok(Some(_)) => Ok(()),
+        Err(e) => {error!("{}", e); Err(Other)}
     }
 }
 
+/**
+ * Validates a reported play length against the length of the video.
+ * Returns the number of seconds to register and bill, and whether the video was played to the end.
+ *
+ * Plays reported at most `tolerance_sec` longer than the video are clamped to the video length,
+ * longer plays and plays of zero or less seconds are rejected with InvalidPlayLength.
+ */
+fn validate_play_length(reported_sec: i32, video_length_sec: i32, tolerance_sec: i32) -> Result<(i32, bool), VideoServiceError> {
+    if reported_sec <= 0 || reported_sec > video_length_sec + tolerance_sec {
+        return Err(InvalidPlayLength(video_length_sec));
+    }
+    let length_sec = std::cmp::min(reported_sec, video_length_sec);
+    Ok((length_sec, length_sec == video_length_sec))
+}
+
 /**
 * Returns an Optional AdvertVideo for the most relevant video, None if there is no payed for video that matches the interests at the location
 * 
 * The video returned will be a random video for which the following
 *
-* The video matches the highest weighted interest for which there is a payed video.
+* The video matches the highest weighted interest for which there is a payed video,
+* or with the weighted_random selection strategy, an interest picked at random weighted by interest weight.
 *
 * holds
 *
@@ -58,42 +107,74 @@ pub fn register_video_view(display_id: i32, video_id: i32, order_id: &String, le
 * [VideoServiceError::NoSuchDisplay](../enum.VideoServiceError.html) if display does not exist
 *
 * # Arguments
+* `occupancy` - the live occupancy of the locations, giving the interests at the display's location
 * `display_id` - an i32 representing a display id.
 */
 #[cfg_attr(test, mockable)]
-pub fn find_relevant_video(display_id: i32) -> Result<Option<AdvertVideoOrder>, VideoServiceError> {
+pub fn find_relevant_video(store: &dyn Store, occupancy: &Occupancy, display_id: i32) -> Result<Option<AdvertVideoOrder>, VideoServiceError> {
     //Find out where the display is located
-    let location = match db::get_display_location(display_id) {
+    let location = match store.get_display_location(display_id) {
         Some(val) => val,
-        None => return Err(NoSuchDisplayLocation)
+        None => return with_outcome(metrics::SELECTION_NO_DISPLAY_LOCATION, Err(NoSuchDisplayLocation))
     };
     
-    let interests = match db::get_interests_at_location(location) {
-        Ok(Some(val)) => val,
-        Ok(None) => return Ok(None),
-        Err(e) => {eprintln!("{}", e); return Err(Other)}
+    let interests = match occupancy.interests_at(location) {
+        Some(val) => val,
+        None => return with_outcome(metrics::SELECTION_NO_TRACKERS, Ok(None))
     };
     
-    for x in interests.iter() {
-        println!("interest: {}, weight: {}", x.0, x.1)    
-    }
+    debug!("interests at location {}: {:?}", location, interests);
     
-    let mut videos: Vec<AdvertVideoOrder> = match db::find_eligible_videos_by_interest(interests.iter().map(|x| x.0).collect()) {
+    let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(e) => panic!("ERROR GETTING SYSTEM TIME!, ERROR\n{}", e),
+    };
+    let mut videos: Vec<AdvertVideoOrder> = match store.find_eligible_videos_by_interest(interests.iter().map(|x| x.0).collect(), now) {
         Ok(Some(val)) => val,
-        Ok(None) => return Ok(None),
-        Err(e) => {println!("{}", e); return Err(Other)}
+        Ok(None) => return with_outcome(metrics::SELECTION_NO_ELIGIBLE_VIDEO, Ok(None)),
+        Err(e) => {error!("{}", e); return with_outcome(metrics::SELECTION_ERROR, Err(Other))}
     };
+    let config = config::get();
+    videos.retain(|video| match pacing::is_ahead_of_target(&video.budget, now, config.pacing_curve, config.pacing_tolerance) {
+        true => {trace!("order {} is ahead of its pacing target: {:?}", video.order, video.budget); false},
+        false => true
+    });
+    if videos.is_empty() {
+        return with_outcome(metrics::SELECTION_NO_ELIGIBLE_VIDEO, Ok(None));
+    }
 
-    println!("{:?}",videos);
+    trace!("eligible videos: {:?}", videos);
     videos.shuffle(&mut thread_rng());
-    println!("{:?}",videos);
+    let interests = match config::get().selection_strategy {
+        SelectionStrategy::HighestWeight => interests,
+        SelectionStrategy::WeightedRandom => weighted_random_interest(&interests, &videos).map(|i| vec![i]).unwrap_or(interests)
+    };
     for x in interests.iter() {
         match videos.iter().find(|el| el.interest == x.0) {
-            Some(val) => return Ok(Some(val.clone())),
+            Some(val) => return with_outcome(metrics::SELECTION_FOUND, Ok(Some(val.clone()))),
             _ => ()
         };
     }
-    Ok(None)
+    with_outcome(metrics::SELECTION_NO_ELIGIBLE_VIDEO, Ok(None))
+}
+
+/**
+ * Picks one of the interests that has an eligible video, with a probability proportional to the interest weight.
+ * None if no interest with a positive weight has an eligible video.
+ */
+fn weighted_random_interest(interests: &Vec<(i32, f32)>, videos: &Vec<AdvertVideoOrder>) -> Option<(i32, f32)> {
+    let candidates: Vec<&(i32, f32)> = interests.iter()
+        .filter(|x| x.1 > 0.0 && videos.iter().any(|v| v.interest == x.0))
+        .collect();
+    candidates.choose_weighted(&mut thread_rng(), |x| x.1).ok().map(|x| **x)
+}
+
+/**
+ * Counts a video selection outcome and passes the result through
+ */
+fn with_outcome<T>(outcome: &str, result: T) -> T {
+    metrics::VIDEO_SELECTIONS.with_label_values(&[outcome]).inc();
+    result
 }
 
 
@@ -104,7 +185,14 @@ pub fn find_relevant_video(display_id: i32) -> Result<Option<AdvertVideoOrder>,
 mod tests {
     use mocktopus::mocking::*;
     use super::*;
-    use crate::model::{ Display, AdvertVideoOrder };
+    use crate::persistance::db;
+    use crate::persistance::store::MysqlStore;
+    use crate::model::{ Display, AdvertVideoOrder, OrderBudget };
+    use crate::services::VideoServiceError::InvalidPlayToken;
+
+    fn budget(spent_credits: i32) -> OrderBudget {
+        OrderBudget {remaining_credits: 100 - spent_credits, spent_credits, start_epoch: 0, end_epoch: u64::max_value()}
+    }
 
     #[test]
     fn register_video_view_for_nonexistent_video_unittest() {
@@ -132,7 +220,7 @@ mod tests {
             })
         );
 
-        assert!(match register_video_view(2,1,&"order_id".to_owned(),  100) {
+        assert!(match register_video_view(&MysqlStore, 2,1,&"order_id".to_owned(),  100, &"token".to_owned()) {
             Err(NoSuchVideo) => true,
             _ => false
         },"incorrect error type on register")
@@ -164,7 +252,7 @@ mod tests {
             })
         );
 
-        assert!(match register_video_view(2,1,&"order_id".to_owned(),  100) {
+        assert!(match register_video_view(&MysqlStore, 2,1,&"order_id".to_owned(),  100, &"token".to_owned()) {
             Err(NoSuchDisplay) => true,
             _ => false
         },"incorrect error type on register")
@@ -175,7 +263,7 @@ mod tests {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
         db::get_advertisement_video_by_id.mock_safe(|_| 
             MockResult::Return(
-                Ok(Some(AdvertVideo {interest: 1, url: "interest_1".to_owned(),length_sec: 1})
+                Ok(Some(AdvertVideo {interest: 1, url: "interest_1".to_owned(),length_sec: 120})
             )));
             
             db::get_display_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Display {id: 1, location: 1}))));
@@ -183,20 +271,119 @@ mod tests {
                 Ok(Some(Order { id: "order_1".to_owned(), credits: 100, user: "user_1".to_owned() }))
             ));
             
-            db::insert_played_video.mock_safe(|_,_,_| MockResult::Return(Ok(())));    
-            db::draw_credits_for_order.mock_safe(|_,_| MockResult::Return(Ok(())));
+            db::get_advertisement_order.mock_safe(|video, order| MockResult::Return(
+                Ok(Some(AdvertisementOrder { video, order: order.clone(), start_time_epoch: 0, end_time_epoch: u64::max_value() }))
+            ));
+            db::get_rate_cards_for_location.mock_safe(|_| MockResult::Return(Ok(vec![])));
+            db::register_play.mock_safe(|played, rate_card, _| {
+                assert_eq!((played.length_sec, played.complete, played.credits), (100, false, 12));
+                assert_eq!(rate_card.id, 0, "the default rate card should be applied");
+                MockResult::Return(Ok(Some(95)))
+            });
+            webhooks::notify_credit_change.mock_safe(|_, _, before, after| {
+                assert_eq!((before, after), (95, 83), "the balance at the time of the play should be notified");
+                MockResult::Return(())
+            });
             
-            assert!(match register_video_view(2,1,&"order_id".to_owned(),  100) {
-                Ok(()) => true,
+            let token = play_tokens::issue_play_token(2, 1, &"order_id".to_owned());
+            assert!(match register_video_view(&MysqlStore, 2,1,&"order_id".to_owned(),  100, &token) {
+                Ok(PlayedVideo {credits: 12, ..}) => true,
                 _ => false
             },"incorrect error type on register")
         }
         
+    #[test]
+    fn register_video_view_with_impossible_length_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::get_advertisement_video_by_id.mock_safe(|_| 
+            MockResult::Return(Ok(Some(AdvertVideo {interest: 1, url: "interest_1".to_owned(), length_sec: 15}))));
+        db::get_display_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Display {id: 1, location: 1}))));
+        db::get_order_by_id.mock_safe(|_| MockResult::Return(
+            Ok(Some(Order { id: "order_1".to_owned(), credits: 100, user: "user_1".to_owned() }))
+        ));
+        db::get_advertisement_order.mock_safe(|video, order| MockResult::Return(
+            Ok(Some(AdvertisementOrder { video, order: order.clone(), start_time_epoch: 0, end_time_epoch: u64::max_value() }))
+        ));
+        db::register_play.mock_safe(|_, _, _| panic!("a play with an impossible length should not be registered"));
+
+        let token = play_tokens::issue_play_token(1, 1, &"order_1".to_owned());
+        for length in vec![0, -5, 10000] {
+            assert!(match register_video_view(&MysqlStore, 1, 1, &"order_1".to_owned(), length, &token) {
+                Err(InvalidPlayLength(15)) => true,
+                _ => false
+            }, "incorrect error type on register of length {}", length)
+        }
+    }
+
+    #[test]
+    fn register_video_view_for_ineligible_play_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::get_advertisement_video_by_id.mock_safe(|_| 
+            MockResult::Return(Ok(Some(AdvertVideo {interest: 1, url: "interest_1".to_owned(), length_sec: 15}))));
+        db::get_display_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Display {id: 1, location: 1}))));
+        db::get_order_by_id.mock_safe(|_| MockResult::Return(
+            Ok(Some(Order { id: "order_1".to_owned(), credits: 100, user: "user_1".to_owned() }))
+        ));
+        db::register_play.mock_safe(|_, _, _| panic!("an ineligible play should not be registered"));
+        let token = play_tokens::issue_play_token(1, 1, &"order_1".to_owned());
+
+        db::get_advertisement_order.mock_safe(|_, _| MockResult::Return(Ok(None)));
+        assert!(match register_video_view(&MysqlStore, 1, 1, &"order_1".to_owned(), 10, &token) {
+            Err(OrderNotForVideo) => true,
+            _ => false
+        }, "a play for an order not paying for the video should be rejected");
+
+        db::get_advertisement_order.mock_safe(|video, order| MockResult::Return(
+            Ok(Some(AdvertisementOrder { video, order: order.clone(), start_time_epoch: 0, end_time_epoch: 1 }))
+        ));
+        assert!(match register_video_view(&MysqlStore, 1, 1, &"order_1".to_owned(), 10, &token) {
+            Err(CampaignNotActive) => true,
+            _ => false
+        }, "a play outside of the campaign should be rejected");
+    }
+
+    #[test]
+    fn register_video_view_requires_valid_unredeemed_token_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::get_advertisement_video_by_id.mock_safe(|_| 
+            MockResult::Return(Ok(Some(AdvertVideo {interest: 1, url: "interest_1".to_owned(), length_sec: 15}))));
+        db::get_display_by_id.mock_safe(|_| MockResult::Return(Ok(Some(Display {id: 1, location: 1}))));
+        db::get_order_by_id.mock_safe(|_| MockResult::Return(
+            Ok(Some(Order { id: "order_1".to_owned(), credits: 100, user: "user_1".to_owned() }))
+        ));
+        db::get_advertisement_order.mock_safe(|video, order| MockResult::Return(
+            Ok(Some(AdvertisementOrder { video, order: order.clone(), start_time_epoch: 0, end_time_epoch: u64::max_value() }))
+        ));
+        db::get_rate_cards_for_location.mock_safe(|_| MockResult::Return(Ok(vec![])));
+        db::register_play.mock_safe(|_, _, _| MockResult::Return(Ok(None)));
+
+        let token_for_other_display = play_tokens::issue_play_token(2, 1, &"order_1".to_owned());
+        assert!(match register_video_view(&MysqlStore, 1, 1, &"order_1".to_owned(), 10, &token_for_other_display) {
+            Err(InvalidPlayToken) => true,
+            _ => false
+        }, "a play with a token for another display should be rejected");
+
+        let token = play_tokens::issue_play_token(1, 1, &"order_1".to_owned());
+        assert!(match register_video_view(&MysqlStore, 1, 1, &"order_1".to_owned(), 10, &token) {
+            Err(RedeemedPlayToken) => true,
+            _ => false
+        }, "a play with an already redeemed token should be rejected");
+    }
+
+    #[test]
+    fn validate_play_length_unittest() {
+        assert_eq!(validate_play_length(5, 60, 2).unwrap(), (5, false));
+        assert_eq!(validate_play_length(60, 60, 2).unwrap(), (60, true));
+        assert_eq!(validate_play_length(62, 60, 2).unwrap(), (60, true));
+        assert!(validate_play_length(63, 60, 2).is_err());
+        assert!(validate_play_length(0, 60, 2).is_err());
+    }
+
     #[test]
     fn get_video_with_notexistent_display_unittest() {
         db::get_display_location.mock_safe(|_| MockResult::Return(None));
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
-        assert!(match find_relevant_video(1) {
+        assert!(match find_relevant_video(&MysqlStore, &Occupancy::default(), 1) {
             Err(NoSuchDisplayLocation) => true,
             _ => false
         },"incorrect error on find relevant video")
@@ -206,9 +393,8 @@ mod tests {
     fn get_video_with_no_trackers_at_display_unittest() {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
         db::get_display_location.mock_safe(|_| MockResult::Return(Some(1)));
-        db::get_interests_at_location.mock_safe(|_| MockResult::Return(Ok(None)));
         
-        assert!(match find_relevant_video(1) {
+        assert!(match find_relevant_video(&MysqlStore, &Occupancy::default(), 1) {
             Ok(None) => true,
             _ => false
         },"incorrect error on find relevant video")
@@ -218,27 +404,70 @@ mod tests {
     fn get_video_success_unittest() {
         db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
         db::get_display_location.mock_safe(|_| MockResult::Return(Some(1)));
-        db::get_interests_at_location.mock_safe(|_| MockResult::Return(Ok(Some(std::vec![(1, 1.0)]))));
-        db::find_eligible_videos_by_interest.mock_safe(|_| MockResult::Return(Ok(Some(std::vec![
+        let occupancy = Occupancy::default();
+        occupancy.enter(&"tracker_1".to_owned(), 1, std::vec![(1, 1.0)]);
+        db::find_eligible_videos_by_interest.mock_safe(|_, _| MockResult::Return(Ok(Some(std::vec![
             AdvertVideoOrder {
                 video_id: 1,
                 interest: 1,
                 url: "example.com/video".to_owned(), 
                 length_sec: 100,
-                order: "order_1".to_owned()
+                order: "order_1".to_owned(),
+                budget: budget(0)
         }]))));
         
-        assert!(match find_relevant_video(1) {
+        assert!(match find_relevant_video(&MysqlStore, &occupancy, 1) {
             Ok(Some(AdvertVideoOrder {
                 video_id: 1,
                 interest: 1,
                 url: _, 
                 length_sec: 100,
-                order: _}
+                order: _,
+                budget: _}
             )) => true,
             _ => false
         },"incorrect error on find relevant video")
     }
-}
 
+    #[test]
+    fn get_video_leaves_out_orders_ahead_of_pacing_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::get_display_location.mock_safe(|_| MockResult::Return(Some(1)));
+        let occupancy = Occupancy::default();
+        occupancy.enter(&"tracker_1".to_owned(), 1, std::vec![(1, 1.0)]);
+        let video = |order: &str, spent_credits| AdvertVideoOrder {
+            video_id: 1,
+            interest: 1,
+            url: "example.com/video".to_owned(),
+            length_sec: 100,
+            order: order.to_owned(),
+            budget: budget(spent_credits)
+        };
+        let (paced, overspent) = (video("paced", 0), video("overspent", 50));
+
+        db::find_eligible_videos_by_interest.mock_safe(move |_, _| MockResult::Return(Ok(Some(std::vec![paced.clone(), overspent.clone()]))));
+        for _ in 0..10 {
+            assert_eq!(find_relevant_video(&MysqlStore, &occupancy, 1).unwrap().unwrap().order, "paced");
+        }
+
+        let overspent = video("overspent", 50);
+        db::find_eligible_videos_by_interest.mock_safe(move |_, _| MockResult::Return(Ok(Some(std::vec![overspent.clone()]))));
+        assert!(find_relevant_video(&MysqlStore, &occupancy, 1).unwrap().is_none(), "an order ahead of its pacing target should not be shown");
+    }
 
+    #[test]
+    fn weighted_random_interest_only_picks_interests_with_videos_unittest() {
+        let video = AdvertVideoOrder {
+            video_id: 1,
+            interest: 2,
+            url: "example.com/video".to_owned(),
+            length_sec: 100,
+            order: "order_1".to_owned(),
+            budget: budget(0)
+        };
+        for _ in 0..20 {
+            assert_eq!(weighted_random_interest(&vec![(1, 100.0), (2, 1.0), (3, 0.0)], &vec![video.clone()]), Some((2, 1.0)));
+        }
+        assert_eq!(weighted_random_interest(&vec![(1, 100.0)], &vec![video]), None);
+    }
+}
\ No newline at end of file
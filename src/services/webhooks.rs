@@ -0,0 +1,283 @@
+/**
+ * Outbound webhook notifications of order and campaign events
+ */
+#[cfg(test)]
+use mocktopus::macros::*;
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use crate::config;
+use crate::model::*;
+use crate::persistance::store::{SharedStore, Store};
+
+pub static EVENT_HEADER:     &'static str = "X-Iot-Event";
+pub static SIGNATURE_HEADER: &'static str = "X-Iot-Signature";
+static DISPATCH_INTERVAL_SEC: u64 = 5;
+static DELIVERIES_PER_DISPATCH: u32 = 100;
+//Campaigns that started or ended longer ago than this when the server starts are not notified
+static CAMPAIGN_EVENT_LOOKBACK_SEC: u64 = 24 * 60 * 60;
+
+/**
+ * Queues events for the webhooks of the order's agency after credits were drawn from an order:
+ * low_credit when the credits fall to or below a webhook's low_credit_threshold (if above 0),
+ * order_exhausted when the order runs out of credits.
+ *
+ * # Arguments
+ * `order_id` - the order credits were drawn from
+ * `credits_before` - the credits of the order before they were drawn
+ * `credits_after` - the credits of the order after they were drawn
+ */
+#[cfg_attr(test, mockable)]
+pub fn notify_credit_change(store: &dyn Store, order_id: &String, credits_before: i32, credits_after: i32) {
+    let webhooks = match store.find_webhooks_for_order(order_id) {
+        Ok(val) => val,
+        Err(e) => {error!("could not find webhooks for order {}: {}", order_id, e); return}
+    };
+    let now = now_epoch();
+    for webhook in webhooks.iter() {
+        let threshold = webhook.low_credit_threshold;
+        if threshold > 0 && credits_before > threshold && credits_after <= threshold && webhook.is_subscribed_to(WebhookEvent::LowCredit) {
+            queue(store, webhook, WebhookEvent::LowCredit, order_id, json!({"credits": credits_after, "threshold": threshold}), now);
+        }
+        if credits_before > 0 && credits_after <= 0 && webhook.is_subscribed_to(WebhookEvent::OrderExhausted) {
+            queue(store, webhook, WebhookEvent::OrderExhausted, order_id, json!({"credits": credits_after}), now);
+        }
+    }
+}
+
+/**
+ * Queues campaign_started and campaign_ended events for the campaigns that started or ended
+ * after `after_epoch` and at or before `now`. Each campaign event is only queued once, as `record_campaign_event`
+ * records it before its deliveries are queued and reports it as handled to any later scan of the same window.
+ * Returns the number of campaign events queued.
+ */
+pub fn queue_campaign_events(store: &dyn Store, after_epoch: u64, now: u64) -> usize {
+    let events = match store.find_unrecorded_campaign_events(after_epoch, now) {
+        Ok(val) => val,
+        Err(e) => {error!("could not find campaign events: {}", e); return 0}
+    };
+    let mut queued = 0;
+    for (campaign, event) in events.iter() {
+        match store.record_campaign_event(campaign, *event, now) {
+            Ok(true) => queued += 1,
+            //Already handled by another server
+            Ok(false) => continue,
+            Err(e) => {error!("could not record {} of order {}: {}", event.as_str(), campaign.order, e); continue}
+        };
+        let webhooks = match store.find_webhooks_for_order(&campaign.order) {
+            Ok(val) => val,
+            Err(e) => {error!("could not find webhooks for order {}: {}", campaign.order, e); continue}
+        };
+        for webhook in webhooks.iter().filter(|w| w.is_subscribed_to(*event)) {
+            queue(store, webhook, *event, &campaign.order, json!({
+                "video": campaign.video,
+                "start_time": campaign.start_time_epoch,
+                "end_time": campaign.end_time_epoch
+            }), now);
+        }
+    }
+    queued
+}
+
+/**
+ * Makes one delivery attempt for each due delivery.
+ * Failed deliveries are retried after webhook_retry_base_sec, doubling the delay for each attempt,
+ * until webhook_max_attempts have been made.
+ * Returns the number of deliveries attempted.
+ */
+pub fn deliver_due(store: &dyn Store, now: u64) -> usize {
+    let deliveries = match store.find_due_webhook_deliveries(now, DELIVERIES_PER_DISPATCH) {
+        Ok(val) => val,
+        Err(e) => {error!("could not find webhook deliveries: {}", e); return 0}
+    };
+    let config = config::get();
+    for delivery in deliveries.iter() {
+        let attempts = delivery.attempts + 1;
+        let (status, next_attempt_epoch, response_code, error) = match post(delivery, config.webhook_timeout_sec) {
+            Ok(code) => (DeliveryStatus::Delivered, now, Some(code), None),
+            Err((code, e)) if attempts >= config.webhook_max_attempts => {
+                warn!("giving up delivery {} of {} to {} after {} attempts: {}", delivery.id, delivery.event.as_str(), delivery.url, attempts, e);
+                (DeliveryStatus::Failed, now, code, Some(e))
+            },
+            Err((code, e)) => {
+                info!("delivery {} of {} to {} failed, retrying: {}", delivery.id, delivery.event.as_str(), delivery.url, e);
+                (DeliveryStatus::Pending, now + retry_delay(config.webhook_retry_base_sec, attempts), code, Some(e))
+            }
+        };
+        if let Err(e) = store.update_webhook_delivery(delivery.id, status, attempts, next_attempt_epoch, response_code, error, now) {
+            error!("could not update webhook delivery {}: {}", delivery.id, e);
+        }
+    }
+    deliveries.len()
+}
+
+/**
+ * Starts a background thread queueing campaign events and delivering due webhook deliveries every few seconds.
+ * The thread runs whether or not any webhook is configured, a round without webhooks only scans for campaign events.
+ * A round that panics does not advance the scanned window, so the next round rescans it and relies on
+ * `record_campaign_event` to skip the campaign events that were already queued.
+ */
+pub fn start_dispatcher(store: SharedStore) {
+    thread::Builder::new().name("webhooks".to_owned()).spawn(move || {
+        let mut last_scan = now_epoch().saturating_sub(CAMPAIGN_EVENT_LOOKBACK_SEC);
+        loop {
+            let now = now_epoch();
+            //A database error panics, the dispatcher keeps running and retries on the next round
+            let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                queue_campaign_events(&*store, last_scan, now);
+                deliver_due(&*store, now);
+            }));
+            match res {
+                Ok(()) => last_scan = now,
+                Err(payload) => error!("webhook dispatch round failed, retrying in {} s: {}", DISPATCH_INTERVAL_SEC, panic_message(&*payload))
+            }
+            thread::sleep(Duration::from_secs(DISPATCH_INTERVAL_SEC));
+        }
+    }).expect("could not start the webhook dispatcher");
+}
+
+/**
+ * The message of a caught panic, panics carry a &str or a String
+ */
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "unknown panic".to_owned()
+        }
+    }
+}
+
+fn queue(store: &dyn Store, webhook: &Webhook, event: WebhookEvent, order_id: &String, details: Value, now: u64) {
+    let mut payload = json!({"event": event.as_str(), "order": order_id, "agency": webhook.agency, "time": now});
+    if let (Some(payload), Value::Object(details)) = (payload.as_object_mut(), details) {
+        payload.extend(details);
+    }
+    if let Err(e) = store.insert_webhook_delivery(webhook.id, event, order_id, &payload.to_string(), now) {
+        error!("could not queue {} of order {} for webhook {}: {}", event.as_str(), order_id, webhook.id, e);
+    }
+}
+
+/**
+ * Posts the payload of a delivery, signed with the webhook secret if it has one.
+ * Returns the status code on a 2xx response, otherwise the status code if any and what went wrong.
+ */
+fn post(delivery: &WebhookDelivery, timeout_sec: u64) -> Result<u16, (Option<u16>, String)> {
+    let mut request = ureq::post(&delivery.url);
+    request
+        .set("Content-Type", "application/json")
+        .set(EVENT_HEADER, delivery.event.as_str())
+        .timeout(Duration::from_secs(timeout_sec));
+    if let Some(secret) = &delivery.secret {
+        request.set(SIGNATURE_HEADER, &format!("sha256={}", sign(secret, &delivery.payload)));
+    }
+    let response = request.send_string(&delivery.payload);
+    match response.synthetic_error() {
+        Some(e) => Err((None, e.to_string())),
+        None if response.ok() => Ok(response.status()),
+        None => Err((Some(response.status()), format!("unexpected response {}", response.status_line())))
+    }
+}
+
+/**
+ * The hex encoded HMAC-SHA256 of the payload, lets receivers verify that a delivery came from us
+ */
+pub fn sign(secret: &String, payload: &String) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.input(payload.as_bytes());
+    mac.result().code().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn retry_delay(base_sec: u64, attempts: u32) -> u64 {
+    base_sec.saturating_mul(1u64 << std::cmp::min(attempts.saturating_sub(1), 16))
+}
+
+fn now_epoch() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(e) => panic!("ERROR GETTING SYSTEM TIME!, ERROR\n{}", e),
+    }
+}
+
+
+
+/**************
+ * Unit tests *
+ **************/
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use mocktopus::mocking::*;
+    use super::*;
+    use crate::persistance::db;
+    use crate::persistance::store::MysqlStore;
+
+    fn webhook(events: Option<Vec<WebhookEvent>>, low_credit_threshold: i32) -> Webhook {
+        Webhook {id: 1, agency: "1".to_owned(), url: "http://localhost/hook".to_owned(), secret: None, events, low_credit_threshold}
+    }
+
+    fn queued_events() -> Rc<RefCell<Vec<(WebhookEvent, Value)>>> {
+        let queued = Rc::new(RefCell::new(vec![]));
+        let queued_clone = queued.clone();
+        db::insert_webhook_delivery.mock_safe(move |_, event, _, payload, _| {
+            queued_clone.borrow_mut().push((event, serde_json::from_str(payload).unwrap()));
+            MockResult::Return(Ok(()))
+        });
+        queued
+    }
+
+    #[test]
+    fn crossing_thresholds_queues_events_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::find_webhooks_for_order.mock_safe(|_| MockResult::Return(Ok(vec![webhook(None, 50)])));
+        let queued = queued_events();
+
+        notify_credit_change(&MysqlStore, &"order_1".to_owned(), 100, 60);
+        assert!(queued.borrow().is_empty(), "no threshold was crossed");
+
+        notify_credit_change(&MysqlStore, &"order_1".to_owned(), 60, 50);
+        notify_credit_change(&MysqlStore, &"order_1".to_owned(), 50, 40);
+        notify_credit_change(&MysqlStore, &"order_1".to_owned(), 5, -1);
+        let queued = queued.borrow();
+        assert_eq!(queued.iter().map(|q| q.0).collect::<Vec<WebhookEvent>>(), vec![WebhookEvent::LowCredit, WebhookEvent::OrderExhausted]);
+        assert_eq!(queued[0].1["order"], "order_1");
+        assert_eq!(queued[0].1["credits"], 50);
+        assert_eq!(queued[0].1["threshold"], 50);
+    }
+
+    #[test]
+    fn only_subscribed_events_are_queued_unittest() {
+        db::Dbconn::new.mock_safe(|| panic!("TRIED TO CONNECT TO DB"));
+        db::find_webhooks_for_order.mock_safe(|_| MockResult::Return(Ok(vec![webhook(Some(vec![WebhookEvent::OrderExhausted]), 50)])));
+        let queued = queued_events();
+
+        notify_credit_change(&MysqlStore, &"order_1".to_owned(), 100, 0);
+        assert_eq!(queued.borrow().iter().map(|q| q.0).collect::<Vec<WebhookEvent>>(), vec![WebhookEvent::OrderExhausted]);
+    }
+
+    #[test]
+    fn retry_delay_doubles_unittest() {
+        assert_eq!(retry_delay(30, 1), 30);
+        assert_eq!(retry_delay(30, 2), 60);
+        assert_eq!(retry_delay(30, 4), 240);
+        assert_eq!(retry_delay(u64::max_value(), 4), u64::max_value());
+    }
+
+    #[test]
+    fn panic_message_reads_str_and_string_payloads_unittest() {
+        let payload = panic::catch_unwind(|| panic!("database gone")).unwrap_err();
+        assert_eq!(panic_message(&*payload), "database gone");
+        let payload = panic::catch_unwind(|| panic!("error {}", 1045)).unwrap_err();
+        assert_eq!(panic_message(&*payload), "error 1045");
+        let payload = panic::catch_unwind(|| panic::panic_any(1)).unwrap_err();
+        assert_eq!(panic_message(&*payload), "unknown panic");
+    }
+}